@@ -13,21 +13,22 @@ use h264_reader::Context;
 use h264_reader::rbsp::RbspDecoder;
 use h264_reader::nal::NalHandler;
 use h264_reader::nal::NalHeader;
+use h264_reader::nal::NalFlow;
 
 struct NullNalHandler {
 }
 impl NalHandler for NullNalHandler {
     type Ctx = ();
 
-    fn start(&mut self, _ctx: &mut Context<Self::Ctx>, _header: NalHeader) {
+    fn start(&mut self, _ctx: &mut Context<Self::Ctx>, _header: NalHeader) -> NalFlow {
         unimplemented!()
     }
 
-    fn push(&mut self, _ctx: &mut Context<Self::Ctx>, _buf: &[u8]) {
+    fn push(&mut self, _ctx: &mut Context<Self::Ctx>, _buf: &[u8]) -> NalFlow {
         unimplemented!()
     }
 
-    fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
+    fn end(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
         unimplemented!()
     }
 }
@@ -41,14 +42,17 @@ struct NullNalReader {
 impl NalReader for NullNalReader {
     type Ctx = ();
 
-    fn start(&mut self, _ctx: &mut Context<Self::Ctx>) {
+    fn start(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
         self.start += 1;
+        NalFlow::Continue
     }
-    fn push(&mut self, _ctx: &mut Context<Self::Ctx>, _buf: &[u8]) {
+    fn push(&mut self, _ctx: &mut Context<Self::Ctx>, _buf: &[u8]) -> NalFlow {
         self.push += 1;
+        NalFlow::Continue
     }
-    fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
+    fn end(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
         self.end += 1;
+        NalFlow::Continue
     }
 }
 
@@ -56,7 +60,7 @@ fn h264_reader(c: &mut Criterion) {
     let mut f = File::open("big_buck_bunny_1080p_24fps_h264.h264").expect("file not found");
     let size = f.metadata().unwrap().len() as usize;
     let mut buf = vec![0; size];
-    f.read(&mut buf[..]).unwrap();
+    f.read_exact(&mut buf[..]).unwrap();
     let mut ctx = Context::default();
     let nal_handler = NullNalHandler {};
     let nal_reader = NullNalReader {
@@ -72,7 +76,7 @@ fn h264_reader(c: &mut Criterion) {
             annexb_reader.push(&mut ctx, &buf[..]);
             annexb_reader.end_units(&mut ctx);
         } );
-    }).throughput(Throughput::Bytes(size as u32)));
+    }).throughput(Throughput::Bytes(size as u64)));
 }
 
 criterion_group!(benches, h264_reader);
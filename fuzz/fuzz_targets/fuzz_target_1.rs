@@ -3,9 +3,9 @@ use libfuzzer_sys::fuzz_target;
 use h264_reader::rbsp::RbspDecoder;
 use h264_reader::annexb::AnnexBReader;
 use std::cell::RefCell;
-use std::io::Read;
+use std::convert::TryFrom;
 use h264_reader::{nal, Context, rbsp};
-use h264_reader::nal::{NalHandler, NalHeader};
+use h264_reader::nal::{NalFlow, NalHandler, NalHeader, UnitType};
 use h264_reader::nal::sps::SeqParameterSetNalHandler;
 use h264_reader::nal::pps::PicParameterSetNalHandler;
 
@@ -16,15 +16,18 @@ struct NalCapture {
 impl NalHandler for NalCapture {
     type Ctx = ();
 
-    fn start(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>, header: NalHeader) {
+    fn start(&mut self, _ctx: &mut h264_reader::Context<Self::Ctx>, _header: NalHeader) -> NalFlow {
         self.buf.clear();
+        NalFlow::Continue
     }
 
-    fn push(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>, buf: &[u8]) {
+    fn push(&mut self, _ctx: &mut h264_reader::Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
         self.buf.extend_from_slice(buf);
+        NalFlow::Continue
     }
 
-    fn end(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>) {
+    fn end(&mut self, _ctx: &mut h264_reader::Context<Self::Ctx>) -> NalFlow {
+        NalFlow::Continue
     }
 }
 
@@ -32,17 +35,36 @@ struct PicTimingFuzz;
 impl nal::sei::pic_timing::PicTimingHandler for PicTimingFuzz {
     type Ctx = ();
 
-    fn handle(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>, pic_timing: nal::sei::pic_timing::PicTiming) {
+    fn handle(&mut self, _ctx: &mut h264_reader::Context<Self::Ctx>, pic_timing: nal::sei::pic_timing::PicTiming) {
         println!("  {:?}", pic_timing);
     }
 }
 
+struct BufferingPeriodFuzz;
+impl nal::sei::buffering_period::BufferingPeriodHandler for BufferingPeriodFuzz {
+    type Ctx = ();
+
+    fn handle(&mut self, _ctx: &mut h264_reader::Context<Self::Ctx>, buffering_period: nal::sei::buffering_period::BufferingPeriod) {
+        println!("  {:?}", buffering_period);
+    }
+}
+
+#[derive(Default)]
+struct TT35Fuzz;
+impl nal::sei::user_data_registered_itu_t_t35::Register for TT35Fuzz {
+    type Ctx = ();
+
+    fn handle(&mut self, _ctx: &mut h264_reader::Context<Self::Ctx>, country_code: nal::sei::user_data_registered_itu_t_t35::ItuTT35, payload: &[u8]) {
+        println!("  {:?} {} bytes", country_code, payload.len());
+    }
+}
+
 h264_reader::sei_switch!{
     SeiSwitch<()> {
-        //BufferingPeriod: h264_reader::nal::sei::buffering_period::BufferingPeriodPayloadReader
-        //    => h264_reader::nal::sei::buffering_period::BufferingPeriodPayloadReader::new(),
-        //UserDataRegisteredItuTT35: h264_reader::nal::sei::user_data_registered_itu_t_t35::UserDataRegisteredItuTT35Reader<TT35Switch>
-        //    => h264_reader::nal::sei::user_data_registered_itu_t_t35::UserDataRegisteredItuTT35Reader::new(TT35Switch::default()),
+        BufferingPeriod: h264_reader::nal::sei::buffering_period::BufferingPeriodPayloadReader<BufferingPeriodFuzz>
+            => h264_reader::nal::sei::buffering_period::BufferingPeriodPayloadReader::new(BufferingPeriodFuzz),
+        UserDataRegisteredItuTT35: h264_reader::nal::sei::user_data_registered_itu_t_t35::UserDataRegisteredItuTT35Reader<TT35Fuzz>
+            => h264_reader::nal::sei::user_data_registered_itu_t_t35::UserDataRegisteredItuTT35Reader::new(TT35Fuzz),
         PicTiming: h264_reader::nal::sei::pic_timing::PicTimingReader<PicTimingFuzz>
             => h264_reader::nal::sei::pic_timing::PicTimingReader::new(PicTimingFuzz),
     }
@@ -88,24 +110,26 @@ impl SliceFuzz {
 impl h264_reader::nal::NalHandler for SliceFuzz {
     type Ctx = ();
 
-    fn start(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>, header: h264_reader::nal::NalHeader) {
+    fn start(&mut self, _ctx: &mut h264_reader::Context<Self::Ctx>, header: h264_reader::nal::NalHeader) -> NalFlow {
         let mut buf = Vec::new();
         buf.push(header.into());
         self.current_slice = Some(InProgressSlice {
             header,
             buf,
         });
+        NalFlow::Continue
     }
 
-    fn push(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>, buf: &[u8]) {
+    fn push(&mut self, _ctx: &mut h264_reader::Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
         self.current_slice
             .as_mut()
             .unwrap()
             .buf
             .extend_from_slice(buf);
+        NalFlow::Continue
     }
 
-    fn end(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>) {
+    fn end(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>) -> NalFlow {
         let current_slice = self.current_slice.take().unwrap();
         let capture = NalCapture::default();
         let mut decode = RbspDecoder::new(capture);
@@ -115,13 +139,64 @@ impl h264_reader::nal::NalHandler for SliceFuzz {
         let capture = decode.into_handler();
         let mut r = rbsp::RbspBitReader::new(&capture.buf[1..]);
         match nal::slice::SliceHeader::read(ctx, &mut r, current_slice.header) {
-            Ok((header, sps, pps)) => {
+            Ok((header, _sps, _pps)) => {
                 println!("{:#?}", header);
             },
             Err(e) => println!("slice_header() error: SliceHeaderError::{:?}", e),
         }
+        NalFlow::Continue
+    }
+}
+struct ExtensionFuzz;
+impl h264_reader::nal::header_extension::ExtendedNalHandler for ExtensionFuzz {
+    type Ctx = ();
+
+    fn start(&mut self, _ctx: &mut h264_reader::Context<Self::Ctx>, header: NalHeader, extension: Option<nal::header_extension::NalHeaderExtension>) -> NalFlow {
+        println!("{:?} {:?}", header, extension);
+        NalFlow::Continue
+    }
+
+    fn push(&mut self, _ctx: &mut h264_reader::Context<Self::Ctx>, _buf: &[u8]) -> NalFlow {
+        NalFlow::Continue
+    }
+
+    fn end(&mut self, _ctx: &mut h264_reader::Context<Self::Ctx>) -> NalFlow {
+        NalFlow::Continue
     }
 }
+
+#[derive(Default)]
+struct AccessUnitFuzz {
+    hrd: Option<h264_reader::hrd::HrdVerifier>,
+}
+impl h264_reader::access_unit::AccessUnitHandler for AccessUnitFuzz {
+    type Ctx = ();
+
+    fn handle(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>, access_unit: h264_reader::access_unit::AccessUnit) {
+        println!("access unit of {} NAL units", access_unit.nal_units.len());
+        for nal_unit in &access_unit.nal_units {
+            match nal::Nal::parse(ctx, nal_unit) {
+                Ok(nal) => println!("  {:?}", nal),
+                Err(e) => println!("  Nal::parse() error: {:?}", e),
+            }
+            if let Ok(header) = NalHeader::new(nal_unit[0]) {
+                if header.nal_unit_type() == UnitType::SliceLayerWithoutPartitioningAux {
+                    let _ = nal::aux_slice::AuxiliarySlice::read(ctx, nal_unit, access_unit.is_idr());
+                }
+            }
+        }
+        if self.hrd.is_none() {
+            self.hrd = ctx.active_sps()
+                .and_then(|sps| h264_reader::hrd::HrdVerifier::new(sps, h264_reader::hrd::HrdType::Vcl, 0).ok());
+        }
+        if let Some(ref mut hrd) = self.hrd {
+            if let Err(e) = hrd.push_access_unit(ctx, &access_unit) {
+                println!("  push_access_unit() error: {:?}", e);
+            }
+        }
+    }
+}
+
 fuzz_target!(|data: &[u8]| {
     let mut switch = h264_reader::nal::NalSwitch::default();
     let sei_handler = h264_reader::nal::sei::SeiNalHandler::new(FuzzSeiPayoadReader { switch: SeiSwitch::default() });
@@ -134,10 +209,56 @@ fuzz_target!(|data: &[u8]| {
     switch.put_handler(h264_reader::nal::UnitType::PicParameterSet, Box::new(RefCell::new(pps_handler)));
     switch.put_handler(h264_reader::nal::UnitType::SliceLayerWithoutPartitioningIdr, Box::new(RefCell::new(slice_wout_part_idr_handler)));
     switch.put_handler(h264_reader::nal::UnitType::SliceLayerWithoutPartitioningNonIdr, Box::new(RefCell::new(slice_wout_part_nonidr_handler)));
+    switch.put_handler(UnitType::PrefixNALUnit, Box::new(RefCell::new(nal::header_extension::NalHeaderExtensionReader::new(ExtensionFuzz))));
+    switch.put_handler(UnitType::SliceExtension, Box::new(RefCell::new(nal::header_extension::NalHeaderExtensionReader::new(ExtensionFuzz))));
+    switch.put_handler(UnitType::SliceExtensionViewComponent, Box::new(RefCell::new(nal::header_extension::NalHeaderExtensionReader::new(ExtensionFuzz))));
 
     let mut ctx = Context::default();
     let mut annexb_reader = AnnexBReader::new(switch);
     annexb_reader.start(&mut ctx);
     annexb_reader.push(&mut ctx, data);
     annexb_reader.end_units(&mut ctx);
+
+    // the same data grouped into access units
+    let mut ctx = Context::default();
+    let mut annexb_reader = AnnexBReader::new(h264_reader::access_unit::AccessUnitReader::new(AccessUnitFuzz::default()));
+    annexb_reader.start(&mut ctx);
+    annexb_reader.push(&mut ctx, data);
+    annexb_reader.end_units(&mut ctx);
+    annexb_reader.nal_reader_mut().flush(&mut ctx);
+    if let Some(mut hrd) = annexb_reader.into_nal_reader().into_handler().hrd {
+        hrd.finish();
+    }
+
+    // the same data interpreted as a single complete NAL unit
+    let _ = nal::Nal::parse(&mut Context::default(), data);
+    if let Some((&first, rest)) = data.split_first() {
+        if let Ok(header) = NalHeader::new(first) {
+            let _ = nal::header_extension::NalHeaderExtension::read(header.nal_unit_type(), rest);
+        }
+    }
+
+    // the same data interpreted as the RBSP of single parameter sets, using any SPS found above,
+    if let Ok(sps) = nal::sps::SeqParameterSet::from_bytes(data) {
+        let _ = sps.pixel_dimensions();
+    }
+    let _ = nal::sps::SeqParameterSet::from_bytes_strict(data);
+    let _ = nal::pps::PicParameterSet::from_bytes(&ctx, data);
+    let _ = nal::pps::PicParameterSet::from_bytes_strict(&ctx, data);
+    let _ = nal::subset_sps::SubsetSeqParameterSet::from_bytes(data);
+    let _ = nal::subset_sps::SubsetSeqParameterSet::from_bytes_strict(data);
+    let _ = nal::sps_extension::SeqParameterSetExtension::from_bytes(data);
+    let _ = nal::sps_extension::SeqParameterSetExtension::from_bytes_strict(data);
+    let _ = nal::sei::SeiMessage::read_all(data);
+
+    // ...and as an AVC decoder configuration record
+    if let Ok(avcc) = h264_reader::avcc::AvcDecoderConfigurationRecord::try_from(data) {
+        for sps in avcc.sequence_parameter_sets() {
+            let _ = sps;
+        }
+        for pps in avcc.picture_parameter_sets() {
+            let _ = pps;
+        }
+        let _ = avcc.create_context(());
+    }
 });
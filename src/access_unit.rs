@@ -0,0 +1,478 @@
+//! Grouping of NAL units into _access units_, each holding the NAL units of one primary coded
+//! picture along with its associated non-VCL NAL units (SEI, parameter sets, access unit
+//! delimiter, etc).
+//!
+//! Streams are not required to include access unit delimiters, so the boundaries between access
+//! units are detected using the rules of clause 7.4.1.2.3 and, where one VCL NAL unit immediately
+//! follows another, by comparing slice headers according to clause 7.4.1.2.4.
+
+use crate::annexb::NalReader;
+use crate::nal::{NalFlow, NalHeader, UnitType};
+use crate::nal::aux_slice::{AuxiliarySlice, AuxSliceError};
+use crate::nal::pps::{PicParamSetId, PicParameterSet};
+use crate::nal::slice::{FieldPic, PicOrderCountLsb, SliceHeader};
+use crate::nal::sps::SeqParameterSet;
+use crate::nal::sps_extension::SeqParameterSetExtension;
+use crate::rbsp::{self, RbspBitReader};
+use crate::Context;
+
+/// The NAL units making up a single access unit.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AccessUnit {
+    /// The NAL units in stream order, each including its header byte, and still in encoded form
+    /// (i.e. still containing any emulation prevention bytes)
+    pub nal_units: Vec<Vec<u8>>,
+    /// The number of bytes of the Annex B byte stream making up the access unit (including start
+    /// codes, `zero_byte`s, and any `leading_zero_8bits` and `trailing_zero_8bits`), if the NAL
+    /// units were read from a byte stream by an `AnnexBReader`
+    pub byte_stream_len: Option<u64>,
+}
+impl AccessUnit {
+    /// The headers of the NAL units in this access unit, in stream order
+    pub fn headers(&self) -> impl Iterator<Item = NalHeader> + '_ {
+        self.nal_units.iter()
+            .filter_map(|nal| nal.first())
+            .filter_map(|&b| NalHeader::new(b).ok())
+    }
+
+    /// True if the primary coded picture of this access unit is an IDR picture
+    pub fn is_idr(&self) -> bool {
+        self.headers().any(|h| h.nal_unit_type() == UnitType::SliceLayerWithoutPartitioningIdr)
+    }
+
+    /// Parses the slice headers of any auxiliary coded picture (e.g. an alpha plane) in this
+    /// access unit, taking the IDR status of the auxiliary picture from the primary coded picture.
+    ///
+    /// The `Context` must hold the parameter sets and SPS extension that the slices refer to.
+    pub fn auxiliary_slices<Ctx>(&self, ctx: &mut Context<Ctx>) -> Vec<Result<AuxiliarySlice, AuxSliceError>> {
+        let primary_idr = self.is_idr();
+        self.nal_units.iter()
+            .filter(|nal| nal.first().and_then(|&b| NalHeader::new(b).ok())
+                .map(|h| h.nal_unit_type() == UnitType::SliceLayerWithoutPartitioningAux)
+                .unwrap_or(false))
+            .map(|nal| AuxiliarySlice::read(ctx, nal, primary_idr))
+            .collect()
+    }
+}
+
+pub trait AccessUnitHandler {
+    type Ctx;
+    fn handle(&mut self, ctx: &mut Context<Self::Ctx>, access_unit: AccessUnit);
+}
+
+/// The values from the first slice of a primary coded picture which clause 7.4.1.2.4 uses to tell
+/// if a following slice belongs to a different picture.
+#[derive(Debug, PartialEq)]
+struct PicIdent {
+    frame_num: u16,
+    pic_parameter_set_id: PicParamSetId,
+    field_pic: FieldPic,
+    nal_ref_idc_zero: bool,
+    pic_order_cnt_lsb: Option<PicOrderCountLsb>,
+    /// `Some` only for IDR pictures, so also captures `IdrPicFlag`
+    idr_pic_id: Option<u32>,
+}
+impl PicIdent {
+    fn new(nal_header: NalHeader, slice_header: &SliceHeader) -> PicIdent {
+        PicIdent {
+            frame_num: slice_header.frame_num,
+            pic_parameter_set_id: slice_header.pic_parameter_set_id,
+            field_pic: slice_header.field_pic,
+            nal_ref_idc_zero: nal_header.nal_ref_idc() == 0,
+            pic_order_cnt_lsb: slice_header.pic_order_cnt_lsb,
+            idr_pic_id: slice_header.idr_pic_id,
+        }
+    }
+}
+
+/// A `NalReader` which collects NAL units into `AccessUnit`s, passing each complete access unit to
+/// the given `AccessUnitHandler`.
+///
+/// To interpret slice headers, SPS, SPS extension and PPS NAL units are parsed and stored in the
+/// `Context` as they are encountered (so it's not necessary to also register handlers for them).
+/// The SPS referred to by the first slice of each access unit is made the active SPS, once the
+/// previous access unit has been passed to the handler.
+///
+/// An access unit is only known to be complete once the first NAL unit of the following access
+/// unit has been seen, so the final access unit of a stream must be passed on by calling
+/// `flush()`.
+pub struct AccessUnitReader<H: AccessUnitHandler> {
+    handler: H,
+    nal: Vec<u8>,
+    /// the byte stream length of the NAL unit in `nal`, if the `AnnexBReader` has given it
+    nal_len: Option<u64>,
+    current: AccessUnit,
+    /// true once the current access unit has a VCL NAL unit of its primary coded picture
+    seen_vcl: bool,
+    prev_pic: Option<PicIdent>,
+    /// prefix NAL units (and NAL units of types 17 and 18) not yet followed by a VCL NAL unit,
+    /// with their byte stream lengths; these precede the slice they relate to, so which access
+    /// unit they belong to is only known once that slice has been seen
+    pending: Vec<(Vec<u8>, Option<u64>)>,
+}
+impl<H: AccessUnitHandler> AccessUnitReader<H> {
+    pub fn new(handler: H) -> Self {
+        AccessUnitReader {
+            handler,
+            nal: Vec::new(),
+            nal_len: None,
+            current: AccessUnit::default(),
+            seen_vcl: false,
+            prev_pic: None,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    pub fn into_handler(self) -> H {
+        self.handler
+    }
+
+    /// Passes any NAL units accumulated so far to the handler as an access unit.  To be called
+    /// at the end of the stream.
+    pub fn flush(&mut self, ctx: &mut Context<H::Ctx>) {
+        self.take_pending();
+        self.handle_current(ctx);
+    }
+
+    /// Adds a NAL unit to the current access unit; the access unit's length is only known if
+    /// that of each of its NAL units is
+    fn push_nal(&mut self, nal: Vec<u8>, len: Option<u64>) {
+        self.current.byte_stream_len = if self.current.nal_units.is_empty() {
+            len
+        } else {
+            self.current.byte_stream_len.and_then(|total| len.map(|len| total + len))
+        };
+        self.current.nal_units.push(nal);
+    }
+
+    fn take_pending(&mut self) {
+        for (nal, len) in std::mem::take(&mut self.pending) {
+            self.push_nal(nal, len);
+        }
+    }
+
+    fn handle_current(&mut self, ctx: &mut Context<H::Ctx>) {
+        if !self.current.nal_units.is_empty() {
+            let au = std::mem::take(&mut self.current);
+            self.handler.handle(ctx, au);
+        }
+        self.seen_vcl = false;
+    }
+
+    fn next_access_unit(&mut self, ctx: &mut Context<H::Ctx>) {
+        if self.seen_vcl {
+            self.handle_current(ctx);
+        }
+    }
+
+    /// `len` is the number of bytes of the byte stream making up the NAL unit, if known
+    fn nal_unit(&mut self, ctx: &mut Context<H::Ctx>, nal: Vec<u8>, len: Option<u64>) {
+        let header = match nal.first().map(|&b| NalHeader::new(b)) {
+            Some(Ok(header)) => header,
+            Some(Err(e)) => {
+                eprintln!("AccessUnitReader: bad NAL header: {:?}", e);
+                return;
+            },
+            None => return,
+        };
+        match header.nal_unit_type() {
+            UnitType::SliceLayerWithoutPartitioningNonIdr
+            | UnitType::SliceDataPartitionALayer
+            | UnitType::SliceLayerWithoutPartitioningIdr => {
+                let rbsp = rbsp::decode_nal(&nal[1..]);
+                let mut r = RbspBitReader::new(&rbsp[..]);
+                match SliceHeader::read(ctx, &mut r, header) {
+                    Ok((slice_header, _, _)) => {
+                        // redundant coded pictures belong to the same access unit as the primary
+                        // coded picture
+                        if slice_header.redundant_pic_cnt.unwrap_or(0) == 0 {
+                            let pic = PicIdent::new(header, &slice_header);
+                            if self.prev_pic.as_ref() != Some(&pic) {
+                                self.next_access_unit(ctx);
+                                // only now that any previous access unit has been handled, the
+                                // SPS of this picture becomes active
+                                if let Some(pps) = ctx.pps_by_id(slice_header.pic_parameter_set_id) {
+                                    let sps_id = pps.seq_parameter_set_id;
+                                    ctx.activate_sps(sps_id);
+                                }
+                            }
+                            self.prev_pic = Some(pic);
+                        }
+                    },
+                    Err(e) => eprintln!("AccessUnitReader: slice_header() error: {:?}", e),
+                }
+                self.seen_vcl = true;
+            },
+            UnitType::SEI
+            | UnitType::SeqParameterSet
+            | UnitType::PicParameterSet
+            | UnitType::AccessUnitDelimiter
+            | UnitType::SubsetSeqParameterSet
+            | UnitType::DepthParameterSet => {
+                self.next_access_unit(ctx);
+                match header.nal_unit_type() {
+                    UnitType::SeqParameterSet => self.seq_parameter_set(ctx, &nal),
+                    UnitType::PicParameterSet => self.pic_parameter_set(ctx, &nal),
+                    _ => (),
+                }
+            },
+            UnitType::SeqParameterSetExtension => {
+                match SeqParameterSetExtension::from_bytes(&rbsp::decode_nal(&nal[1..])) {
+                    Ok(ext) => ctx.put_seq_param_set_ext(ext),
+                    Err(e) => eprintln!("AccessUnitReader: sps extension: {:?}", e),
+                }
+            },
+            UnitType::PrefixNALUnit
+            | UnitType::Reserved(17)
+            | UnitType::Reserved(18) => {
+                // a prefix NAL unit also precedes each further slice of a multi-slice picture,
+                // so only starts a new access unit if the slice following it does
+                self.pending.push((nal, len));
+                return;
+            },
+            UnitType::EndOfSeq | UnitType::EndOfStream => {
+                // the next picture will be an IDR picture, and must start a new access unit
+                // even if its slice headers happen to match those of the previous picture
+                self.prev_pic = None;
+            },
+            _ => (),
+        }
+        self.take_pending();
+        self.push_nal(nal, len);
+    }
+
+    fn seq_parameter_set(&mut self, ctx: &mut Context<H::Ctx>, nal: &[u8]) {
+        let rbsp = rbsp::decode_nal(&nal[1..]);
+        match SeqParameterSet::from_bytes(&rbsp) {
+            Ok(sps) => { ctx.put_seq_param_set_with_nal(sps, nal); },
+            Err(e) => eprintln!("AccessUnitReader: sps: {:?}", e),
+        }
+    }
+
+    fn pic_parameter_set(&mut self, ctx: &mut Context<H::Ctx>, nal: &[u8]) {
+        let rbsp = rbsp::decode_nal(&nal[1..]);
+        match PicParameterSet::from_bytes(ctx, &rbsp) {
+            Ok(pps) => { ctx.put_pic_param_set_with_nal(pps, nal); },
+            Err(e) => eprintln!("AccessUnitReader: pps: {:?}", e),
+        }
+    }
+}
+impl<H: AccessUnitHandler> NalReader for AccessUnitReader<H> {
+    type Ctx = H::Ctx;
+
+    fn start(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
+        self.nal.clear();
+        self.nal_len = None;
+        NalFlow::Continue
+    }
+
+    fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
+        self.nal.extend_from_slice(buf);
+        NalFlow::Continue
+    }
+
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) -> NalFlow {
+        let nal = std::mem::take(&mut self.nal);
+        let len = self.nal_len.take();
+        self.nal_unit(ctx, nal, len);
+        NalFlow::Continue
+    }
+
+    fn byte_stream_len(&mut self, _ctx: &mut Context<Self::Ctx>, len: u64) {
+        self.nal_len = Some(len);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::annexb::AnnexBReader;
+    use hex_literal::*;
+
+    #[derive(Default)]
+    struct State {
+        access_units: Vec<AccessUnit>,
+        /// the id of the SPS active when each access unit was handled
+        active_sps: Vec<Option<u8>>,
+    }
+    impl AccessUnitHandler for State {
+        type Ctx = ();
+
+        fn handle(&mut self, ctx: &mut Context<Self::Ctx>, access_unit: AccessUnit) {
+            self.access_units.push(access_unit);
+            self.active_sps.push(ctx.active_sps().map(|sps| sps.seq_parameter_set_id.id()));
+        }
+    }
+
+    /// builds a NAL unit from the given header byte and a string of RBSP bits, appending the
+    /// rbsp_trailing_bits()
+    fn nal(header: u8, bits: &str) -> Vec<u8> {
+        let mut bits: Vec<bool> = bits.chars().filter(|c| !c.is_whitespace()).map(|c| c == '1').collect();
+        bits.push(true);
+        let padding = (8 - bits.len() % 8) % 8;
+        bits.extend(std::iter::repeat(false).take(padding));
+        let mut result = vec![header];
+        result.extend(bits.chunks(8).map(|byte| byte.iter().fold(0, |acc, &b| acc << 1 | b as u8)));
+        result
+    }
+
+    // first_mb_in_slice, slice_type=7 (I), pic_parameter_set_id=0, frame_num, idr_pic_id,
+    // pic_order_cnt_lsb, delta_pic_order_cnt_bottom=0, no_output_of_prior_pics_flag=0,
+    // long_term_reference_flag=0, slice_qp_delta=0, disable_deblocking_filter_idc=1
+    fn idr_slice(first_mb: &str, idr_pic_id: &str, poc_lsb: &str) -> Vec<u8> {
+        nal(0x65, &format!("{} 0001000 1 000000 {} {} 1 00 1 010", first_mb, idr_pic_id, poc_lsb))
+    }
+
+    // first_mb_in_slice, slice_type=5 (P), pic_parameter_set_id=0, frame_num, pic_order_cnt_lsb,
+    // delta_pic_order_cnt_bottom=0, num_ref_idx_active_override_flag=0,
+    // ref_pic_list_modification_flag_l0=0, adaptive_ref_pic_marking_mode_flag=0,
+    // slice_qp_delta=0, disable_deblocking_filter_idc=1
+    fn p_slice(first_mb: &str, frame_num: &str, poc_lsb: &str) -> Vec<u8> {
+        nal(0x41, &format!("{} 00110 1 {} {} 1 0 0 0 1 010", first_mb, frame_num, poc_lsb))
+    }
+
+    #[test]
+    fn boundaries() {
+        let sps = hex!("6742c01e b91061ff 78088000 00030080 00001971 3006d600 daf7bdc0 7c2211a8").to_vec();
+        let pps = hex!("68de3c80").to_vec();
+        let sei = hex!("06 05 01 ff 80").to_vec();
+        let nals = vec![
+            // access unit 1: parameter sets, SEI and an IDR picture of two slices
+            sps.clone(),
+            pps.clone(),
+            sei.clone(),
+            idr_slice("1", "1", "0000000"),
+            idr_slice("00110", "1", "0000000"),
+            // access unit 2: P picture with no preceding non-VCL NAL units
+            p_slice("1", "000001", "0000010"),
+            p_slice("00110", "000001", "0000010"),
+            // access unit 3: SEI followed by a P picture
+            sei.clone(),
+            p_slice("1", "000010", "0000100"),
+            hex!("0c ff ff 80").to_vec(),
+            // access unit 4: another IDR picture, after an end of sequence
+            hex!("0a").to_vec(),
+            idr_slice("1", "010", "0000000"),
+        ];
+        let mut stream = vec![];
+        for n in &nals {
+            stream.extend_from_slice(&[0, 0, 0, 1]);
+            stream.extend_from_slice(n);
+        }
+        let mut ctx = Context::default();
+        let mut r = AnnexBReader::new(AccessUnitReader::new(State::default()));
+        r.start(&mut ctx);
+        r.push(&mut ctx, &stream[..]);
+        r.end_units(&mut ctx);
+        let mut au_reader = r.into_nal_reader();
+        au_reader.flush(&mut ctx);
+        let aus = au_reader.into_handler().access_units;
+        let lens: Vec<usize> = aus.iter().map(|au| au.nal_units.len()).collect();
+        assert_eq!(lens, vec![5, 2, 4, 1]);
+        let byte_stream_len: u64 = aus.iter().map(|au| au.byte_stream_len.unwrap()).sum();
+        assert_eq!(byte_stream_len, stream.len() as u64);
+        assert_eq!(aus[0].nal_units[0], sps);
+        assert!(aus[0].is_idr());
+        assert!(!aus[1].is_idr());
+        // end_of_seq belongs to the access unit it follows
+        assert_eq!(aus[2].headers().last().unwrap().nal_unit_type(), UnitType::EndOfSeq);
+        assert!(aus[3].is_idr());
+    }
+
+    #[test]
+    fn auxiliary() {
+        let mut ctx = Context::default();
+        // an SPS extension giving aux_format_idc=1, then the parameter sets it extends
+        let nals = vec![
+            hex!("6d a9 fe 00 40").to_vec(),
+            hex!("6742c01e b91061ff 78088000 00030080 00001971 3006d600 daf7bdc0 7c2211a8").to_vec(),
+            hex!("68de3c80").to_vec(),
+            idr_slice("1", "1", "0000000"),
+            nal(0x73, "1 0001000 1 000000 1 0000000 1 00 1 010"),
+        ];
+        let mut au_reader = AccessUnitReader::new(State::default());
+        for n in nals {
+            au_reader.nal_unit(&mut ctx, n, None);
+        }
+        au_reader.flush(&mut ctx);
+        let aus = au_reader.into_handler().access_units;
+        assert_eq!(aus.len(), 1);
+        let aux = aus[0].auxiliary_slices(&mut ctx);
+        assert_eq!(aux.len(), 1);
+        let aux = aux[0].as_ref().unwrap();
+        // the primary picture is IDR, so the auxiliary picture is too
+        assert_eq!(aux.header.idr_pic_id, Some(0));
+    }
+
+    #[test]
+    fn activates_sps() {
+        let mut ctx = Context::default();
+        let nals = vec![
+            hex!("6742c01e b91061ff 78088000 00030080 00001971 3006d600 daf7bdc0 7c2211a8").to_vec(),
+            hex!("68de3c80").to_vec(),
+            // the same SPS and PPS, but with seq_parameter_set_id=1 and pic_parameter_set_id=1
+            hex!("6742c01e 4e44187f de022000 00030020 0000065c 4c01b580 36bdef70 1f08846a").to_vec(),
+            hex!("6849e3c8").to_vec(),
+            idr_slice("1", "1", "0000000"),
+            // an IDR picture using PPS 1, immediately following the first
+            nal(0x65, "1 0001000 010 000000 010 0000000 1 00 1 010"),
+        ];
+        let mut au_reader = AccessUnitReader::new(State::default());
+        for n in nals {
+            au_reader.nal_unit(&mut ctx, n, None);
+        }
+        au_reader.flush(&mut ctx);
+        let state = au_reader.into_handler();
+        assert_eq!(state.access_units.len(), 2);
+        // the second picture's SPS is not activated until the first access unit was handled
+        assert_eq!(state.active_sps, vec![Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn prefix_nal_units() {
+        let mut ctx = Context::default();
+        // nal_ref_idc=3, svc_extension_flag=1, idr_flag=1, no_inter_layer_pred_flag=1,
+        // output_flag=1, and an empty prefix_nal_unit_rbsp()
+        let prefix = hex!("6e c0 80 07 80").to_vec();
+        let nals = vec![
+            hex!("6742c01e b91061ff 78088000 00030080 00001971 3006d600 daf7bdc0 7c2211a8").to_vec(),
+            hex!("68de3c80").to_vec(),
+            // an IDR picture of two slices, each preceded by a prefix NAL unit
+            prefix.clone(),
+            idr_slice("1", "1", "0000000"),
+            prefix.clone(),
+            idr_slice("00110", "1", "0000000"),
+            // the prefix NAL unit of the next picture starts the next access unit
+            prefix.clone(),
+            idr_slice("1", "010", "0000000"),
+        ];
+        let mut au_reader = AccessUnitReader::new(State::default());
+        for n in nals {
+            au_reader.nal_unit(&mut ctx, n, None);
+        }
+        au_reader.flush(&mut ctx);
+        let aus = au_reader.into_handler().access_units;
+        let types: Vec<Vec<UnitType>> = aus.iter()
+            .map(|au| au.headers().map(|h| h.nal_unit_type()).collect())
+            .collect();
+        assert_eq!(types, vec![
+            vec![
+                UnitType::SeqParameterSet,
+                UnitType::PicParameterSet,
+                UnitType::PrefixNALUnit,
+                UnitType::SliceLayerWithoutPartitioningIdr,
+                UnitType::PrefixNALUnit,
+                UnitType::SliceLayerWithoutPartitioningIdr,
+            ],
+            vec![
+                UnitType::PrefixNALUnit,
+                UnitType::SliceLayerWithoutPartitioningIdr,
+            ],
+        ]);
+    }
+
+}
@@ -2,6 +2,7 @@
 //! as used when H264 data is embedded in an MPEG2 Transport Stream
 
 use crate::Context;
+use crate::nal::NalFlow;
 use memchr;
 
 #[derive(Debug)]
@@ -53,12 +54,20 @@ impl ParseState {
 }
 
 
+/// Receives the data of each NAL unit found by an `AnnexBReader`.  The `NalFlow` values returned
+/// allow the implementation to skip the remainder of a NAL unit, or to abort reading the stream.
 pub trait NalReader {
     type Ctx;
 
-    fn start(&mut self, ctx: &mut Context<Self::Ctx>);
-    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]);
-    fn end(&mut self, ctx: &mut Context<Self::Ctx>);
+    fn start(&mut self, ctx: &mut Context<Self::Ctx>) -> NalFlow;
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow;
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) -> NalFlow;
+
+    /// Called immediately before `end()` with the number of bytes of the byte stream making up
+    /// the NAL unit's `byte_stream_nal_unit()`: the NAL unit itself, plus its start code prefix,
+    /// any `zero_byte`, and any `leading_zero_8bits` or `trailing_zero_8bits`.  This is the size
+    /// counted by the Type II HRD.  Does nothing by default.
+    fn byte_stream_len(&mut self, _ctx: &mut Context<Self::Ctx>, _len: u64) {}
 }
 
 pub struct AnnexBReader<R, Ctx>
@@ -67,6 +76,12 @@ pub struct AnnexBReader<R, Ctx>
 {
     state: ParseState,
     nal_reader: R,
+    /// the most recent `NalFlow` returned by `nal_reader` for the current NAL unit
+    flow: NalFlow,
+    /// the position in the byte stream of the end of the data given to `push()` so far
+    offset: u64,
+    /// the position in the byte stream at which the current `byte_stream_nal_unit()` began
+    unit_offset: u64,
 }
 impl<R, Ctx> AnnexBReader<R, Ctx>
     where
@@ -76,19 +91,48 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
         AnnexBReader {
             state: ParseState::End,
             nal_reader,
+            flow: NalFlow::Continue,
+            offset: 0,
+            unit_offset: 0,
         }
     }
 
+    pub fn nal_reader(&self) -> &R {
+        &self.nal_reader
+    }
+
+    pub fn nal_reader_mut(&mut self) -> &mut R {
+        &mut self.nal_reader
+    }
+
+    pub fn into_nal_reader(self) -> R {
+        self.nal_reader
+    }
+
+    /// Prepares to read a new stream, also clearing any previous `NalFlow::Abort`.
     pub fn start(&mut self, ctx: &mut Context<Ctx>) {
         if self.state.in_unit() {
             // TODO: or reset()?
-            self.nal_reader.end(ctx);
+            self.reader_end(ctx, self.offset);
         }
+        self.flow = NalFlow::Continue;
+        self.offset = 0;
+        self.unit_offset = 0;
         self.to(ParseState::Start);
     }
 
-    pub fn push(&mut self, ctx: &mut Context<Ctx>, buf: &[u8]) {
+    /// Parses the given data, passing the NAL units found to the `NalReader`.
+    ///
+    /// Returns `NalFlow::Abort` if the `NalReader` has requested that processing stop, in which
+    /// case this and any subsequent data is ignored until `start()` is called again; otherwise
+    /// returns `NalFlow::Continue`.
+    pub fn push(&mut self, ctx: &mut Context<Ctx>, buf: &[u8]) -> NalFlow {
+        if self.flow == NalFlow::Abort {
+            return NalFlow::Abort;
+        }
         let mut unit_start: Option<isize> = self.state.end_backtrack_bytes().map(|v| -(v as isize));
+        let buf_offset = self.offset;
+        self.offset += buf.len() as u64;
 
         let mut i = 0;
         while i < buf.len() {
@@ -97,9 +141,9 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
                 ParseState::End => {
                     eprintln!("no previous call to start()");
                     self.state = ParseState::Error;
-                    return;
+                    return NalFlow::Continue;
                 },
-                ParseState::Error => return,
+                ParseState::Error => return NalFlow::Continue,
                 ParseState::Start => {
                     match b {
                         0x00 => self.to(ParseState::StartOneZero),
@@ -118,13 +162,13 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
                         0x01 => {
                             self.to(ParseState::InUnit);
                             unit_start = Some(i as isize + 1);
-                            self.nal_reader.start(ctx);
+                            self.reader_start(ctx);
                         },
                         _ => self.err(b),
                     }
                 },
                 ParseState::InUnitStart => {
-                    self.nal_reader.start(ctx);
+                    self.reader_start(ctx);
                     match b {
                         0x00 => self.to(ParseState::InUnitOneZero),
                         _ => self.to(ParseState::InUnit),
@@ -159,7 +203,8 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
                             if unit_start.is_some() && (unit_start.unwrap() > 0 || i > 2) {
                                 self.emit(ctx, buf, unit_start, i - 2);
                             }
-                            self.nal_reader.end(ctx);
+                            // the next NAL unit begins with the three byte start code
+                            self.reader_end(ctx, buf_offset + i as u64 - 2);
                             unit_start = Some(i as isize + 1);
                             self.to(ParseState::InUnitStart);
                         },
@@ -178,7 +223,8 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
                             if unit_start.is_some() && (unit_start.unwrap() > 0 || i > 3) {
                                 self.emit(ctx, buf, unit_start, i - 3);
                             }
-                            self.nal_reader.end(ctx);
+                            // the next NAL unit begins with the zero_byte preceding its start code
+                            self.reader_end(ctx, buf_offset + i as u64 - 3);
                             unit_start = Some(i as isize + 1);
                             self.to(ParseState::InUnitStart);
                         },
@@ -192,24 +238,21 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
                     match b {
                         0x00 => {
                             // We now presume that the earliest of the three zeros was part of
-                            // cabac_zero_word, but we can't yet know how many more zeros might be
-                            // part of further cabac_zero_word entries, and how many might
-                            // become part of a 0x00000001 start code (because we've not seen
-                            // the 0x01 yet); so we stay in InUnitThreeZero state
+                            // trailing_zero_8bits (or the zero_byte of a four byte start code),
+                            // but we can't yet know how many more zeros might follow, and how
+                            // many might become part of a 0x00000001 start code (because we've
+                            // not seen the 0x01 yet); so we stay in InUnitThreeZero state
                             //
-                            // Spec:
-                            // "One or more cabac_zero_word 16-bit syntax elements equal to 0x0000
-                            //  may be present in some RBSPs after the rbsp_trailing_bits( ) at
-                            //  the end of the RBSP."
-                            //
-                            // We're not yet bothering to check that the trailing 0x00 come in
-                            // pairs to make 16 bit elements mentioned above,
+                            // Any number of these zeros is permitted.  Note that cabac_zero_word
+                            // elements are not seen here, since within the NAL unit they are
+                            // followed by emulation prevention bytes (i.e. appear as 0x000003).
                         },
                         0x01 => {
                             if unit_start.is_some() && (unit_start.unwrap() > 0 || i > 3) {
                                 self.emit(ctx, buf, unit_start, i - 3);
                             }
-                            self.nal_reader.end(ctx);
+                            // the next NAL unit begins with the zero_byte preceding its start code
+                            self.reader_end(ctx, buf_offset + i as u64 - 3);
                             unit_start = Some(i as isize + 1);
                             self.to(ParseState::InUnitStart);
                         },
@@ -220,6 +263,9 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
                     }
                 },
             }
+            if self.flow == NalFlow::Abort {
+                return NalFlow::Abort;
+            }
             i += 1;
         }
         if let (Some(start), Some(backtrack)) = (unit_start, self.state.end_backtrack_bytes()) {
@@ -229,9 +275,14 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
                 start as usize
             };
             if buf.len() > backtrack {
-                self.nal_reader.push(ctx, &buf[adjusted_start..buf.len() - backtrack])
+                self.reader_push(ctx, &buf[adjusted_start..buf.len() - backtrack])
             }
         }
+        if self.flow == NalFlow::Abort {
+            NalFlow::Abort
+        } else {
+            NalFlow::Continue
+        }
     }
 
     /// To be invoked when calling code knows that the end of a sequence of NAL Unit data has been
@@ -240,18 +291,56 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
     /// For example, if the containing data structure demarcates the end of a sequence of NAL
     /// Units explicitly, the parser for that structure should call `end_units()` once all data
     /// has been passed to the `push()` function.
-    pub fn end_units(&mut self, ctx: &mut Context<Ctx>) {
+    ///
+    /// Returns `NalFlow::Abort` if the `NalReader` has requested that processing stop, and
+    /// `NalFlow::Continue` otherwise.
+    pub fn end_units(&mut self, ctx: &mut Context<Ctx>) -> NalFlow {
+        if self.flow == NalFlow::Abort {
+            return NalFlow::Abort;
+        }
         if let Some(backtrack) = self.state.end_backtrack_bytes() {
             // if we were in the middle of parsing a sequence of 0x00 bytes that might have become
             // a start-code, but actually reached the end of input, then we will now need to emit
             // those 0x00 bytes that we had been holding back,
             if backtrack > 0 {
                 let tmp = [0u8; 3];
-                self.nal_reader.push(ctx, &tmp[0..backtrack]);
+                self.reader_push(ctx, &tmp[0..backtrack]);
             }
         }
         self.to(ParseState::End);
-        self.nal_reader.end(ctx);
+        self.reader_end(ctx, self.offset);
+        if self.flow == NalFlow::Abort {
+            NalFlow::Abort
+        } else {
+            NalFlow::Continue
+        }
+    }
+
+    fn reader_start(&mut self, ctx: &mut Context<Ctx>) {
+        if self.flow != NalFlow::Abort {
+            self.flow = self.nal_reader.start(ctx);
+        }
+    }
+
+    fn reader_push(&mut self, ctx: &mut Context<Ctx>, buf: &[u8]) {
+        if self.flow == NalFlow::Continue {
+            self.flow = self.nal_reader.push(ctx, buf);
+        }
+    }
+
+    /// `end_offset` is the position in the byte stream at which the NAL unit now ending is
+    /// followed by the next, or by the end of the stream
+    fn reader_end(&mut self, ctx: &mut Context<Ctx>, end_offset: u64) {
+        let len = end_offset - self.unit_offset;
+        self.unit_offset = end_offset;
+        if self.flow != NalFlow::Abort {
+            self.nal_reader.byte_stream_len(ctx, len);
+            // a Skip only applies to the NAL unit now ending
+            self.flow = match self.nal_reader.end(ctx) {
+                NalFlow::Abort => NalFlow::Abort,
+                _ => NalFlow::Continue,
+            };
+        }
     }
 
     fn to(&mut self, new_state: ParseState) {
@@ -261,7 +350,7 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
     /// count must be 4 or less
     fn emit_fake(&mut self, ctx: &mut Context<Ctx>, count: usize) {
         let fake = [0u8; 4];
-        self.nal_reader.push(ctx, &fake[..count]);
+        self.reader_push(ctx, &fake[..count]);
     }
 
     fn emit(&mut self, ctx: &mut Context<Ctx>, buf:&[u8], start_index: Option<isize>, end_index: usize) {
@@ -271,7 +360,7 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
             } else {
                 start as usize
             };
-            self.nal_reader.push(ctx, &buf[start..end_index])
+            self.reader_push(ctx, &buf[start..end_index])
         } else {
             eprintln!("AnnexBReader: no start_index");
         }
@@ -308,16 +397,54 @@ mod tests {
     impl NalReader for MockReader {
         type Ctx = ();
 
-        fn start(&mut self, _ctx: &mut Context<Self::Ctx>) {
+        fn start(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
             self.state.borrow_mut().started += 1;
+            NalFlow::Continue
         }
 
-        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
             self.state.borrow_mut().data.extend_from_slice(buf);
+            NalFlow::Continue
         }
 
-        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
+        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
             self.state.borrow_mut().ended += 1;
+            NalFlow::Continue
+        }
+    }
+
+    /// Wants only the first byte of each NAL unit, and only the first two NAL units
+    #[derive(Default)]
+    struct SkippingReader {
+        started: u32,
+        ended: u32,
+        data: Vec<u8>,
+    }
+    impl NalReader for SkippingReader {
+        type Ctx = ();
+
+        fn start(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
+            self.started += 1;
+            NalFlow::Continue
+        }
+
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
+            match buf.first() {
+                Some(b) => {
+                    self.data.push(*b);
+                    NalFlow::Skip
+                },
+                None => NalFlow::Continue,
+            }
+        }
+
+        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
+            self.ended += 1;
+            if self.ended == 2 {
+                NalFlow::Abort
+            } else {
+                NalFlow::Continue
+            }
         }
     }
 
@@ -346,6 +473,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn skip_and_abort() {
+        let mut r = AnnexBReader::new(SkippingReader::default());
+        let data = hex!(
+            "00 00 00 01 67 AA BB
+             00 00 01 68 CC
+             00 00 01 65 DD");
+        let mut ctx = Context::default();
+        r.start(&mut ctx);
+        let mut flows = Vec::new();
+        for b in data.iter() {
+            flows.push(r.push(&mut ctx, &[*b]));
+        }
+        // the second NAL unit's end() returns Abort once the third NAL unit's start code is seen
+        assert_eq!(flows.iter().position(|f| *f == NalFlow::Abort), Some(14));
+        assert!(flows[14..].iter().all(|f| *f == NalFlow::Abort));
+        assert_eq!(r.end_units(&mut ctx), NalFlow::Abort);
+        assert_eq!(r.nal_reader.started, 2);
+        assert_eq!(r.nal_reader.ended, 2);
+        assert_eq!(&r.nal_reader.data[..], &[0x67, 0x68][..]);
+
+        // start() allows reading to resume
+        r.start(&mut ctx);
+        assert_eq!(r.push(&mut ctx, &data[..4]), NalFlow::Continue);
+        assert_eq!(r.nal_reader.started, 3);
+    }
+
     #[test]
     fn short_start_code() {
         let state = Rc::new(RefCell::new(State {
@@ -400,6 +554,80 @@ mod tests {
         }
     }
 
+    // trailing_zero_8bits followed by a four byte start code, as is typical before an access unit
+    #[test]
+    fn trailing_zeros_before_four_byte_start_code() {
+        let state = Rc::new(RefCell::new(State {
+            started: 0,
+            ended: 0,
+            data: Vec::new(),
+        }));
+        let mock = MockReader::new(Rc::clone(&state));
+        let mut r = AnnexBReader::new(mock);
+        let data = vec!(
+            0, 0, 0, 1,  // start-code
+            3,           // NAL data
+            0x80,        // 1 stop-bit + 7 alignment-zero-bits
+            0,           // trailing_zero_8bits
+            0, 0, 0, 1,  // start-code (including zero_byte)
+            4,           // NAL data
+            0x80,        // 1 stop-bit + 7 alignment-zero-bits
+        );
+        let mut ctx = Context::default();
+        r.start(&mut ctx);
+        assert_eq!(r.push(&mut ctx, &data[..]), NalFlow::Continue);
+        r.end_units(&mut ctx);
+        let s = state.borrow();
+        assert_eq!(2, s.started);
+        assert_eq!(2, s.ended);
+        assert_eq!(&s.data[..], &[3, 0x80, 0, 4, 0x80][..]);
+    }
+
+    /// Records the byte stream length of each NAL unit
+    #[derive(Default)]
+    struct LenReader {
+        lens: Vec<u64>,
+    }
+    impl NalReader for LenReader {
+        type Ctx = ();
+
+        fn start(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
+            NalFlow::Continue
+        }
+
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, _buf: &[u8]) -> NalFlow {
+            NalFlow::Continue
+        }
+
+        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
+            NalFlow::Continue
+        }
+
+        fn byte_stream_len(&mut self, _ctx: &mut Context<Self::Ctx>, len: u64) {
+            self.lens.push(len);
+        }
+    }
+
+    #[test]
+    fn byte_stream_len() {
+        let data = hex!(
+            "00 00 00 00 01 03 80 00
+             00 00 00 01 04 80
+             00 00 01 05 80 00 00");
+        for chunk_size in 1..data.len() {
+            let mut r = AnnexBReader::new(LenReader::default());
+            let mut ctx = Context::default();
+            r.start(&mut ctx);
+            for chunk in data.chunks(chunk_size) {
+                r.push(&mut ctx, chunk);
+            }
+            r.end_units(&mut ctx);
+            // the first includes a leading_zero_8bits and a trailing_zero_8bits, the second the
+            // zero_byte of its start code, and the third two trailing_zero_8bits
+            assert_eq!(r.into_nal_reader().lens, vec![8, 6, 7], "chunk_size={}", chunk_size);
+        }
+    }
+
     #[test]
     fn implicit_end() {
         let state = Rc::new(RefCell::new(State {
@@ -2,11 +2,11 @@
 //! File Format_ (AKA MP4), as the specified in _ISO/IEC 14496-15_.
 //!
 
-use crate::nal::{sps, UnitType, NalHeader, NalHeaderError, pps, NalHandler};
+use crate::nal::{sps, UnitType, NalHeader, NalHeaderError, pps};
 use std::convert::TryFrom;
-use crate::nal::sps::{ProfileIdc, Level, ConstraintFlags, SeqParameterSet, SeqParameterSetNalHandler};
+use crate::nal::sps::{ProfileIdc, Level, ConstraintFlags, SeqParameterSet};
 use crate::Context;
-use crate::nal::pps::PicParameterSetNalHandler;
+use crate::nal::pps::PicParameterSet;
 use crate::rbsp;
 
 #[derive(Debug)]
@@ -37,7 +37,8 @@ impl<'buf> TryFrom<&'buf[u8]> for AvcDecoderConfigurationRecord<'buf> {
         // Do a whole load of work to ensure that the buffer is large enough for all the optional
         // fields actually indicated to be present, so that we don't have to put these checks into
         // the accessor functions of individual fields,
-        let mut len = avcc.seq_param_sets_end()?;
+        let mut len = avcc.seq_param_sets_end()
+            .map_err(|expected| AvccError::NotEnoughData { expected, actual: data.len() })?;
 
         avcc.ck(len + 1)?;
         let mut num_pps = data[len];
@@ -58,14 +59,21 @@ impl<'buf> TryFrom<&'buf[u8]> for AvcDecoderConfigurationRecord<'buf> {
 impl<'buf> AvcDecoderConfigurationRecord<'buf> {
     const MIN_CONF_SIZE: usize = 6;
 
-    fn seq_param_sets_end(&self) -> Result<usize, AvccError> {
+    /// The offset following the last _sequence parameter set_, or an `Err` giving the length of
+    /// data needed if the buffer is truncated
+    fn seq_param_sets_end(&self) -> Result<usize, usize> {
+        let ck = |len: usize| if self.data.len() < len {
+            Err(len)
+        } else {
+            Ok(())
+        };
         let mut num_sps = self.num_of_sequence_parameter_sets();
         let mut len = Self::MIN_CONF_SIZE;
         while num_sps > 0 {
-            self.ck(len + 2)?;
+            ck(len + 2)?;
             let sps_len = (u16::from(self.data[len]) << 8 | u16::from(self.data[len + 1])) as usize;
             len += 2;
-            self.ck(len + sps_len)?;
+            ck(len + sps_len)?;
             len += sps_len;
             num_sps -= 1;
         }
@@ -91,7 +99,7 @@ impl<'buf> AvcDecoderConfigurationRecord<'buf> {
         self.data[2].into()
     }
     pub fn avc_level_indication(&self) -> Level {
-        Level::from_constraint_flags_and_level_idc(self.profile_compatibility(), self.data[3])
+        Level::from_profile_constraint_flags_and_level_idc(self.avc_profile_indication(), self.profile_compatibility(), self.data[3])
     }
     /// Number of bytes used to specify the length of each NAL unit
     /// 0 => 1 byte, 1 => 2 bytes, 2 => 3 bytes, 3 => 4 bytes
@@ -99,35 +107,50 @@ impl<'buf> AvcDecoderConfigurationRecord<'buf> {
         self.data[4] & 0b0000_0011
     }
     pub fn sequence_parameter_sets(&self) -> impl Iterator<Item = Result<&'buf[u8], ParamSetError>> {
+        self.sequence_parameter_set_units(false)
+    }
+    pub fn picture_parameter_sets(&self) -> impl Iterator<Item = Result<&'buf[u8], ParamSetError>> + 'buf {
+        self.picture_parameter_set_units(false)
+    }
+    fn sequence_parameter_set_units(&self, include_header: bool) -> impl Iterator<Item = Result<&'buf[u8], ParamSetError>> {
         let num = self.num_of_sequence_parameter_sets();
         let data = &self.data[Self::MIN_CONF_SIZE..];
-        ParamSetIter::new(data, UnitType::SeqParameterSet)
+        ParamSetIter::new(data, UnitType::SeqParameterSet, include_header)
             .take(num)
     }
-    pub fn picture_parameter_sets(&self) -> impl Iterator<Item = Result<&'buf[u8], ParamSetError>> + 'buf {
-        let offset = self.seq_param_sets_end().unwrap();
-        let num = self.data[offset];
-        let data = &self.data[offset+1..];
-        ParamSetIter::new(data, UnitType::PicParameterSet)
-            .take(num as usize)
+    fn picture_parameter_set_units(&self, include_header: bool) -> impl Iterator<Item = Result<&'buf[u8], ParamSetError>> + 'buf {
+        // try_from() already checked that all the data is present, so this should not fail
+        let pps_list = self.seq_param_sets_end().and_then(|offset| match self.data.get(offset) {
+            Some(&num) => Ok((num, &self.data[offset+1..])),
+            None => Err(offset + 1),
+        });
+        let (units, err) = match pps_list {
+            Ok((num, data)) => (Some(ParamSetIter::new(data, UnitType::PicParameterSet, include_header).take(num as usize)), None),
+            Err(expected) => (None, Some(Err(ParamSetError::NotEnoughData { expected, actual: self.data.len() }))),
+        };
+        units.into_iter().flatten().chain(err)
     }
 
     /// Creates an H264 parser context from the given user context, using the settings encoded into
     /// this `AvcDecoderConfigurationRecord`.
     ///
     /// In particular, the _sequence parameter set_ and _picture parameter set_ values of this
-    /// configuration record will be inserted into the resulting context.
+    /// configuration record will be inserted into the resulting context (along with the NAL units
+    /// they were parsed from, see `Context::sps_nal_by_id()`).  An error is returned if any of
+    /// them can't be parsed.
     pub fn create_context<C>(&self, ctx: C) -> Result<Context<C>, AvccError> {
         let mut ctx = Context::new(ctx);
-        let mut sps_decode = rbsp::RbspDecoder::new(SeqParameterSetNalHandler::default());
-        for sps in self.sequence_parameter_sets() {
-            sps_decode.push(&mut ctx, sps.map_err(AvccError::ParamSet)?);
-            sps_decode.end(&mut ctx);
+        for sps in self.sequence_parameter_set_units(true) {
+            let nal = sps.map_err(AvccError::ParamSet)?;
+            let sps = SeqParameterSet::from_bytes(&rbsp::decode_nal(&nal[1..]))
+                .map_err(AvccError::Sps)?;
+            ctx.put_seq_param_set_with_nal(sps, nal);
         }
-        let mut pps_decode = rbsp::RbspDecoder::new(PicParameterSetNalHandler::default());
-        for pps in self.picture_parameter_sets() {
-            pps_decode.push(&mut ctx, pps.map_err(AvccError::ParamSet)?);
-            pps_decode.end(&mut ctx);
+        for pps in self.picture_parameter_set_units(true) {
+            let nal = pps.map_err(AvccError::ParamSet)?;
+            let pps = PicParameterSet::from_bytes(&ctx, &rbsp::decode_nal(&nal[1..]))
+                .map_err(AvccError::Pps)?;
+            ctx.put_pic_param_set_with_nal(pps, nal);
         }
         Ok(ctx)
     }
@@ -135,6 +158,9 @@ impl<'buf> AvcDecoderConfigurationRecord<'buf> {
 
 #[derive(Debug)]
 pub enum ParamSetError {
+    /// The parameter set's length prefix, or the data it indicated, was truncated (an empty
+    /// parameter set, lacking even a NAL header byte, is also reported this way)
+    NotEnoughData { expected: usize, actual: usize },
     NalHeader(NalHeaderError),
     IncorrectNalType { expected: UnitType, actual: UnitType },
     /// A _sequence parameter set_ found within the AVC decoder config was not consistent with the
@@ -142,11 +168,13 @@ pub enum ParamSetError {
     IncompatibleSps(SeqParameterSet),
 }
 
-struct ParamSetIter<'buf>(&'buf[u8], UnitType);
+/// Iterates over the length-prefixed parameter sets of the given type, optionally including the
+/// NAL header byte of each
+struct ParamSetIter<'buf>(&'buf[u8], UnitType, bool);
 
 impl<'buf> ParamSetIter<'buf> {
-    pub fn new(buf: &'buf[u8], unit_type: UnitType) -> ParamSetIter<'buf> {
-        ParamSetIter(buf, unit_type)
+    pub fn new(buf: &'buf[u8], unit_type: UnitType, include_header: bool) -> ParamSetIter<'buf> {
+        ParamSetIter(buf, unit_type, include_header)
     }
 }
 impl<'buf> Iterator for ParamSetIter<'buf>
@@ -156,15 +184,31 @@ impl<'buf> Iterator for ParamSetIter<'buf>
     fn next(&mut self) -> Option<Self::Item> {
         if self.0.is_empty() {
             None
+        } else if self.0.len() < 2 {
+            let actual = self.0.len();
+            self.0 = &[];
+            Some(Err(ParamSetError::NotEnoughData { expected: 2, actual }))
         } else {
-            let len = u16::from(self.0[0]) << 8 | u16::from(self.0[1]);
+            let len = usize::from(self.0[0]) << 8 | usize::from(self.0[1]);
             let data = &self.0[2..];
+            if len == 0 {
+                self.0 = data;
+                return Some(Err(ParamSetError::NotEnoughData { expected: 1, actual: 0 }));
+            }
+            if data.len() < len {
+                self.0 = &[];
+                return Some(Err(ParamSetError::NotEnoughData { expected: len, actual: data.len() }));
+            }
+            let (data, remainder) = data.split_at(len);
+            self.0 = remainder;
             let res = match NalHeader::new(data[0]) {
                 Ok(nal_header) => {
                     if nal_header.nal_unit_type() == self.1 {
-                        let (data, remainder) = data.split_at(len as usize);
-                        self.0 = remainder;
-                        Ok(&data[1..])  // trim off the nal_header byte
+                        if self.2 {
+                            Ok(data)
+                        } else {
+                            Ok(&data[1..])  // trim off the nal_header byte
+                        }
                     } else {
                         Err(ParamSetError::IncorrectNalType { expected: self.1, actual: nal_header.nal_unit_type() })
                     }
@@ -179,7 +223,8 @@ impl<'buf> Iterator for ParamSetIter<'buf>
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::nal::pps::ParamSetId;
+    use crate::nal::pps::PicParamSetId;
+    use crate::nal::sps::SeqParamSetId;
     use hex_literal::*;
 
     #[test]
@@ -197,12 +242,12 @@ mod test {
         assert!(!flags.flag4());
         assert!(!flags.flag5());
         let ctx = avcc.create_context(()).unwrap();
-        let sps = ctx.sps_by_id(ParamSetId::from_u32(0).unwrap())
+        let sps = ctx.sps_by_id(SeqParamSetId::from_u32(0).unwrap())
             .expect("missing sps");
         assert_eq!(avcc.avc_level_indication(), sps.level());
         assert_eq!(avcc.avc_profile_indication(), sps.profile_idc);
-        assert_eq!(ParamSetId::from_u32(0).unwrap(), sps.seq_parameter_set_id);
-        let _pps = ctx.pps_by_id(ParamSetId::from_u32(0).unwrap())
+        assert_eq!(SeqParamSetId::from_u32(0).unwrap(), sps.seq_parameter_set_id);
+        let _pps = ctx.pps_by_id(PicParamSetId::from_u32(0).unwrap())
             .expect("missing pps");
     }
     #[test]
@@ -212,9 +257,23 @@ mod test {
                               ff350101 01400000 fa000003 01f40101
                               000468ee 3c80");
         let avcc = AvcDecoderConfigurationRecord::try_from(&avcc_data[..]).unwrap();
-        let _sps_data = avcc.sequence_parameter_sets().next().unwrap().unwrap();
+        let sps_data = avcc.sequence_parameter_sets().next().unwrap().unwrap();
         let ctx = avcc.create_context(()).unwrap();
-        let _sps = ctx.sps_by_id(ParamSetId::from_u32(0).unwrap())
+        let _sps = ctx.sps_by_id(SeqParamSetId::from_u32(0).unwrap())
             .expect("missing sps");
+        // the original NAL units are retained, including emulation prevention bytes
+        assert_eq!(ctx.sps_nal_by_id(SeqParamSetId::from_u32(0).unwrap()).map(|n| &n[1..]), Some(sps_data));
+        assert_eq!(ctx.pps_nal_by_id(PicParamSetId::from_u32(0).unwrap()), Some(&hex!("68ee3c80")[..]));
+    }
+    #[test]
+    fn empty_param_set() {
+        // one SPS, of length zero, and one PPS
+        let avcc_data = hex!("0142c01e ffe10000 01000468 de3c80");
+        let avcc = AvcDecoderConfigurationRecord::try_from(&avcc_data[..]).unwrap();
+        assert!(matches!(
+            avcc.sequence_parameter_sets().next(),
+            Some(Err(ParamSetError::NotEnoughData { expected: 1, actual: 0 }))
+        ));
+        assert!(matches!(avcc.create_context(()), Err(AvccError::ParamSet(_))));
     }
 }
\ No newline at end of file
@@ -0,0 +1,663 @@
+//! Checking of stream timing against the _hypothetical reference decoder_ (HRD) of _Annex C_.
+//!
+//! `HrdVerifier` follows the arrival of each access unit into the _coded picture buffer_ (CPB),
+//! its removal for decoding, and the output of the decoded picture from the _decoded picture
+//! buffer_ (DPB), using the timing given by `buffering_period` and `pic_timing` SEI messages.
+//! Violations of the buffer constraints are collected, so that a whole stream can be checked
+//! offline.
+//!
+//! Only pictures waiting for output are counted against the DPB size; decoded pictures which are
+//! retained only for reference are not tracked, since that would require processing of the
+//! reference picture marking of every slice.
+
+use std::collections::VecDeque;
+use crate::Context;
+use crate::access_unit::AccessUnit;
+use crate::nal::{NalHeader, UnitType};
+use crate::nal::sei::{HeaderType, SeiMessage, SeiMessageError};
+use crate::nal::sei::buffering_period::{BufferingPeriod, BufferingPeriodError, InitialCpbRemoval};
+use crate::nal::sei::pic_timing::{Delays, PicTiming, PicTimingError};
+use crate::nal::slice::{SliceHeader, SliceHeaderError};
+use crate::nal::sps::SeqParameterSet;
+use crate::rbsp::{self, RbspBitReader};
+
+/// Timing comparisons allow for this much rounding error, in seconds
+const EPSILON: f64 = 1e-9;
+
+/// The frequency of the clock in which initial CPB removal delays are given
+const INITIAL_DELAY_CLOCK: f64 = 90000.0;
+
+/// Selects which of the two HRD conformance points is checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HrdType {
+    /// _Type II_ conformance, counting every byte of the Annex B byte stream, using the
+    /// `nal_hrd_parameters`
+    Nal,
+    /// _Type I_ conformance, counting only VCL and filler data NAL units, using the
+    /// `vcl_hrd_parameters`
+    Vcl,
+}
+
+#[derive(Debug)]
+pub enum HrdError {
+    /// The SPS has no HRD parameters of the requested type
+    NoHrdParameters(HrdType),
+    /// The SPS has no `timing_info`, or gives a zero `num_units_in_tick` or `time_scale`
+    NoTimingInfo,
+    /// The requested `SchedSelIdx` is not less than `cpb_cnt_minus1 + 1`
+    SchedSelIdxOutOfRange { sched_sel_idx: usize, cpb_cnt: usize },
+    /// The first access unit had no `buffering_period` message, or a `buffering_period` message
+    /// had no initial CPB removal delay for the selected HRD type and `SchedSelIdx`
+    MissingBufferingPeriod { access_unit: usize },
+    /// An access unit had no `pic_timing` message giving the CPB and DPB delays
+    MissingPicTiming { access_unit: usize },
+    BufferingPeriod(BufferingPeriodError),
+    PicTiming(PicTimingError),
+    Sei(SeiMessageError),
+    Slice(SliceHeaderError),
+}
+impl From<BufferingPeriodError> for HrdError {
+    fn from(e: BufferingPeriodError) -> Self {
+        HrdError::BufferingPeriod(e)
+    }
+}
+impl From<PicTimingError> for HrdError {
+    fn from(e: PicTimingError) -> Self {
+        HrdError::PicTiming(e)
+    }
+}
+impl From<SeiMessageError> for HrdError {
+    fn from(e: SeiMessageError) -> Self {
+        HrdError::Sei(e)
+    }
+}
+impl From<SliceHeaderError> for HrdError {
+    fn from(e: SliceHeaderError) -> Self {
+        HrdError::Slice(e)
+    }
+}
+
+/// A breach of the buffer constraints of _Annex C_.  Access units are identified by their index
+/// in the stream, counting from zero.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HrdViolation {
+    /// The last bit of the access unit arrived in the CPB after the time at which it should have
+    /// been removed for decoding
+    CpbUnderflow { access_unit: usize, final_arrival: f64, nominal_removal: f64 },
+    /// Immediately before the removal of the access unit, the CPB held more bits than its size
+    CpbOverflow { access_unit: usize, fullness: f64, cpb_size: f64 },
+    /// When the access unit was decoded, more pictures were waiting for output than the DPB
+    /// could hold
+    DpbOverflow { access_unit: usize, pictures: u32, max: u32 },
+}
+
+/// The times calculated for one access unit, in seconds from the arrival of the first bit of the
+/// stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessUnitTiming {
+    /// the index of the access unit within the stream, counting from zero
+    pub index: usize,
+    /// the size of the access unit, counted as required by the `HrdType`
+    pub size_bits: u64,
+    /// the time at which the first bit of the access unit enters the CPB, _t<sub>ai</sub>_
+    pub initial_arrival: f64,
+    /// the time at which the last bit of the access unit enters the CPB, _t<sub>af</sub>_
+    pub final_arrival: f64,
+    /// the time at which the access unit should be removed from the CPB, _t<sub>r,n</sub>_
+    pub nominal_removal: f64,
+    /// the time at which the access unit is actually removed from the CPB and decoded,
+    /// _t<sub>r</sub>_, which is later than the nominal time only for low-delay streams
+    pub removal: f64,
+    /// the time at which the decoded picture is output from the DPB, _t<sub>o,dpb</sub>_
+    pub output: f64,
+}
+
+/// An access unit which is in the CPB, or still arriving into it
+#[derive(Debug)]
+struct CpbEntry {
+    index: usize,
+    size_bits: f64,
+    initial_arrival: f64,
+    final_arrival: f64,
+    removal: f64,
+}
+impl CpbEntry {
+    /// the number of bits of this access unit which have arrived at time `t`
+    fn arrived_bits(&self, t: f64, bit_rate: f64) -> f64 {
+        if t >= self.final_arrival {
+            self.size_bits
+        } else if t > self.initial_arrival {
+            (t - self.initial_arrival) * bit_rate
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Simulates the HRD for one SPS, HRD type and `SchedSelIdx`.
+///
+/// Access units are supplied in decoding order, either with `push_access_unit()` (or
+/// `push_access_unit_with_size()`), which extracts the timing SEI messages from an `AccessUnit`,
+/// or with `access_unit()`, for callers which have already done so.  Since CPB overflow can only
+/// be detected once the arrival of later access units is known, `finish()` must be called after
+/// the final access unit, before the `violations()` are examined.
+#[derive(Debug)]
+pub struct HrdVerifier {
+    hrd_type: HrdType,
+    sched_sel_idx: usize,
+    /// the clock tick, _t<sub>c</sub>_
+    clock_tick: f64,
+    bit_rate: f64,
+    cpb_size: f64,
+    cbr: bool,
+    low_delay: bool,
+    max_dec_frame_buffering: Option<u32>,
+
+    next_index: usize,
+    initial_cpb_removal: Option<InitialCpbRemoval>,
+    /// the nominal removal time of the access unit which started the current buffering period
+    buffering_period_removal: f64,
+    prev_final_arrival: f64,
+    cpb: VecDeque<CpbEntry>,
+    /// output times of the decoded pictures waiting in the DPB
+    dpb: Vec<f64>,
+    violations: Vec<HrdViolation>,
+}
+impl HrdVerifier {
+    pub fn new(sps: &SeqParameterSet, hrd_type: HrdType, sched_sel_idx: usize) -> Result<HrdVerifier, HrdError> {
+        let vui = sps.vui_parameters.as_ref().ok_or(HrdError::NoHrdParameters(hrd_type))?;
+        let hrd = match hrd_type {
+            HrdType::Nal => vui.nal_hrd_parameters.as_ref(),
+            HrdType::Vcl => vui.vcl_hrd_parameters.as_ref(),
+        }.ok_or(HrdError::NoHrdParameters(hrd_type))?;
+        let timing = vui.timing_info.as_ref()
+            .filter(|t| t.num_units_in_tick > 0 && t.time_scale > 0)
+            .ok_or(HrdError::NoTimingInfo)?;
+        let out_of_range = HrdError::SchedSelIdxOutOfRange { sched_sel_idx, cpb_cnt: hrd.cpb_specs.len() };
+        let (spec, bit_rate, cpb_size) = match (hrd.cpb_specs.get(sched_sel_idx), hrd.bit_rate(sched_sel_idx), hrd.cpb_size(sched_sel_idx)) {
+            (Some(spec), Some(bit_rate), Some(cpb_size)) => (spec, bit_rate, cpb_size),
+            _ => return Err(out_of_range),
+        };
+        Ok(HrdVerifier {
+            hrd_type,
+            sched_sel_idx,
+            clock_tick: f64::from(timing.num_units_in_tick) / f64::from(timing.time_scale),
+            bit_rate: bit_rate as f64,
+            cpb_size: cpb_size as f64,
+            cbr: spec.cbr_flag,
+            low_delay: vui.low_delay_hrd_flag.unwrap_or(false),
+            max_dec_frame_buffering: sps.max_dec_frame_buffering(),
+            next_index: 0,
+            initial_cpb_removal: None,
+            buffering_period_removal: 0.0,
+            prev_final_arrival: 0.0,
+            cpb: VecDeque::new(),
+            dpb: Vec::new(),
+            violations: Vec::new(),
+        })
+    }
+
+    /// Extracts the timing SEI messages of the given access unit, and passes them to
+    /// `access_unit()` along with the access unit's size.  For `HrdType::Nal`, this is the
+    /// `byte_stream_len` recorded by `AccessUnitReader` when reading from an `AnnexBReader`;
+    /// otherwise the size is estimated (see `counted_bytes()`), and where the exact size is known,
+    /// `push_access_unit_with_size()` should be used instead.
+    ///
+    /// The `Context` must hold the parameter sets that the access unit refers to.
+    pub fn push_access_unit<Ctx>(&mut self, ctx: &mut Context<Ctx>, access_unit: &AccessUnit) -> Result<AccessUnitTiming, HrdError> {
+        if let (HrdType::Nal, Some(len)) = (self.hrd_type, access_unit.byte_stream_len) {
+            return self.push_access_unit_with_size(ctx, access_unit, 8 * len);
+        }
+        let size_bytes: usize = access_unit.nal_units.iter()
+            .enumerate()
+            .filter_map(|(i, nal)| {
+                let header = nal.first().and_then(|&b| NalHeader::new(b).ok())?;
+                Some(self.counted_bytes(i, header.nal_unit_type(), nal.len()))
+            })
+            .sum();
+        self.push_access_unit_with_size(ctx, access_unit, 8 * size_bytes as u64)
+    }
+
+    /// Like `push_access_unit()`, but using the given size for the access unit, as measured by the
+    /// caller.  For `HrdType::Vcl` this must count only the VCL and filler data NAL units; for
+    /// `HrdType::Nal` it must count all the bytes of the byte stream belonging to the access unit,
+    /// including start code prefixes, `zero_byte`s, and any `leading_zero_8bits` and
+    /// `trailing_zero_8bits`.
+    ///
+    /// The first slice is parsed, and its SPS activated, before the SEI messages are read, since
+    /// the active SPS determines the syntax of the `pic_timing` message.
+    pub fn push_access_unit_with_size<Ctx>(&mut self, ctx: &mut Context<Ctx>, access_unit: &AccessUnit, size_bits: u64) -> Result<AccessUnitTiming, HrdError> {
+        let nal_units = access_unit.nal_units.iter()
+            .filter_map(|nal| nal.first().and_then(|&b| NalHeader::new(b).ok()).map(|header| (header, nal)));
+        let first_slice = nal_units.clone().find(|(header, _)| matches!(header.nal_unit_type(),
+            UnitType::SliceLayerWithoutPartitioningNonIdr
+            | UnitType::SliceDataPartitionALayer
+            | UnitType::SliceLayerWithoutPartitioningIdr
+        ));
+        if let Some((header, nal)) = first_slice {
+            let rbsp = rbsp::decode_nal(&nal[1..]);
+            let mut r = RbspBitReader::new(&rbsp[..]);
+            let (_, sps, _) = SliceHeader::read(ctx, &mut r, header)?;
+            let sps_id = sps.seq_parameter_set_id;
+            ctx.activate_sps(sps_id);
+        }
+        let mut buffering_period = None;
+        let mut delays = None;
+        for (_, nal) in nal_units.filter(|(header, _)| header.nal_unit_type() == UnitType::SEI) {
+            for msg in SeiMessage::read_all(&rbsp::decode_nal(&nal[1..]))?.messages {
+                match msg.payload_type {
+                    HeaderType::BufferingPeriod if buffering_period.is_none() => {
+                        buffering_period = Some(BufferingPeriod::read(ctx, &msg.payload)?);
+                    },
+                    HeaderType::PicTiming if delays.is_none() => {
+                        delays = PicTiming::read(ctx, &msg.payload)?.delays;
+                    },
+                    _ => (),
+                }
+            }
+        }
+        let index = self.next_index;
+        let initial_cpb_removal = match buffering_period {
+            Some(bp) => {
+                let list = match self.hrd_type {
+                    HrdType::Nal => bp.nal_hrd_bp(),
+                    HrdType::Vcl => bp.vcl_hrd_bp(),
+                };
+                let initial = list.and_then(|l| l.get(self.sched_sel_idx))
+                    .ok_or(HrdError::MissingBufferingPeriod { access_unit: index })?;
+                Some(*initial)
+            },
+            None => None,
+        };
+        let delays = delays.ok_or(HrdError::MissingPicTiming { access_unit: index })?;
+        self.access_unit(size_bits, initial_cpb_removal, delays)
+    }
+
+    /// An estimate of the number of bytes of the given NAL unit which are counted for this HRD
+    /// type.  For _Type II_ conformance, where the Annex B byte stream framing is not known from
+    /// the `AccessUnit`, it is assumed that each NAL unit has a 3-byte start code prefix, preceded
+    /// by a `zero_byte` only where one is required (before the first NAL unit of an access unit,
+    /// and before parameter sets), and that there are no `leading_zero_8bits` or
+    /// `trailing_zero_8bits` other than any included in the NAL unit data itself.  The VCL HRD
+    /// counts only NAL unit data, so is exact unless that data includes zero bytes which followed
+    /// the NAL unit in the byte stream.
+    fn counted_bytes(&self, index_in_access_unit: usize, unit_type: UnitType, len: usize) -> usize {
+        match self.hrd_type {
+            HrdType::Vcl => match unit_type {
+                UnitType::SliceLayerWithoutPartitioningNonIdr
+                | UnitType::SliceDataPartitionALayer
+                | UnitType::SliceDataPartitionBLayer
+                | UnitType::SliceDataPartitionCLayer
+                | UnitType::SliceLayerWithoutPartitioningIdr
+                | UnitType::FillerData => len,
+                _ => 0,
+            },
+            HrdType::Nal => {
+                let zero_byte = index_in_access_unit == 0
+                    || unit_type == UnitType::SeqParameterSet
+                    || unit_type == UnitType::PicParameterSet;
+                len + 3 + if zero_byte { 1 } else { 0 }
+            },
+        }
+    }
+
+    /// Adds the next access unit in decoding order, of the given size, which is either the first
+    /// of a new buffering period (if `initial_cpb_removal` is given, from the access unit's
+    /// `buffering_period` message), or continues the current one.
+    pub fn access_unit(&mut self, size_bits: u64, initial_cpb_removal: Option<InitialCpbRemoval>, delays: Delays) -> Result<AccessUnitTiming, HrdError> {
+        let index = self.next_index;
+        let tc = self.clock_tick;
+        let initial = initial_cpb_removal.or(self.initial_cpb_removal)
+            .ok_or(HrdError::MissingBufferingPeriod { access_unit: index })?;
+        let nominal_removal = if index == 0 {
+            f64::from(initial.initial_cpb_removal_delay()) / INITIAL_DELAY_CLOCK
+        } else {
+            self.buffering_period_removal + tc * f64::from(delays.cpb_removal_delay)
+        };
+        let starts_buffering_period = initial_cpb_removal.is_some();
+        if starts_buffering_period {
+            self.buffering_period_removal = nominal_removal;
+            self.initial_cpb_removal = Some(initial);
+        }
+
+        let initial_arrival = if index == 0 || self.cbr {
+            self.prev_final_arrival
+        } else {
+            let mut earliest_delay = f64::from(initial.initial_cpb_removal_delay());
+            if !starts_buffering_period {
+                earliest_delay += f64::from(initial.initial_cpb_removal_delay_offset());
+            }
+            let earliest = nominal_removal - earliest_delay / INITIAL_DELAY_CLOCK;
+            self.prev_final_arrival.max(earliest)
+        };
+        let final_arrival = initial_arrival + size_bits as f64 / self.bit_rate;
+        self.prev_final_arrival = final_arrival;
+
+        let late = final_arrival > nominal_removal + EPSILON;
+        let removal = if late && self.low_delay {
+            nominal_removal + tc * ((final_arrival - nominal_removal) / tc - EPSILON).ceil()
+        } else {
+            nominal_removal
+        };
+        if late && !self.low_delay {
+            self.violations.push(HrdViolation::CpbUnderflow { access_unit: index, final_arrival, nominal_removal });
+        }
+        let output = removal + tc * f64::from(delays.dpb_output_delay);
+
+        // access units due for removal before this one started arriving have now seen all the
+        // bits that will arrive before their removal
+        while self.cpb.front().map(|e| e.removal <= initial_arrival).unwrap_or(false) {
+            self.remove_from_cpb();
+        }
+        self.cpb.push_back(CpbEntry {
+            index,
+            size_bits: size_bits as f64,
+            initial_arrival,
+            final_arrival,
+            removal,
+        });
+        self.decode(index, removal, output);
+        self.next_index += 1;
+
+        Ok(AccessUnitTiming {
+            index,
+            size_bits,
+            initial_arrival,
+            final_arrival,
+            nominal_removal,
+            removal,
+            output,
+        })
+    }
+
+    /// checks the CPB fullness immediately before the removal of the oldest access unit, then
+    /// removes it
+    fn remove_from_cpb(&mut self) {
+        let entry = match self.cpb.front() {
+            Some(entry) => entry,
+            None => return,
+        };
+        let fullness: f64 = self.cpb.iter()
+            .map(|e| e.arrived_bits(entry.removal, self.bit_rate))
+            .sum();
+        // allow for rounding of the arrival of the final fraction of a bit
+        if fullness > self.cpb_size + 0.5 {
+            self.violations.push(HrdViolation::CpbOverflow { access_unit: entry.index, fullness, cpb_size: self.cpb_size });
+        }
+        self.cpb.pop_front();
+    }
+
+    /// updates the DPB for the decoding of an access unit at time `removal`
+    fn decode(&mut self, index: usize, removal: f64, output: f64) {
+        self.dpb.retain(|&t| t > removal + EPSILON);
+        if output > removal + EPSILON {
+            self.dpb.push(output);
+        }
+        if let Some(max) = self.max_dec_frame_buffering {
+            let pictures = self.dpb.len() as u32;
+            if pictures > max {
+                self.violations.push(HrdViolation::DpbOverflow { access_unit: index, pictures, max });
+            }
+        }
+    }
+
+    /// Completes the checks of any access units remaining in the CPB.  To be called after the
+    /// final access unit of the stream.
+    pub fn finish(&mut self) {
+        while !self.cpb.is_empty() {
+            self.remove_from_cpb();
+        }
+    }
+
+    /// The violations found so far, in the order they were detected
+    pub fn violations(&self) -> &[HrdViolation] {
+        &self.violations
+    }
+
+    /// `BitRate[SchedSelIdx]`, in bits per second
+    pub fn bit_rate_bps(&self) -> f64 {
+        self.bit_rate
+    }
+
+    /// `CpbSize[SchedSelIdx]`, in bits
+    pub fn cpb_size_bits(&self) -> f64 {
+        self.cpb_size
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nal::pps::PicParameterSet;
+    use crate::nal::sps::SeqParamSetId;
+    use crate::nal::sps::test::{HRD_SPS, VCL_HRD_SPS};
+    use crate::access_unit::{AccessUnitHandler, AccessUnitReader};
+    use crate::annexb::AnnexBReader;
+    use hex_literal::*;
+
+    fn sps() -> SeqParameterSet {
+        SeqParameterSet::from_bytes(&HRD_SPS).unwrap()
+    }
+
+    /// feeds access units of the given sizes, decoded every two ticks and output `output_delay`
+    /// ticks after decoding
+    fn run(sps: &SeqParameterSet, initial_delay: u32, sizes: &[u64], output_delay: u32) -> (Vec<AccessUnitTiming>, HrdVerifier) {
+        let mut hrd = HrdVerifier::new(sps, HrdType::Vcl, 0).unwrap();
+        let timings = sizes.iter().enumerate().map(|(i, &size)| {
+            let initial = if i == 0 { Some(InitialCpbRemoval::new(initial_delay, 0)) } else { None };
+            let delays = Delays { cpb_removal_delay: 2 * i as u32, dpb_output_delay: output_delay };
+            hrd.access_unit(size, initial, delays).unwrap()
+        }).collect();
+        hrd.finish();
+        (timings, hrd)
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn conforming() {
+        let sps = sps();
+        let (timings, hrd) = run(&sps, 18000, &[2560; 20], 2);
+        assert_eq!(hrd.bit_rate_bps(), 64000.0);
+        assert_eq!(hrd.cpb_size_bits(), 16000.0);
+        assert_eq!(hrd.violations(), &[]);
+        assert!(close(timings[0].final_arrival, 0.04));
+        assert!(close(timings[0].removal, 0.2));
+        assert!(close(timings[0].output, 0.24));
+        // VBR arrival starts no earlier than the initial delay before removal
+        assert!(close(timings[5].initial_arrival, 0.2));
+        assert!(close(timings[5].removal, 0.4));
+    }
+
+    #[test]
+    fn underflow() {
+        let sps = sps();
+        let mut sizes = [2560; 10];
+        sizes[2] = 20000;
+        let (timings, hrd) = run(&sps, 18000, &sizes, 2);
+        assert!(matches!(hrd.violations().first(), Some(HrdViolation::CpbUnderflow { access_unit: 2, .. })));
+        assert_eq!(timings[2].removal, timings[2].nominal_removal);
+    }
+
+    #[test]
+    fn low_delay() {
+        let mut sps = sps();
+        sps.vui_parameters.as_mut().unwrap().low_delay_hrd_flag = Some(true);
+        let mut sizes = [2560; 10];
+        sizes[2] = 20000;
+        let (timings, hrd) = run(&sps, 18000, &sizes, 2);
+        assert!(!hrd.violations().iter().any(|v| matches!(v, HrdViolation::CpbUnderflow { .. })));
+        // arrival completes at 0.08 + 0.3125, so removal is delayed to the next whole tick
+        assert!(close(timings[2].final_arrival, 0.3925));
+        assert!(close(timings[2].removal, 0.4));
+    }
+
+    #[test]
+    fn overflow() {
+        let sps = sps();
+        // half a second of data at 64kbit/s is more than the CPB can hold
+        let (_, hrd) = run(&sps, 45000, &[2560; 20], 2);
+        assert!(matches!(hrd.violations().first(), Some(HrdViolation::CpbOverflow { access_unit: 0, .. })));
+    }
+
+    #[test]
+    fn cbr() {
+        let mut sps = sps();
+        sps.vui_parameters.as_mut().unwrap().vcl_hrd_parameters.as_mut().unwrap().cpb_specs[0].cbr_flag = true;
+        let (timings, _) = run(&sps, 18000, &[2560; 10], 2);
+        for w in timings.windows(2) {
+            assert_eq!(w[1].initial_arrival, w[0].final_arrival);
+        }
+    }
+
+    #[test]
+    fn dpb_overflow() {
+        let sps = sps();
+        let (_, hrd) = run(&sps, 18000, &[2560; 10], 10);
+        assert!(matches!(hrd.violations().first(), Some(HrdViolation::DpbOverflow { access_unit: 1, pictures: 2, max: 1 })));
+    }
+
+    #[test]
+    fn missing_buffering_period() {
+        let sps = sps();
+        let mut hrd = HrdVerifier::new(&sps, HrdType::Vcl, 0).unwrap();
+        let delays = Delays { cpb_removal_delay: 0, dpb_output_delay: 0 };
+        assert!(matches!(hrd.access_unit(100, None, delays), Err(HrdError::MissingBufferingPeriod { access_unit: 0 })));
+        assert!(matches!(HrdVerifier::new(&sps, HrdType::Nal, 0), Err(HrdError::NoHrdParameters(HrdType::Nal))));
+        assert!(matches!(HrdVerifier::new(&sps, HrdType::Vcl, 1), Err(HrdError::SchedSelIdxOutOfRange { sched_sel_idx: 1, cpb_cnt: 1 })));
+    }
+
+    #[test]
+    fn push_access_unit() {
+        let sps = sps();
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps.clone());
+        let mut filler = vec![0x0c];
+        filler.resize(319, 0xff);
+        filler.push(0x80);
+        let mut sps_nal = vec![0x67];
+        sps_nal.extend_from_slice(&HRD_SPS);
+        let aus = [
+            AccessUnit {
+                nal_units: vec![
+                    sps_nal,
+                    // buffering_period with initial_cpb_removal_delay=18000, and pic_timing
+                    hex!("06 00 05 a3 28 00 00 80 01 02 00 02 80").to_vec(),
+                    filler.clone(),
+                ],
+                byte_stream_len: None,
+            },
+            AccessUnit {
+                nal_units: vec![
+                    hex!("06 01 02 02 02 80").to_vec(),
+                    filler,
+                ],
+                byte_stream_len: None,
+            },
+        ];
+        let mut hrd = HrdVerifier::new(&sps, HrdType::Vcl, 0).unwrap();
+        let timings: Vec<_> = aus.iter()
+            .map(|au| hrd.push_access_unit(&mut ctx, au).unwrap())
+            .collect();
+        hrd.finish();
+        assert_eq!(hrd.violations(), &[]);
+        // only the filler data is counted for Type I conformance
+        assert_eq!(timings[0].size_bits, 2560);
+        assert!(close(timings[0].removal, 0.2));
+        assert!(close(timings[1].removal, 0.24));
+        assert!(close(timings[1].output, 0.28));
+    }
+
+    #[test]
+    fn push_access_unit_with_size() {
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps());
+        // an SPS whose pic_timing syntax includes pic_struct
+        ctx.put_seq_param_set(SeqParameterSet::from_bytes(&VCL_HRD_SPS).unwrap());
+        ctx.put_pic_param_set(PicParameterSet::from_bytes(&ctx, &hex!("de 3c 80")).unwrap());
+        let mut hrd = HrdVerifier::new(&sps(), HrdType::Vcl, 0).unwrap();
+        let au = AccessUnit {
+            nal_units: vec![hex!("06 00 05 a3 28 00 00 80 01 02 00 02 80").to_vec()],
+            byte_stream_len: None,
+        };
+        hrd.push_access_unit_with_size(&mut ctx, &au, 2560).unwrap();
+
+        ctx.activate_sps(SeqParamSetId::from_u32(1).unwrap());
+        // pic_timing precedes the IDR slice which activates SPS 0, and would fail to parse
+        // against SPS 1
+        let au = AccessUnit {
+            nal_units: vec![
+                hex!("06 01 02 02 02 80").to_vec(),
+                hex!("65 88 84 a8").to_vec(),
+            ],
+            byte_stream_len: None,
+        };
+        let timing = hrd.push_access_unit_with_size(&mut ctx, &au, 1000).unwrap();
+        assert_eq!(timing.size_bits, 1000);
+        assert!(close(timing.removal, 0.24));
+        assert_eq!(ctx.active_sps().map(|sps| sps.seq_parameter_set_id.id()), Some(0));
+    }
+
+    #[derive(Default)]
+    struct AccessUnits(Vec<AccessUnit>);
+    impl AccessUnitHandler for AccessUnits {
+        type Ctx = ();
+
+        fn handle(&mut self, _ctx: &mut Context<Self::Ctx>, access_unit: AccessUnit) {
+            self.0.push(access_unit);
+        }
+    }
+
+    #[test]
+    fn push_access_unit_nal() {
+        // the same HRD parameters, given as NAL HRD parameters rather than VCL
+        let mut sps = sps();
+        let vui = sps.vui_parameters.as_mut().unwrap();
+        vui.nal_hrd_parameters = vui.vcl_hrd_parameters.take();
+        let mut sps_nal = vec![0x67];
+        sps_nal.extend_from_slice(&HRD_SPS);
+        let nals = [
+            rbsp::encode_nal(&sps_nal),
+            hex!("68 de 3c 80").to_vec(),
+            // buffering_period with initial_cpb_removal_delay=18000, and pic_timing
+            hex!("06 00 05 a3 28 00 00 80 01 02 00 02 80").to_vec(),
+            hex!("65 88 84 a8").to_vec(),
+            hex!("06 01 02 02 02 80").to_vec(),
+            hex!("65 88 84 a8").to_vec(),
+        ];
+        // two leading_zero_8bits
+        let mut stream = vec![0, 0];
+        for nal in &nals {
+            stream.extend_from_slice(&[0, 0, 0, 1]);
+            stream.extend_from_slice(nal);
+        }
+        // two trailing_zero_8bits
+        stream.extend_from_slice(&[0, 0]);
+
+        let mut ctx = Context::default();
+        let mut r = AnnexBReader::new(AccessUnitReader::new(AccessUnits::default()));
+        r.start(&mut ctx);
+        r.push(&mut ctx, &stream[..]);
+        r.end_units(&mut ctx);
+        let mut au_reader = r.into_nal_reader();
+        au_reader.flush(&mut ctx);
+        // the SPS in the stream lacks the NAL HRD parameters
+        ctx.put_seq_param_set(sps.clone());
+        let aus = au_reader.into_handler().0;
+        assert_eq!(aus.len(), 2);
+
+        let mut hrd = HrdVerifier::new(&sps, HrdType::Nal, 0).unwrap();
+        let timings: Vec<_> = aus.iter()
+            .map(|au| hrd.push_access_unit(&mut ctx, au).unwrap())
+            .collect();
+        hrd.finish();
+        assert_eq!(hrd.violations(), &[]);
+        let first_len = 2 + nals[..4].iter().map(|nal| 4 + nal.len()).sum::<usize>();
+        assert_eq!(timings[0].size_bits, 8 * first_len as u64);
+        assert_eq!(timings[1].size_bits, 8 * (stream.len() - first_len) as u64);
+        assert!(close(timings[1].removal, 0.24));
+    }
+
+}
@@ -7,12 +7,138 @@ pub mod rbsp;
 pub mod annexb;
 pub mod nal;
 pub mod avcc;
+pub mod access_unit;
+pub mod hrd;
+
+/// The result of storing a parameter set in the `Context`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamSetUpdate {
+    /// No parameter set was previously stored with the same id
+    New,
+    /// The parameter set was identical to the one already stored with the same id (as is usual
+    /// when parameter sets are repeated ahead of each IDR picture)
+    Unchanged,
+    /// The parameter set replaced a different one previously stored with the same id
+    Changed,
+}
+
+/// Notable changes to the parameter sets held in the `Context`, collected (once enabled with
+/// `Context::set_record_param_set_events()`) until retrieved with
+/// `Context::take_param_set_events()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamSetEvent {
+    /// An SPS was stored for an id which previously had none
+    NewSps(nal::sps::SeqParamSetId),
+    /// The SPS stored for the given id was replaced by one with different content
+    SpsChanged(nal::sps::SeqParamSetId),
+    /// A PPS was stored for an id which previously had none
+    NewPps(nal::pps::PicParamSetId),
+    /// The PPS stored for the given id was replaced by one with different content
+    PpsChanged(nal::pps::PicParamSetId),
+    /// The PPS with the given id was removed because the SPS it refers to changed (only when
+    /// `DependentPpsPolicy::Invalidate` is in effect)
+    PpsInvalidated(nal::pps::PicParamSetId),
+    /// A different SPS became active, or the content of the active SPS changed (for example, to
+    /// give a new picture size)
+    ActiveSpsChanged { id: nal::sps::SeqParamSetId, generation: u32 },
+}
+
+/// What to do with the PPSs that refer to an SPS when the content of that SPS changes.
+///
+/// Parsing a PPS depends on the SPS it refers to, so a PPS parsed against an earlier version of
+/// the SPS may no longer be valid.  An encoder changing an SPS will normally send new PPSs too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependentPpsPolicy {
+    /// Keep the PPSs (the default); `Context::pps_is_current()` can be used to check them
+    Keep,
+    /// Remove the PPSs, so that slices referring to them fail to parse until they are resent
+    Invalidate,
+}
+// not derived, since #[default] on enum variants needs Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for DependentPpsPolicy {
+    fn default() -> Self {
+        DependentPpsPolicy::Keep
+    }
+}
+/// The NAL unit that a parameter set was parsed from, in whichever form it was made available.
+#[derive(Clone)]
+enum ParamSetNal {
+    /// the complete NAL unit as it appeared in the bitstream, including emulation prevention bytes
+    Escaped(Vec<u8>),
+    /// the NAL header byte followed by the RBSP, for handlers that only see the decoded data
+    Rbsp(Vec<u8>),
+}
+impl ParamSetNal {
+    fn rbsp(&self) -> Vec<u8> {
+        match self {
+            ParamSetNal::Escaped(nal_unit) => {
+                let mut result = Vec::with_capacity(nal_unit.len());
+                result.extend_from_slice(&nal_unit[..1]);
+                result.extend(rbsp::decode_nal(&nal_unit[1..]));
+                result
+            },
+            ParamSetNal::Rbsp(rbsp) => rbsp.clone(),
+        }
+    }
+}
+
+/// The parameter sets of one kind, indexed by id, along with a count of the number of times the
+/// content for each id has been set or changed, and the NAL unit each was parsed from (if known).
+struct ParamSetTable<T> {
+    entries: Vec<Option<T>>,
+    generations: Vec<u32>,
+    nal_units: Vec<Option<ParamSetNal>>,
+}
+impl<T: PartialEq> ParamSetTable<T> {
+    fn new(size: usize) -> Self {
+        ParamSetTable {
+            entries: (0..size).map(|_| None).collect(),
+            generations: vec![0; size],
+            nal_units: vec![None; size],
+        }
+    }
+    fn get(&self, i: usize) -> Option<&T> {
+        self.entries[i].as_ref()
+    }
+    fn put(&mut self, i: usize, value: T, nal_unit: Option<ParamSetNal>) -> ParamSetUpdate {
+        let update = match self.entries[i] {
+            None => ParamSetUpdate::New,
+            Some(ref old) if *old == value => {
+                if nal_unit.is_some() {
+                    self.nal_units[i] = nal_unit;
+                }
+                return ParamSetUpdate::Unchanged
+            },
+            Some(_) => ParamSetUpdate::Changed,
+        };
+        self.entries[i] = Some(value);
+        self.nal_units[i] = nal_unit;
+        self.generations[i] = self.generations[i].wrapping_add(1);
+        update
+    }
+    fn remove(&mut self, i: usize) -> Option<T> {
+        self.nal_units[i] = None;
+        self.entries[i].take()
+    }
+    fn nal_unit(&self, i: usize) -> Option<&ParamSetNal> {
+        self.nal_units[i].as_ref()
+    }
+}
 
 /// Contextual data that needs to be tracked between evaluations of different portions of H264
 /// syntax.
 pub struct Context<Ctx> {
-    seq_param_sets: Vec<Option<nal::sps::SeqParameterSet>>,
-    pic_param_sets: Vec<Option<nal::pps::PicParameterSet>>,
+    seq_param_sets: ParamSetTable<nal::sps::SeqParameterSet>,
+    seq_param_set_exts: Vec<Option<nal::sps_extension::SeqParameterSetExtension>>,
+    subset_seq_param_sets: Vec<Option<nal::subset_sps::SubsetSeqParameterSet>>,
+    pic_param_sets: ParamSetTable<nal::pps::PicParameterSet>,
+    /// for each PPS, the generation of the SPS it referred to at the time it was stored
+    pic_param_set_sps_generations: Vec<u32>,
+    active_sps: Option<(nal::sps::SeqParamSetId, u32)>,
+    dependent_pps_policy: DependentPpsPolicy,
+    record_events: bool,
+    events: Vec<ParamSetEvent>,
     pub user_context: Ctx,
 }
 impl Default for Context<()> {
@@ -21,39 +147,331 @@ impl Default for Context<()> {
     }
 }
 impl<Ctx> Context<Ctx> {
+    /// Creates an empty context.
+    ///
+    /// `ParamSetEvent`s are not recorded by default, since they would accumulate without limit
+    /// for a caller that never retrieves them; see `set_record_param_set_events()`.  Likewise,
+    /// `DependentPpsPolicy::Keep` is in effect until changed with `set_dependent_pps_policy()`.
     pub fn new(user_context: Ctx) -> Self {
-        let mut seq_param_sets = vec!();
-        for _ in 0..32 { seq_param_sets.push(None); }
-        let mut pic_param_sets = vec!();
-        for _ in 0..32 { pic_param_sets.push(None); }
+        let mut seq_param_set_exts = vec!();
+        for _ in 0..32 { seq_param_set_exts.push(None); }
+        let mut subset_seq_param_sets = vec!();
+        for _ in 0..32 { subset_seq_param_sets.push(None); }
         Context {
-            seq_param_sets,
-            pic_param_sets,
+            seq_param_sets: ParamSetTable::new(32),
+            seq_param_set_exts,
+            subset_seq_param_sets,
+            pic_param_sets: ParamSetTable::new(256),
+            pic_param_set_sps_generations: vec![0; 256],
+            active_sps: None,
+            dependent_pps_policy: DependentPpsPolicy::default(),
+            record_events: false,
+            events: vec!(),
             user_context,
         }
     }
 }
 impl<Ctx> Context<Ctx> {
-    pub fn sps_by_id(&self, id: nal::pps::ParamSetId) -> Option<&nal::sps::SeqParameterSet> {
+    pub fn sps_by_id(&self, id: nal::sps::SeqParamSetId) -> Option<&nal::sps::SeqParameterSet> {
+        self.seq_param_sets.get(id.id() as usize)
+    }
+    /// Stores the given SPS, replacing any previously stored with the same id.
+    ///
+    /// If the content differs from that previously stored, the generation for this id is
+    /// incremented, and PPSs referring to the SPS are handled according to the
+    /// `DependentPpsPolicy`.
+    pub fn put_seq_param_set(&mut self, sps: nal::sps::SeqParameterSet) -> ParamSetUpdate {
+        self.store_seq_param_set(sps, None)
+    }
+    /// Stores the given SPS as for `put_seq_param_set()`, also retaining the complete NAL unit it
+    /// was parsed from, as it appeared in the bitstream (i.e. including the header byte and any
+    /// emulation prevention bytes).
+    pub fn put_seq_param_set_with_nal(&mut self, sps: nal::sps::SeqParameterSet, nal_unit: &[u8]) -> ParamSetUpdate {
+        self.store_seq_param_set(sps, Some(ParamSetNal::Escaped(nal_unit.to_vec())))
+    }
+    /// Stores the given SPS as for `put_seq_param_set()`, also retaining the NAL unit it was
+    /// parsed from, given as its header and RBSP (i.e. with emulation prevention bytes removed).
+    /// Where the original NAL unit is available, `put_seq_param_set_with_nal()` is preferable.
+    pub fn put_seq_param_set_with_rbsp(&mut self, sps: nal::sps::SeqParameterSet, header: nal::NalHeader, rbsp: &[u8]) -> ParamSetUpdate {
+        self.store_seq_param_set(sps, Some(ParamSetNal::Rbsp(Self::nal_unit_rbsp(header, rbsp))))
+    }
+    fn nal_unit_rbsp(header: nal::NalHeader, rbsp: &[u8]) -> Vec<u8> {
+        let mut nal_unit = Vec::with_capacity(rbsp.len() + 1);
+        nal_unit.push(u8::from(header));
+        nal_unit.extend_from_slice(rbsp);
+        nal_unit
+    }
+    fn store_seq_param_set(&mut self, sps: nal::sps::SeqParameterSet, nal_unit: Option<ParamSetNal>) -> ParamSetUpdate {
+        let id = sps.seq_parameter_set_id;
+        let update = self.seq_param_sets.put(id.id() as usize, sps, nal_unit);
+        match update {
+            ParamSetUpdate::New => self.push_event(ParamSetEvent::NewSps(id)),
+            ParamSetUpdate::Unchanged => (),
+            ParamSetUpdate::Changed => {
+                self.push_event(ParamSetEvent::SpsChanged(id));
+                if self.dependent_pps_policy == DependentPpsPolicy::Invalidate {
+                    self.invalidate_dependent_pps(id);
+                }
+                if let Some((active_id, _)) = self.active_sps {
+                    if active_id == id {
+                        self.activate_sps(id);
+                    }
+                }
+            },
+        }
+        update
+    }
+    fn invalidate_dependent_pps(&mut self, sps_id: nal::sps::SeqParamSetId) {
+        for i in 0..self.pic_param_sets.entries.len() {
+            let dependent = self.pic_param_sets.get(i)
+                .map(|pps| pps.seq_parameter_set_id == sps_id)
+                .unwrap_or(false);
+            if dependent {
+                self.pic_param_sets.remove(i);
+                self.push_event(ParamSetEvent::PpsInvalidated(nal::pps::PicParamSetId::from_u32(i as u32).unwrap()));
+            }
+        }
+    }
+    /// The number of times that an SPS with new or changed content has been stored for the given
+    /// id (`0` if none has been stored).
+    pub fn sps_generation(&self, id: nal::sps::SeqParamSetId) -> u32 {
+        self.seq_param_sets.generations[id.id() as usize]
+    }
+    /// The NAL unit that the SPS with the given id was parsed from, as the NAL header byte
+    /// followed by the RBSP (without emulation prevention bytes).  Only available if the SPS was
+    /// stored with `put_seq_param_set_with_nal()` (as `AccessUnitReader`, `Nal::parse()` and
+    /// `AvcDecoderConfigurationRecord::create_context()` do) or `put_seq_param_set_with_rbsp()`
+    /// (as `SeqParameterSetNalHandler` does).
+    pub fn sps_rbsp_by_id(&self, id: nal::sps::SeqParamSetId) -> Option<Vec<u8>> {
+        self.seq_param_sets.nal_unit(id.id() as usize).map(ParamSetNal::rbsp)
+    }
+    /// The NAL unit that the SPS with the given id was parsed from, exactly as it appeared in the
+    /// bitstream, ready to be written into a bitstream or an `avcC` record.  Only available if the
+    /// SPS was stored with `put_seq_param_set_with_nal()`; for an SPS stored from its RBSP,
+    /// `rbsp::encode_nal()` can be applied to the result of `sps_rbsp_by_id()` instead.
+    pub fn sps_nal_by_id(&self, id: nal::sps::SeqParamSetId) -> Option<&[u8]> {
+        match self.seq_param_sets.nal_unit(id.id() as usize) {
+            Some(ParamSetNal::Escaped(nal_unit)) => Some(&nal_unit[..]),
+            _ => None,
+        }
+    }
+    /// The extension for the SPS with the given id, if a `SeqParameterSetExtension` NAL unit
+    /// has been seen for it
+    pub fn sps_ext_by_id(&self, id: nal::sps::SeqParamSetId) -> Option<&nal::sps_extension::SeqParameterSetExtension> {
         if id.id() > 31 {
             None
         } else {
-            self.seq_param_sets[id.id() as usize].as_ref()
+            self.seq_param_set_exts[id.id() as usize].as_ref()
         }
     }
-    pub fn put_seq_param_set(&mut self, sps: nal::sps::SeqParameterSet) {
-        let i = sps.seq_parameter_set_id.id() as usize;
-        self.seq_param_sets[i] = Some(sps);
+    pub fn put_seq_param_set_ext(&mut self, ext: nal::sps_extension::SeqParameterSetExtension) {
+        let i = ext.seq_parameter_set_id.id() as usize;
+        self.seq_param_set_exts[i] = Some(ext);
     }
-    pub fn pps_by_id(&self, id: nal::pps::ParamSetId) -> Option<&nal::pps::PicParameterSet> {
+    /// The subset SPS with the given id.  Subset SPSs have their own id-space, separate from that
+    /// of ordinary SPSs.
+    pub fn subset_sps_by_id(&self, id: nal::sps::SeqParamSetId) -> Option<&nal::subset_sps::SubsetSeqParameterSet> {
         if id.id() > 31 {
             None
         } else {
-            self.pic_param_sets[id.id() as usize].as_ref()
+            self.subset_seq_param_sets[id.id() as usize].as_ref()
         }
     }
-    pub fn put_pic_param_set(&mut self, pps: nal::pps::PicParameterSet) {
-        let i = pps.pic_parameter_set_id.id() as usize;
-        self.pic_param_sets[i] = Some(pps);
+    pub fn put_subset_seq_param_set(&mut self, subset_sps: nal::subset_sps::SubsetSeqParameterSet) {
+        let i = subset_sps.sps.seq_parameter_set_id.id() as usize;
+        self.subset_seq_param_sets[i] = Some(subset_sps);
     }
-}
\ No newline at end of file
+    pub fn pps_by_id(&self, id: nal::pps::PicParamSetId) -> Option<&nal::pps::PicParameterSet> {
+        self.pic_param_sets.get(id.id() as usize)
+    }
+    /// Stores the given PPS, replacing any previously stored with the same id.
+    pub fn put_pic_param_set(&mut self, pps: nal::pps::PicParameterSet) -> ParamSetUpdate {
+        self.store_pic_param_set(pps, None)
+    }
+    /// Stores the given PPS as for `put_pic_param_set()`, also retaining the complete NAL unit it
+    /// was parsed from, as it appeared in the bitstream (i.e. including the header byte and any
+    /// emulation prevention bytes).
+    pub fn put_pic_param_set_with_nal(&mut self, pps: nal::pps::PicParameterSet, nal_unit: &[u8]) -> ParamSetUpdate {
+        self.store_pic_param_set(pps, Some(ParamSetNal::Escaped(nal_unit.to_vec())))
+    }
+    /// Stores the given PPS as for `put_pic_param_set()`, also retaining the NAL unit it was
+    /// parsed from, given as its header and RBSP (i.e. with emulation prevention bytes removed).
+    /// Where the original NAL unit is available, `put_pic_param_set_with_nal()` is preferable.
+    pub fn put_pic_param_set_with_rbsp(&mut self, pps: nal::pps::PicParameterSet, header: nal::NalHeader, rbsp: &[u8]) -> ParamSetUpdate {
+        self.store_pic_param_set(pps, Some(ParamSetNal::Rbsp(Self::nal_unit_rbsp(header, rbsp))))
+    }
+    fn store_pic_param_set(&mut self, pps: nal::pps::PicParameterSet, nal_unit: Option<ParamSetNal>) -> ParamSetUpdate {
+        let id = pps.pic_parameter_set_id;
+        let i = id.id() as usize;
+        let sps_generation = self.sps_generation(pps.seq_parameter_set_id);
+        let update = self.pic_param_sets.put(i, pps, nal_unit);
+        self.pic_param_set_sps_generations[i] = sps_generation;
+        match update {
+            ParamSetUpdate::New => self.push_event(ParamSetEvent::NewPps(id)),
+            ParamSetUpdate::Unchanged => (),
+            ParamSetUpdate::Changed => self.push_event(ParamSetEvent::PpsChanged(id)),
+        }
+        update
+    }
+    /// The number of times that a PPS with new or changed content has been stored for the given
+    /// id (`0` if none has been stored).
+    pub fn pps_generation(&self, id: nal::pps::PicParamSetId) -> u32 {
+        self.pic_param_sets.generations[id.id() as usize]
+    }
+    /// The NAL unit that the PPS with the given id was parsed from, as the NAL header byte
+    /// followed by the RBSP (without emulation prevention bytes).  Only available if the PPS was
+    /// stored with `put_pic_param_set_with_nal()` or `put_pic_param_set_with_rbsp()` (see
+    /// `sps_rbsp_by_id()`).
+    pub fn pps_rbsp_by_id(&self, id: nal::pps::PicParamSetId) -> Option<Vec<u8>> {
+        self.pic_param_sets.nal_unit(id.id() as usize).map(ParamSetNal::rbsp)
+    }
+    /// The NAL unit that the PPS with the given id was parsed from, exactly as it appeared in the
+    /// bitstream.  Only available if the PPS was stored with `put_pic_param_set_with_nal()` (see
+    /// `sps_nal_by_id()`).
+    pub fn pps_nal_by_id(&self, id: nal::pps::PicParamSetId) -> Option<&[u8]> {
+        match self.pic_param_sets.nal_unit(id.id() as usize) {
+            Some(ParamSetNal::Escaped(nal_unit)) => Some(&nal_unit[..]),
+            _ => None,
+        }
+    }
+    /// Returns `true` if a PPS is stored for the given id, and the SPS it refers to has not
+    /// changed since the PPS was stored.
+    pub fn pps_is_current(&self, id: nal::pps::PicParamSetId) -> bool {
+        match self.pps_by_id(id) {
+            Some(pps) => self.pic_param_set_sps_generations[id.id() as usize] == self.sps_generation(pps.seq_parameter_set_id),
+            None => false,
+        }
+    }
+    /// Sets how PPSs are treated when the SPS they refer to changes.
+    pub fn set_dependent_pps_policy(&mut self, policy: DependentPpsPolicy) {
+        self.dependent_pps_policy = policy;
+    }
+
+    /// Makes the SPS with the given id the active SPS.  `AccessUnitReader` does this for the SPS
+    /// referred to by the first slice of each access unit, once the previous access unit has been
+    /// handled; other callers must do so themselves, since `SliceHeader::read()` does not.
+    ///
+    /// A `ParamSetEvent::ActiveSpsChanged` event is generated if this is a different SPS than
+    /// was active before, or the same SPS with different content.
+    pub fn activate_sps(&mut self, id: nal::sps::SeqParamSetId) {
+        let generation = self.sps_generation(id);
+        if self.active_sps != Some((id, generation)) {
+            self.active_sps = Some((id, generation));
+            self.push_event(ParamSetEvent::ActiveSpsChanged { id, generation });
+        }
+    }
+    /// The currently active SPS, if any
+    pub fn active_sps(&self) -> Option<&nal::sps::SeqParameterSet> {
+        self.active_sps.and_then(|(id, _)| self.sps_by_id(id))
+    }
+    /// Sets whether `ParamSetEvent`s are recorded, for retrieval with `take_param_set_events()`.
+    /// Recording is off by default.  Turning it off discards any events not yet retrieved.
+    pub fn set_record_param_set_events(&mut self, record: bool) {
+        self.record_events = record;
+        if !record {
+            self.events.clear();
+        }
+    }
+    fn push_event(&mut self, event: ParamSetEvent) {
+        if self.record_events {
+            self.events.push(event);
+        }
+    }
+    /// Returns the events which have occurred since the last call, oldest first, or since
+    /// recording was turned on with `set_record_param_set_events()`.
+    ///
+    /// Only the first storing of a parameter set and subsequent changes generate events, so
+    /// parameter sets repeated with identical content do not cause this list to grow.
+    pub fn take_param_set_events(&mut self) -> Vec<ParamSetEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nal::pps::{PicParameterSet, PicParamSetId};
+    use crate::nal::sps::{SeqParameterSet, SeqParamSetId};
+    use crate::nal::sps::test::HIGH_PROFILE_SPS;
+    use crate::nal::NalHeader;
+    use hex_literal::*;
+
+    #[test]
+    fn param_set_lifecycle() {
+        let sps = SeqParameterSet::from_bytes(&HIGH_PROFILE_SPS).unwrap();
+        let mut changed_sps = sps.clone();
+        changed_sps.level_idc = 31;
+        let sps_id = SeqParamSetId::from_u32(0).unwrap();
+        let pps_id = PicParamSetId::from_u32(0).unwrap();
+
+        let mut ctx = Context::default();
+        ctx.set_record_param_set_events(true);
+        assert_eq!(ctx.sps_generation(sps_id), 0);
+        assert_eq!(ctx.put_seq_param_set(sps.clone()), ParamSetUpdate::New);
+        assert_eq!(ctx.put_seq_param_set(sps.clone()), ParamSetUpdate::Unchanged);
+        assert_eq!(ctx.sps_generation(sps_id), 1);
+        let pps = PicParameterSet::from_bytes(&ctx, &hex!("E8 43 8F 13 21 30")).unwrap();
+        assert_eq!(ctx.put_pic_param_set(pps.clone()), ParamSetUpdate::New);
+        assert!(ctx.pps_is_current(pps_id));
+        ctx.activate_sps(sps_id);
+        ctx.activate_sps(sps_id);
+        assert_eq!(ctx.take_param_set_events(), vec![
+            ParamSetEvent::NewSps(sps_id),
+            ParamSetEvent::NewPps(pps_id),
+            ParamSetEvent::ActiveSpsChanged { id: sps_id, generation: 1 },
+        ]);
+
+        // changing the active SPS is reported, and leaves the PPS out of date
+        assert_eq!(ctx.put_seq_param_set(changed_sps), ParamSetUpdate::Changed);
+        assert_eq!(ctx.sps_generation(sps_id), 2);
+        assert_eq!(ctx.active_sps().map(|s| s.level_idc), Some(31));
+        assert!(!ctx.pps_is_current(pps_id));
+        assert_eq!(ctx.put_pic_param_set(pps), ParamSetUpdate::Unchanged);
+        assert!(ctx.pps_is_current(pps_id));
+        assert_eq!(ctx.take_param_set_events(), vec![
+            ParamSetEvent::SpsChanged(sps_id),
+            ParamSetEvent::ActiveSpsChanged { id: sps_id, generation: 2 },
+        ]);
+
+        ctx.set_dependent_pps_policy(DependentPpsPolicy::Invalidate);
+        assert_eq!(ctx.put_seq_param_set(sps), ParamSetUpdate::Changed);
+        assert!(ctx.pps_by_id(pps_id).is_none());
+        assert_eq!(ctx.take_param_set_events(), vec![
+            ParamSetEvent::SpsChanged(sps_id),
+            ParamSetEvent::PpsInvalidated(pps_id),
+            ParamSetEvent::ActiveSpsChanged { id: sps_id, generation: 3 },
+        ]);
+    }
+
+    #[test]
+    fn param_set_events_off_by_default() {
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(SeqParameterSet::from_bytes(&HIGH_PROFILE_SPS).unwrap());
+        ctx.activate_sps(SeqParamSetId::from_u32(0).unwrap());
+        assert!(ctx.take_param_set_events().is_empty());
+    }
+
+    #[test]
+    fn param_set_nal_units() {
+        // includes an emulation prevention byte
+        let sps_rbsp = hex!("42c01e b91061ff 78088000 00030080 00001971 3006d600 daf7bdc0 7c2211a8");
+        let sps = SeqParameterSet::from_bytes(&rbsp::decode_nal(&sps_rbsp)).unwrap();
+        let sps_id = sps.seq_parameter_set_id;
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps.clone());
+        assert_eq!(ctx.sps_rbsp_by_id(sps_id), None);
+        // repeating the same SPS with its bytes retains them
+        let header = NalHeader::new(0x67).unwrap();
+        let decoded = rbsp::decode_nal(&sps_rbsp);
+        ctx.put_seq_param_set_with_rbsp(sps.clone(), header, &decoded);
+        assert_eq!(ctx.sps_rbsp_by_id(sps_id).map(|b| b[0]), Some(0x67));
+        assert_eq!(&ctx.sps_rbsp_by_id(sps_id).unwrap()[1..], &decoded[..]);
+        // the original NAL unit isn't known, so isn't made up by re-adding emulation prevention
+        assert_eq!(ctx.sps_nal_by_id(sps_id), None);
+        let mut nal = vec![0x67];
+        nal.extend_from_slice(&sps_rbsp);
+        ctx.put_seq_param_set_with_nal(sps, &nal);
+        assert_eq!(ctx.sps_nal_by_id(sps_id), Some(&nal[..]));
+        assert_eq!(&ctx.sps_rbsp_by_id(sps_id).unwrap()[1..], &decoded[..]);
+    }
+}
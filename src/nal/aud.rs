@@ -0,0 +1,189 @@
+//! Parser for `access_unit_delimiter_rbsp()`, which may mark the start of each access unit.
+
+use crate::Context;
+use crate::rbsp::RbspBitReader;
+use super::{NalFlow, NalHandler, NalHeader, UnitType};
+use super::slice::SliceFamily;
+
+#[derive(Debug, PartialEq)]
+pub enum AudError {
+    ReaderError(bitreader::BitReaderError),
+}
+impl From<bitreader::BitReaderError> for AudError {
+    fn from(e: bitreader::BitReaderError) -> Self {
+        AudError::ReaderError(e)
+    }
+}
+
+/// Indicates which slice types may be present in the primary coded picture of the access unit
+/// (Table 7-5 in the spec).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrimaryPicType {
+    I,
+    IP,
+    IPB,
+    SI,
+    SISP,
+    ISI,
+    ISIPSP,
+    ISIPSPB,
+}
+impl PrimaryPicType {
+    pub fn from_id(id: u8) -> Option<PrimaryPicType> {
+        match id {
+            0 => Some(PrimaryPicType::I),
+            1 => Some(PrimaryPicType::IP),
+            2 => Some(PrimaryPicType::IPB),
+            3 => Some(PrimaryPicType::SI),
+            4 => Some(PrimaryPicType::SISP),
+            5 => Some(PrimaryPicType::ISI),
+            6 => Some(PrimaryPicType::ISIPSP),
+            7 => Some(PrimaryPicType::ISIPSPB),
+            _ => None,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            PrimaryPicType::I => 0,
+            PrimaryPicType::IP => 1,
+            PrimaryPicType::IPB => 2,
+            PrimaryPicType::SI => 3,
+            PrimaryPicType::SISP => 4,
+            PrimaryPicType::ISI => 5,
+            PrimaryPicType::ISIPSP => 6,
+            PrimaryPicType::ISIPSPB => 7,
+        }
+    }
+
+    /// The slice types that may be present in the primary coded picture.
+    pub fn slice_types(self) -> &'static [SliceFamily] {
+        match self {
+            PrimaryPicType::I => &[SliceFamily::I],
+            PrimaryPicType::IP => &[SliceFamily::I, SliceFamily::P],
+            PrimaryPicType::IPB => &[SliceFamily::I, SliceFamily::P, SliceFamily::B],
+            PrimaryPicType::SI => &[SliceFamily::SI],
+            PrimaryPicType::SISP => &[SliceFamily::SI, SliceFamily::SP],
+            PrimaryPicType::ISI => &[SliceFamily::I, SliceFamily::SI],
+            PrimaryPicType::ISIPSP => &[SliceFamily::I, SliceFamily::SI, SliceFamily::P, SliceFamily::SP],
+            PrimaryPicType::ISIPSPB => &[SliceFamily::I, SliceFamily::SI, SliceFamily::P, SliceFamily::SP, SliceFamily::B],
+        }
+    }
+
+    /// True if slices of the given type may appear in the primary coded picture.
+    pub fn allows(self, family: SliceFamily) -> bool {
+        self.slice_types().contains(&family)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessUnitDelimiter {
+    pub primary_pic_type: PrimaryPicType,
+}
+impl AccessUnitDelimiter {
+    pub fn from_bytes(buf: &[u8]) -> Result<AccessUnitDelimiter, AudError> {
+        let mut r = RbspBitReader::new(buf);
+        let primary_pic_type = PrimaryPicType::from_id(r.read_u8(3)?)
+            .expect("3 bit value can't be out of range");
+        Ok(AccessUnitDelimiter {
+            primary_pic_type,
+        })
+    }
+}
+
+pub trait AccessUnitDelimiterHandler {
+    type Ctx;
+    fn handle(&mut self, ctx: &mut Context<Self::Ctx>, aud: AccessUnitDelimiter);
+}
+
+/// A `NalHandler` which parses `AccessUnitDelimiter` NAL units, passing the result to the given
+/// `AccessUnitDelimiterHandler`.
+pub struct AccessUnitDelimiterNalHandler<H: AccessUnitDelimiterHandler> {
+    handler: H,
+    started: bool,
+}
+impl<H: AccessUnitDelimiterHandler> AccessUnitDelimiterNalHandler<H> {
+    pub fn new(handler: H) -> Self {
+        AccessUnitDelimiterNalHandler {
+            handler,
+            started: false,
+        }
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+}
+impl<H: AccessUnitDelimiterHandler> NalHandler for AccessUnitDelimiterNalHandler<H> {
+    type Ctx = H::Ctx;
+
+    fn start(&mut self, _ctx: &mut Context<Self::Ctx>, header: NalHeader) -> NalFlow {
+        if header.nal_unit_type() != UnitType::AccessUnitDelimiter {
+            eprintln!("AccessUnitDelimiterNalHandler given NAL unit type {:?}", header.nal_unit_type());
+            return NalFlow::Skip;
+        }
+        self.started = true;
+        NalFlow::Continue
+    }
+
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
+        if buf.is_empty() {
+            return NalFlow::Continue;
+        }
+        if !self.started {
+            return NalFlow::Skip;
+        }
+        self.started = false;
+        // the whole of the syntax is within the first byte, so there's no need to see any more
+        match AccessUnitDelimiter::from_bytes(buf) {
+            Ok(aud) => self.handler.handle(ctx, aud),
+            Err(e) => eprintln!("access_unit_delimiter: {:?}", e),
+        }
+        NalFlow::Skip
+    }
+
+    fn end(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
+        if self.started {
+            eprintln!("access_unit_delimiter: no data");
+            self.started = false;
+        }
+        NalFlow::Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct State {
+        auds: Vec<AccessUnitDelimiter>,
+    }
+    impl AccessUnitDelimiterHandler for State {
+        type Ctx = ();
+
+        fn handle(&mut self, _ctx: &mut Context<Self::Ctx>, aud: AccessUnitDelimiter) {
+            self.auds.push(aud);
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let aud = AccessUnitDelimiter::from_bytes(&[0x50]).unwrap();
+        assert_eq!(aud.primary_pic_type, PrimaryPicType::IPB);
+        assert!(aud.primary_pic_type.allows(SliceFamily::B));
+        assert!(!aud.primary_pic_type.allows(SliceFamily::SP));
+        assert_eq!(PrimaryPicType::from_id(aud.primary_pic_type.id()), Some(PrimaryPicType::IPB));
+        assert!(AccessUnitDelimiter::from_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn handler() {
+        let mut ctx = Context::default();
+        let mut h = AccessUnitDelimiterNalHandler::new(State::default());
+        assert_eq!(h.start(&mut ctx, NalHeader::new(0x09).unwrap()), NalFlow::Continue);
+        assert_eq!(h.push(&mut ctx, &[0xf0]), NalFlow::Skip);
+        assert_eq!(h.end(&mut ctx), NalFlow::Continue);
+        assert_eq!(h.handler().auds, vec![AccessUnitDelimiter { primary_pic_type: PrimaryPicType::ISIPSPB }]);
+    }
+}
@@ -0,0 +1,111 @@
+//! Support for the slices of _auxiliary coded pictures_ (`SliceLayerWithoutPartitioningAux` NAL
+//! units), which most commonly carry an alpha plane to accompany the primary coded picture.
+//!
+//! Auxiliary slices use the same syntax as primary slices, but are monochrome, and take their
+//! `IdrPicFlag` from the primary coded picture of the same access unit rather than from their own
+//! NAL unit type.  The meaning of their samples is given by the `AuxInfo` of the
+//! `SeqParameterSetExtension` for the SPS they refer to.
+
+use crate::Context;
+use crate::rbsp::{self, RbspBitReader};
+use super::{NalHeader, NalHeaderError, UnitType};
+use super::sps::SeqParamSetId;
+use super::slice::{SliceHeader, SliceHeaderError};
+use super::sps_extension::AuxInfo;
+
+#[derive(Debug)]
+pub enum AuxSliceError {
+    /// The NAL unit had no data at all
+    Empty,
+    BadHeader(NalHeaderError),
+    /// The NAL unit was not a `SliceLayerWithoutPartitioningAux` NAL unit
+    NotAuxiliary(UnitType),
+    Slice(SliceHeaderError),
+    /// The SPS with the given id has no `SeqParameterSetExtension` giving an `aux_format_idc`
+    /// other than `0`, so auxiliary coded pictures are not expected
+    NoAuxFormat(SeqParamSetId),
+}
+impl From<SliceHeaderError> for AuxSliceError {
+    fn from(e: SliceHeaderError) -> Self {
+        AuxSliceError::Slice(e)
+    }
+}
+
+/// The header of a slice of an auxiliary coded picture, along with the details of the auxiliary
+/// format that it uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuxiliarySlice {
+    pub nal_header: NalHeader,
+    pub header: SliceHeader,
+    /// The alpha (or other) properties of the auxiliary picture, from the SPS extension
+    pub aux_info: AuxInfo,
+}
+impl AuxiliarySlice {
+    /// Parses the slice header from a complete NAL unit (given including its header byte, and
+    /// still containing any emulation prevention bytes).
+    ///
+    /// `primary_idr` must give whether the primary coded picture of the access unit containing
+    /// this NAL unit is an IDR picture.
+    pub fn read<Ctx>(ctx: &mut Context<Ctx>, nal_unit: &[u8], primary_idr: bool) -> Result<AuxiliarySlice, AuxSliceError> {
+        let nal_header = NalHeader::new(*nal_unit.first().ok_or(AuxSliceError::Empty)?)
+            .map_err(AuxSliceError::BadHeader)?;
+        if nal_header.nal_unit_type() != UnitType::SliceLayerWithoutPartitioningAux {
+            return Err(AuxSliceError::NotAuxiliary(nal_header.nal_unit_type()));
+        }
+        let rbsp = rbsp::decode_nal(&nal_unit[1..]);
+        let mut r = RbspBitReader::new(&rbsp[..]);
+        let (header, sps, _) = SliceHeader::read_auxiliary(ctx, &mut r, nal_header, primary_idr)?;
+        let sps_id = sps.seq_parameter_set_id;
+        let aux_info = ctx.sps_ext_by_id(sps_id)
+            .and_then(|ext| ext.aux_info.clone())
+            .ok_or(AuxSliceError::NoAuxFormat(sps_id))?;
+        Ok(AuxiliarySlice {
+            nal_header,
+            header,
+            aux_info,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nal::pps::PicParameterSet;
+    use crate::nal::slice::DecRefPicMarking;
+    use crate::nal::sps::SeqParameterSet;
+    use crate::nal::sps_extension::{AuxFormat, SeqParameterSetExtension};
+    use hex_literal::*;
+
+    fn ctx() -> Context<()> {
+        let mut ctx = Context::default();
+        let sps = SeqParameterSet::from_bytes(&rbsp::decode_nal(&hex!("42c01e b91061ff 78088000 00030080 00001971 3006d600 daf7bdc0 7c2211a8"))).unwrap();
+        ctx.put_seq_param_set(sps);
+        let pps = PicParameterSet::from_bytes(&ctx, &hex!("de3c80")).unwrap();
+        ctx.put_pic_param_set(pps);
+        ctx
+    }
+
+    #[test]
+    fn alpha() {
+        let mut ctx = ctx();
+        // the same slice header data as an IDR slice of the primary picture
+        let nal = hex!("73 88 81 01 2a 80");
+        assert!(matches!(AuxiliarySlice::read(&mut ctx, &nal, true), Err(AuxSliceError::NoAuxFormat(_))));
+
+        // seq_parameter_set_id=0, aux_format_idc=1, bit_depth_aux_minus8=0, alpha_incr_flag=0,
+        // alpha_opaque_value=255, alpha_transparent_value=0
+        let ext = SeqParameterSetExtension::from_bytes(&hex!("a9 fe 00 40")).unwrap();
+        ctx.put_seq_param_set_ext(ext);
+        let slice = AuxiliarySlice::read(&mut ctx, &nal, true).unwrap();
+        assert_eq!(slice.aux_info.aux_format, AuxFormat::StraightAlpha);
+        assert_eq!(slice.aux_info.alpha_opaque_value, 255);
+        assert!(slice.header.idr_pic_id.is_some());
+        assert!(matches!(slice.header.dec_ref_pic_marking, Some(DecRefPicMarking::Idr { .. })));
+
+        // when the primary picture is not IDR, neither is the auxiliary picture
+        let slice = AuxiliarySlice::read(&mut ctx, &nal, false).unwrap();
+        assert_eq!(slice.header.idr_pic_id, None);
+
+        assert!(matches!(AuxiliarySlice::read(&mut ctx, &hex!("65 88 81 01 2a 80"), true), Err(AuxSliceError::NotAuxiliary(_))));
+    }
+}
@@ -0,0 +1,148 @@
+//! Handlers for `end_of_seq_rbsp()` and `end_of_stream_rbsp()`, which have no content of their
+//! own but signal that the next picture (if any) will be an IDR picture, so that state such as
+//! picture order count tracking should be reset.
+
+use crate::Context;
+use super::{NalFlow, NalHandler, NalHeader, UnitType};
+
+pub trait EndOfSeqHandler {
+    type Ctx;
+    fn end_of_seq(&mut self, ctx: &mut Context<Self::Ctx>);
+}
+
+pub trait EndOfStreamHandler {
+    type Ctx;
+    fn end_of_stream(&mut self, ctx: &mut Context<Self::Ctx>);
+}
+
+/// A `NalHandler` for `EndOfSeq` NAL units, which notifies the given `EndOfSeqHandler` once the
+/// NAL unit is complete.
+pub struct EndOfSeqNalHandler<H: EndOfSeqHandler> {
+    handler: H,
+    started: bool,
+}
+impl<H: EndOfSeqHandler> EndOfSeqNalHandler<H> {
+    pub fn new(handler: H) -> Self {
+        EndOfSeqNalHandler {
+            handler,
+            started: false,
+        }
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+}
+impl<H: EndOfSeqHandler> NalHandler for EndOfSeqNalHandler<H> {
+    type Ctx = H::Ctx;
+
+    fn start(&mut self, _ctx: &mut Context<Self::Ctx>, header: NalHeader) -> NalFlow {
+        if header.nal_unit_type() != UnitType::EndOfSeq {
+            eprintln!("EndOfSeqNalHandler given NAL unit type {:?}", header.nal_unit_type());
+            return NalFlow::Skip;
+        }
+        self.started = true;
+        NalFlow::Skip
+    }
+
+    fn push(&mut self, _ctx: &mut Context<Self::Ctx>, _buf: &[u8]) -> NalFlow {
+        NalFlow::Skip
+    }
+
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) -> NalFlow {
+        if self.started {
+            self.started = false;
+            self.handler.end_of_seq(ctx);
+        }
+        NalFlow::Continue
+    }
+}
+
+/// A `NalHandler` for `EndOfStream` NAL units, which notifies the given `EndOfStreamHandler` once
+/// the NAL unit is complete.
+pub struct EndOfStreamNalHandler<H: EndOfStreamHandler> {
+    handler: H,
+    started: bool,
+}
+impl<H: EndOfStreamHandler> EndOfStreamNalHandler<H> {
+    pub fn new(handler: H) -> Self {
+        EndOfStreamNalHandler {
+            handler,
+            started: false,
+        }
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+}
+impl<H: EndOfStreamHandler> NalHandler for EndOfStreamNalHandler<H> {
+    type Ctx = H::Ctx;
+
+    fn start(&mut self, _ctx: &mut Context<Self::Ctx>, header: NalHeader) -> NalFlow {
+        if header.nal_unit_type() != UnitType::EndOfStream {
+            eprintln!("EndOfStreamNalHandler given NAL unit type {:?}", header.nal_unit_type());
+            return NalFlow::Skip;
+        }
+        self.started = true;
+        NalFlow::Skip
+    }
+
+    fn push(&mut self, _ctx: &mut Context<Self::Ctx>, _buf: &[u8]) -> NalFlow {
+        NalFlow::Skip
+    }
+
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) -> NalFlow {
+        if self.started {
+            self.started = false;
+            self.handler.end_of_stream(ctx);
+        }
+        NalFlow::Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct State {
+        seq: u32,
+        stream: u32,
+    }
+    impl EndOfSeqHandler for State {
+        type Ctx = ();
+
+        fn end_of_seq(&mut self, _ctx: &mut Context<Self::Ctx>) {
+            self.seq += 1;
+        }
+    }
+    impl EndOfStreamHandler for State {
+        type Ctx = ();
+
+        fn end_of_stream(&mut self, _ctx: &mut Context<Self::Ctx>) {
+            self.stream += 1;
+        }
+    }
+
+    #[test]
+    fn end_of_seq() {
+        let mut ctx = Context::default();
+        let mut h = EndOfSeqNalHandler::new(State::default());
+        assert_eq!(h.start(&mut ctx, NalHeader::new(0x0a).unwrap()), NalFlow::Skip);
+        h.end(&mut ctx);
+        // wrong NAL unit type is not reported
+        h.start(&mut ctx, NalHeader::new(0x0b).unwrap());
+        h.end(&mut ctx);
+        assert_eq!(h.handler().seq, 1);
+    }
+
+    #[test]
+    fn end_of_stream() {
+        let mut ctx = Context::default();
+        let mut h = EndOfStreamNalHandler::new(State::default());
+        h.start(&mut ctx, NalHeader::new(0x0b).unwrap());
+        h.end(&mut ctx);
+        assert_eq!(h.handler().stream, 1);
+    }
+}
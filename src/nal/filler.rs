@@ -0,0 +1,150 @@
+//! Handler for `filler_data_rbsp()`, which consists of some number of `0xFF` bytes followed by
+//! `rbsp_trailing_bits()`.
+//!
+//! The filler data carries no information, but contributes to the size of the bitstream, so the
+//! handler keeps count of the filler bytes seen.
+
+use crate::Context;
+use super::{NalFlow, NalHandler, NalHeader, UnitType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FillerData {
+    /// The number of `ff_byte` values in the NAL unit
+    pub ff_bytes: usize,
+    /// `false` if the `0xFF` bytes were not followed by just the `rbsp_trailing_bits()` byte,
+    /// `0x80`
+    pub well_formed: bool,
+}
+
+pub trait FillerDataHandler {
+    type Ctx;
+    fn handle(&mut self, ctx: &mut Context<Self::Ctx>, filler: FillerData);
+}
+
+#[derive(Debug)]
+enum FillerState {
+    Idle,
+    /// counting `0xFF` bytes
+    FfBytes,
+    /// the trailing bits byte has been seen
+    Trailing,
+    /// something other than trailing bits followed the `0xFF` bytes
+    Malformed,
+}
+
+/// A `NalHandler` for `FillerData` NAL units, which passes a summary of each NAL unit to the given
+/// `FillerDataHandler`, and keeps a running total of the filler bytes seen.
+pub struct FillerDataNalHandler<H: FillerDataHandler> {
+    handler: H,
+    state: FillerState,
+    ff_bytes: usize,
+    total_ff_bytes: u64,
+    total_nal_bytes: u64,
+}
+impl<H: FillerDataHandler> FillerDataNalHandler<H> {
+    pub fn new(handler: H) -> Self {
+        FillerDataNalHandler {
+            handler,
+            state: FillerState::Idle,
+            ff_bytes: 0,
+            total_ff_bytes: 0,
+            total_nal_bytes: 0,
+        }
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// The total number of `ff_byte` values seen over all `FillerData` NAL units so far.
+    pub fn total_ff_bytes(&self) -> u64 {
+        self.total_ff_bytes
+    }
+
+    /// The total size of all `FillerData` NAL units so far, including their one-byte NAL headers
+    /// and trailing bits (but not including any emulation prevention bytes or Annex B start
+    /// codes).
+    pub fn total_nal_bytes(&self) -> u64 {
+        self.total_nal_bytes
+    }
+}
+impl<H: FillerDataHandler> NalHandler for FillerDataNalHandler<H> {
+    type Ctx = H::Ctx;
+
+    fn start(&mut self, _ctx: &mut Context<Self::Ctx>, header: NalHeader) -> NalFlow {
+        if header.nal_unit_type() != UnitType::FillerData {
+            eprintln!("FillerDataNalHandler given NAL unit type {:?}", header.nal_unit_type());
+            return NalFlow::Skip;
+        }
+        self.state = FillerState::FfBytes;
+        self.ff_bytes = 0;
+        self.total_nal_bytes += 1;
+        NalFlow::Continue
+    }
+
+    fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
+        if let FillerState::Idle = self.state {
+            return NalFlow::Skip;
+        }
+        self.total_nal_bytes += buf.len() as u64;
+        for &b in buf {
+            self.state = match self.state {
+                FillerState::FfBytes if b == 0xff => {
+                    self.ff_bytes += 1;
+                    FillerState::FfBytes
+                },
+                FillerState::FfBytes if b == 0x80 => FillerState::Trailing,
+                _ => FillerState::Malformed,
+            }
+        }
+        NalFlow::Continue
+    }
+
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) -> NalFlow {
+        let well_formed = match self.state {
+            FillerState::Idle => return NalFlow::Continue,
+            FillerState::Trailing => true,
+            FillerState::FfBytes | FillerState::Malformed => false,
+        };
+        self.state = FillerState::Idle;
+        self.total_ff_bytes += self.ff_bytes as u64;
+        self.handler.handle(ctx, FillerData { ff_bytes: self.ff_bytes, well_formed });
+        NalFlow::Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct State {
+        fillers: Vec<FillerData>,
+    }
+    impl FillerDataHandler for State {
+        type Ctx = ();
+
+        fn handle(&mut self, _ctx: &mut Context<Self::Ctx>, filler: FillerData) {
+            self.fillers.push(filler);
+        }
+    }
+
+    #[test]
+    fn count() {
+        let mut ctx = Context::default();
+        let mut h = FillerDataNalHandler::new(State::default());
+        h.start(&mut ctx, NalHeader::new(0x0c).unwrap());
+        h.push(&mut ctx, &[0xff, 0xff, 0xff]);
+        h.push(&mut ctx, &[0xff, 0x80]);
+        h.end(&mut ctx);
+        h.start(&mut ctx, NalHeader::new(0x0c).unwrap());
+        h.push(&mut ctx, &[0xff, 0x00, 0x80]);
+        h.end(&mut ctx);
+        assert_eq!(h.handler().fillers, vec![
+            FillerData { ff_bytes: 4, well_formed: true },
+            FillerData { ff_bytes: 1, well_formed: false },
+        ]);
+        assert_eq!(h.total_ff_bytes(), 5);
+        assert_eq!(h.total_nal_bytes(), 10);
+    }
+}
@@ -0,0 +1,471 @@
+//! Support for the NAL unit header extensions that follow the first header byte in NAL units
+//! of type `PrefixNALUnit`, `SliceExtension` and `SliceExtensionViewComponent`.
+//!
+//! The extension is one of,
+//!
+//!  - `nal_unit_header_svc_extension()` (Annex G, scalable video coding) of 3 bytes
+//!  - `nal_unit_header_mvc_extension()` (Annex H, multiview video coding) of 3 bytes
+//!  - `nal_unit_header_3davc_extension()` (Annex J, 3D-AVC) of 2 bytes
+//!
+//! Handlers implementing [`ExtendedNalHandler`](trait.ExtendedNalHandler.html) can be wrapped in
+//! a [`NalHeaderExtensionReader`](struct.NalHeaderExtensionReader.html) to have the extension
+//! parsed for them before the rest of the NAL unit's data is delivered.
+//!
+//! Note that,
+//!
+//!  - Nothing in this crate uses these types on the caller's behalf: `Nal::parse()` reports
+//!    these NAL unit types as `Nal::Other` (with the extension bytes left as they were, ready
+//!    to be given to `NalHeaderExtension::read()`), and neither `AccessUnitReader` nor a
+//!    `nal_switch!` struct parses the extension unless a `NalHeaderExtensionReader` is given as
+//!    the handler for the unit type.
+//!  - The extension bytes are part of the NAL unit header, so emulation prevention does not
+//!    apply to them, and they must be read from the NAL unit data _before_ emulation prevention
+//!    bytes are removed.  For example the MVC extension `00 00 03` (`view_id` of `0`,
+//!    `inter_view_flag` set) would be corrupted by an `RbspDecoder`, which would drop the `03`.
+//!    A `NalHeaderExtensionReader` must therefore not be wrapped in an `RbspDecoder`; instead the
+//!    wrapped handler receives the still-escaped data following the extension, and removes
+//!    emulation prevention bytes itself (emulation prevention starts afresh after the header).
+//!
+//! ```ignore
+//! #[derive(Default)]
+//! struct MvcSliceHandler {
+//!     extension: Option<NalHeaderExtension>,
+//!     data: Vec<u8>,
+//! }
+//! impl ExtendedNalHandler for MvcSliceHandler {
+//!     type Ctx = ();
+//!
+//!     fn start(&mut self, _ctx: &mut Context<()>, _header: NalHeader, extension: Option<NalHeaderExtension>) -> NalFlow {
+//!         self.extension = extension;
+//!         self.data.clear();
+//!         NalFlow::Continue
+//!     }
+//!     fn push(&mut self, _ctx: &mut Context<()>, buf: &[u8]) -> NalFlow {
+//!         self.data.extend_from_slice(buf);
+//!         NalFlow::Continue
+//!     }
+//!     fn end(&mut self, _ctx: &mut Context<()>) -> NalFlow {
+//!         let rbsp = rbsp::decode_nal(&self.data);
+//!         // ... parse the slice header from `rbsp` ...
+//!         NalFlow::Continue
+//!     }
+//! }
+//!
+//! nal_switch!{
+//!     MyNalSwitch<()> {
+//!         // not wrapped in an RbspDecoder, since the extension must be read from escaped data
+//!         SliceExtension: NalHeaderExtensionReader<MvcSliceHandler>
+//!             => NalHeaderExtensionReader::new(MvcSliceHandler::default()),
+//!         SeqParameterSet: RbspDecoder<SeqParameterSetNalHandler<()>>
+//!             => RbspDecoder::new(SeqParameterSetNalHandler::default()),
+//!     }
+//! }
+//! ```
+
+use super::{NalFlow, NalHandler, NalHeader, UnitType};
+use crate::Context;
+
+#[derive(Debug, PartialEq)]
+pub enum NalHeaderExtensionError {
+    /// The given NAL unit type does not have a header extension
+    NotExtendedUnitType(UnitType),
+    /// Not enough bytes were given to hold the extension
+    NotEnoughData { expected: usize, actual: usize },
+}
+
+/// `nal_unit_header_svc_extension()`, used when `svc_extension_flag` is `1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SvcExtension {
+    pub idr_flag: bool,
+    pub priority_id: u8,
+    pub no_inter_layer_pred_flag: bool,
+    pub dependency_id: u8,
+    pub quality_id: u8,
+    pub temporal_id: u8,
+    pub use_ref_base_pic_flag: bool,
+    pub discardable_flag: bool,
+    pub output_flag: bool,
+}
+
+/// `nal_unit_header_mvc_extension()`, used when both `svc_extension_flag` and
+/// `avc_3d_extension_flag` are `0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MvcExtension {
+    pub non_idr_flag: bool,
+    pub priority_id: u8,
+    pub view_id: u16,
+    pub temporal_id: u8,
+    pub anchor_pic_flag: bool,
+    pub inter_view_flag: bool,
+}
+
+/// `nal_unit_header_3davc_extension()`, used when `avc_3d_extension_flag` is `1` (which is only
+/// possible for `SliceExtensionViewComponent` NAL units).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Avc3dExtension {
+    pub view_idx: u8,
+    pub depth_flag: bool,
+    pub non_idr_flag: bool,
+    pub temporal_id: u8,
+    pub anchor_pic_flag: bool,
+    pub inter_view_flag: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalHeaderExtension {
+    Svc(SvcExtension),
+    Mvc(MvcExtension),
+    Avc3d(Avc3dExtension),
+}
+impl NalHeaderExtension {
+    /// The number of bytes the extension will occupy, given the NAL unit type and the first byte
+    /// following the initial header byte.
+    pub fn byte_len_for(unit_type: UnitType, first_byte: u8) -> Result<usize, NalHeaderExtensionError> {
+        if !unit_type.has_header_extension() {
+            return Err(NalHeaderExtensionError::NotExtendedUnitType(unit_type));
+        }
+        let flag = first_byte & 0b1000_0000 != 0;
+        Ok(if flag && unit_type == UnitType::SliceExtensionViewComponent {
+            2
+        } else {
+            3
+        })
+    }
+
+    /// Parses the header extension from the bytes immediately following the initial header byte
+    /// of a NAL unit of the given type.  Any bytes in `buf` beyond the length of the extension
+    /// are ignored.
+    pub fn read(unit_type: UnitType, buf: &[u8]) -> Result<NalHeaderExtension, NalHeaderExtensionError> {
+        let first_byte = *buf.first()
+            .ok_or(NalHeaderExtensionError::NotEnoughData { expected: 1, actual: 0 })?;
+        let len = Self::byte_len_for(unit_type, first_byte)?;
+        if buf.len() < len {
+            return Err(NalHeaderExtensionError::NotEnoughData { expected: len, actual: buf.len() });
+        }
+        // the 1-bit flag, followed by the 23- or 15-bit extension
+        let bits = u32::from(buf[0]) << 16 | u32::from(buf[1]) << 8 | u32::from(if len == 3 { buf[2] } else { 0 });
+        let bit = |n: u32| bits & (1 << (23 - n)) != 0;
+        let field = |n: u32, width: u32| (bits >> (24 - n - width)) & ((1 << width) - 1);
+        Ok(if !bit(0) {
+            NalHeaderExtension::Mvc(MvcExtension {
+                non_idr_flag: bit(1),
+                priority_id: field(2, 6) as u8,
+                view_id: field(8, 10) as u16,
+                temporal_id: field(18, 3) as u8,
+                anchor_pic_flag: bit(21),
+                inter_view_flag: bit(22),
+                // reserved_one_bit
+            })
+        } else if len == 2 {
+            NalHeaderExtension::Avc3d(Avc3dExtension {
+                view_idx: field(1, 8) as u8,
+                depth_flag: bit(9),
+                non_idr_flag: bit(10),
+                temporal_id: field(11, 3) as u8,
+                anchor_pic_flag: bit(14),
+                inter_view_flag: bit(15),
+            })
+        } else {
+            NalHeaderExtension::Svc(SvcExtension {
+                idr_flag: bit(1),
+                priority_id: field(2, 6) as u8,
+                no_inter_layer_pred_flag: bit(8),
+                dependency_id: field(9, 3) as u8,
+                quality_id: field(12, 4) as u8,
+                temporal_id: field(16, 3) as u8,
+                use_ref_base_pic_flag: bit(19),
+                discardable_flag: bit(20),
+                output_flag: bit(21),
+                // reserved_three_2bits
+            })
+        })
+    }
+
+    /// The number of bytes occupied by this extension (including the flag bit that selects the
+    /// extension type).
+    pub fn byte_len(&self) -> usize {
+        match self {
+            NalHeaderExtension::Svc(_) | NalHeaderExtension::Mvc(_) => 3,
+            NalHeaderExtension::Avc3d(_) => 2,
+        }
+    }
+
+    pub fn temporal_id(&self) -> u8 {
+        match self {
+            NalHeaderExtension::Svc(e) => e.temporal_id,
+            NalHeaderExtension::Mvc(e) => e.temporal_id,
+            NalHeaderExtension::Avc3d(e) => e.temporal_id,
+        }
+    }
+}
+
+/// Like [`NalHandler`](../trait.NalHandler.html), but `start()` is additionally given the NAL
+/// unit header extension, for those NAL unit types which have one.
+pub trait ExtendedNalHandler {
+    type Ctx;
+
+    /// `extension` is `None` for NAL unit types which do not have a header extension.
+    fn start(&mut self, ctx: &mut Context<Self::Ctx>, header: NalHeader, extension: Option<NalHeaderExtension>) -> NalFlow;
+    /// receives the NAL unit data following the header extension
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow;
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) -> NalFlow;
+}
+
+enum ExtensionState {
+    Idle,
+    /// buffering the header extension bytes
+    Reading { header: NalHeader, buf: [u8; 3], len: usize },
+    Started,
+    Ignoring,
+}
+
+/// A `NalHandler` that parses the NAL unit header extension (if the NAL unit type has one) before
+/// passing the NAL unit on to the wrapped `ExtendedNalHandler`.
+///
+/// This must be given the NAL unit data as it appears in the bitstream, still including emulation
+/// prevention bytes (see the [module documentation](index.html)), and the wrapped handler is
+/// given the data in the same form.
+///
+/// NAL units with a header extension that is truncated or malformed are reported with `eprintln!`
+/// and not passed to the wrapped handler.
+pub struct NalHeaderExtensionReader<H: ExtendedNalHandler> {
+    state: ExtensionState,
+    handler: H,
+}
+impl<H: ExtendedNalHandler> NalHeaderExtensionReader<H> {
+    pub fn new(handler: H) -> Self {
+        NalHeaderExtensionReader {
+            state: ExtensionState::Idle,
+            handler,
+        }
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    pub fn into_handler(self) -> H {
+        self.handler
+    }
+}
+impl<H: ExtendedNalHandler> NalHandler for NalHeaderExtensionReader<H> {
+    type Ctx = H::Ctx;
+
+    fn start(&mut self, ctx: &mut Context<Self::Ctx>, header: NalHeader) -> NalFlow {
+        if header.nal_unit_type().has_header_extension() {
+            self.state = ExtensionState::Reading { header, buf: [0; 3], len: 0 };
+            NalFlow::Continue
+        } else {
+            self.state = ExtensionState::Started;
+            self.handler.start(ctx, header, None)
+        }
+    }
+
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
+        match self.state {
+            ExtensionState::Started => self.handler.push(ctx, buf),
+            ExtensionState::Reading { header, buf: mut ext_buf, len } => {
+                if buf.is_empty() {
+                    return NalFlow::Continue;
+                }
+                let unit_type = header.nal_unit_type();
+                let first_byte = if len == 0 { buf[0] } else { ext_buf[0] };
+                let needed = match NalHeaderExtension::byte_len_for(unit_type, first_byte) {
+                    Ok(needed) => needed,
+                    Err(e) => {
+                        eprintln!("NalHeaderExtensionReader: {:?}", e);
+                        self.state = ExtensionState::Ignoring;
+                        return NalFlow::Skip;
+                    },
+                };
+                let count = (needed - len).min(buf.len());
+                ext_buf[len..len + count].copy_from_slice(&buf[..count]);
+                let len = len + count;
+                if len < needed {
+                    self.state = ExtensionState::Reading { header, buf: ext_buf, len };
+                    return NalFlow::Continue;
+                }
+                let extension = match NalHeaderExtension::read(unit_type, &ext_buf[..len]) {
+                    Ok(extension) => extension,
+                    Err(e) => {
+                        eprintln!("NalHeaderExtensionReader: {:?}", e);
+                        self.state = ExtensionState::Ignoring;
+                        return NalFlow::Skip;
+                    },
+                };
+                self.state = ExtensionState::Started;
+                match self.handler.start(ctx, header, Some(extension)) {
+                    NalFlow::Continue if count < buf.len() => self.handler.push(ctx, &buf[count..]),
+                    flow => flow,
+                }
+            },
+            ExtensionState::Ignoring => NalFlow::Skip,
+            ExtensionState::Idle => {
+                eprintln!("NalHeaderExtensionReader: no previous call to start()");
+                NalFlow::Skip
+            },
+        }
+    }
+
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) -> NalFlow {
+        let state = std::mem::replace(&mut self.state, ExtensionState::Idle);
+        match state {
+            ExtensionState::Started => self.handler.end(ctx),
+            ExtensionState::Reading { len, .. } => {
+                eprintln!("NalHeaderExtensionReader: NAL unit ended after only {} bytes of header extension", len);
+                NalFlow::Continue
+            },
+            ExtensionState::Ignoring => NalFlow::Continue,
+            ExtensionState::Idle => {
+                eprintln!("NalHeaderExtensionReader: no previous call to start()");
+                NalFlow::Continue
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+// byte literals are grouped by syntax element
+#[allow(clippy::unusual_byte_groupings)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn svc() {
+        // svc_extension_flag=1, idr_flag=1, priority_id=5, no_inter_layer_pred_flag=1,
+        // dependency_id=2, quality_id=3, temporal_id=4, use_ref_base_pic_flag=0,
+        // discardable_flag=1, output_flag=1, reserved_three_2bits=3
+        let ext = NalHeaderExtension::read(UnitType::SliceExtension, &[0b1_1_000101, 0b1_010_0011, 0b100_0_1_1_11]).unwrap();
+        assert_eq!(ext, NalHeaderExtension::Svc(SvcExtension {
+            idr_flag: true,
+            priority_id: 5,
+            no_inter_layer_pred_flag: true,
+            dependency_id: 2,
+            quality_id: 3,
+            temporal_id: 4,
+            use_ref_base_pic_flag: false,
+            discardable_flag: true,
+            output_flag: true,
+        }));
+        assert_eq!(ext.byte_len(), 3);
+    }
+
+    #[test]
+    fn mvc() {
+        // svc_extension_flag=0, non_idr_flag=1, priority_id=0, view_id=513, temporal_id=2,
+        // anchor_pic_flag=1, inter_view_flag=1, reserved_one_bit=1
+        let ext = NalHeaderExtension::read(UnitType::PrefixNALUnit, &[0b0_1_000000, 0b10000000, 0b01_010_1_1_1]).unwrap();
+        assert_eq!(ext, NalHeaderExtension::Mvc(MvcExtension {
+            non_idr_flag: true,
+            priority_id: 0,
+            view_id: 513,
+            temporal_id: 2,
+            anchor_pic_flag: true,
+            inter_view_flag: true,
+        }));
+        assert_eq!(ext.temporal_id(), 2);
+    }
+
+    #[test]
+    fn avc_3d() {
+        // avc_3d_extension_flag=1, view_idx=3, depth_flag=1, non_idr_flag=0, temporal_id=1,
+        // anchor_pic_flag=0, inter_view_flag=1
+        let ext = NalHeaderExtension::read(UnitType::SliceExtensionViewComponent, &[0b1_0000001, 0b1_1_0_001_0_1, 0xff]).unwrap();
+        assert_eq!(ext, NalHeaderExtension::Avc3d(Avc3dExtension {
+            view_idx: 3,
+            depth_flag: true,
+            non_idr_flag: false,
+            temporal_id: 1,
+            anchor_pic_flag: false,
+            inter_view_flag: true,
+        }));
+        assert_eq!(ext.byte_len(), 2);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            NalHeaderExtension::read(UnitType::SeqParameterSet, &[0, 0, 0]),
+            Err(NalHeaderExtensionError::NotExtendedUnitType(UnitType::SeqParameterSet))
+        );
+        assert_eq!(
+            NalHeaderExtension::read(UnitType::SliceExtension, &[0x80, 0]),
+            Err(NalHeaderExtensionError::NotEnoughData { expected: 3, actual: 2 })
+        );
+        assert_eq!(
+            NalHeaderExtension::read(UnitType::SliceExtension, &[]),
+            Err(NalHeaderExtensionError::NotEnoughData { expected: 1, actual: 0 })
+        );
+    }
+
+    #[derive(Default)]
+    struct MockHandler {
+        extension: Option<NalHeaderExtension>,
+        data: Vec<u8>,
+        ended: bool,
+    }
+    impl ExtendedNalHandler for MockHandler {
+        type Ctx = ();
+
+        fn start(&mut self, _ctx: &mut Context<Self::Ctx>, _header: NalHeader, extension: Option<NalHeaderExtension>) -> NalFlow {
+            self.extension = extension;
+            NalFlow::Continue
+        }
+
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
+            self.data.extend_from_slice(buf);
+            NalFlow::Continue
+        }
+
+        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
+            self.ended = true;
+            NalFlow::Continue
+        }
+    }
+
+    #[test]
+    fn reader_split_extension() {
+        let mut ctx = Context::default();
+        let mut r = NalHeaderExtensionReader::new(MockHandler::default());
+        let header = NalHeader::new(0x14).unwrap();
+        assert_eq!(r.start(&mut ctx, header), NalFlow::Continue);
+        assert_eq!(r.push(&mut ctx, &[0b0_1_000000]), NalFlow::Continue);
+        assert_eq!(r.push(&mut ctx, &[]), NalFlow::Continue);
+        assert!(r.handler().extension.is_none());
+        assert_eq!(r.push(&mut ctx, &[0b10000000, 0b01_010_1_1_1, 0xaa]), NalFlow::Continue);
+        assert_eq!(r.push(&mut ctx, &[0xbb]), NalFlow::Continue);
+        assert_eq!(r.end(&mut ctx), NalFlow::Continue);
+        let h = r.into_handler();
+        match h.extension {
+            Some(NalHeaderExtension::Mvc(MvcExtension { view_id, .. })) => assert_eq!(view_id, 513),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(&h.data[..], &[0xaa, 0xbb][..]);
+        assert!(h.ended);
+    }
+
+    #[test]
+    fn reader_no_extension() {
+        let mut ctx = Context::default();
+        let mut r = NalHeaderExtensionReader::new(MockHandler::default());
+        r.start(&mut ctx, NalHeader::new(0x65).unwrap());
+        r.push(&mut ctx, &[0x88, 0x84]);
+        r.end(&mut ctx);
+        let h = r.into_handler();
+        assert!(h.extension.is_none());
+        assert_eq!(&h.data[..], &[0x88, 0x84][..]);
+        assert!(h.ended);
+    }
+
+    #[test]
+    fn reader_truncated() {
+        let mut ctx = Context::default();
+        let mut r = NalHeaderExtensionReader::new(MockHandler::default());
+        r.start(&mut ctx, NalHeader::new(0x0e).unwrap());
+        r.push(&mut ctx, &[0x80, 0x00]);
+        r.end(&mut ctx);
+        let h = r.into_handler();
+        assert!(h.extension.is_none());
+        assert!(!h.ended);
+    }
+}
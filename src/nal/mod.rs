@@ -5,9 +5,16 @@
 //! 'emulation prevention bytes'.
 
 pub mod sps;
+pub mod sps_extension;
+pub mod subset_sps;
 pub mod pps;
 pub mod sei;
 pub mod slice;
+pub mod header_extension;
+pub mod aud;
+pub mod aux_slice;
+pub mod eos;
+pub mod filler;
 
 use crate::annexb::NalReader;
 use std::cell::RefCell;
@@ -101,6 +108,12 @@ impl UnitType {
             UnitType::Reserved(v) => v,
         }
     }
+
+    /// True for the NAL unit types whose header continues beyond the first byte with one of the
+    /// extensions defined in the [`header_extension`](header_extension/index.html) module.
+    pub fn has_header_extension(self) -> bool {
+        matches!(self, UnitType::PrefixNALUnit | UnitType::SliceExtension | UnitType::SliceExtensionViewComponent)
+    }
 }
 
 #[derive(Debug)]
@@ -109,7 +122,7 @@ pub enum UnitTypeError {
     ValueOutOfRange(u8)
 }
 
-#[derive(Copy,Clone)]
+#[derive(Copy,Clone,PartialEq,Eq)]
 pub struct NalHeader ( u8 );
 
 #[derive(Debug)]
@@ -148,13 +161,21 @@ impl fmt::Debug for NalHeader {
     }
 }
 
+/// Tracks which handler, if any, is receiving the data of the current NAL unit.
+///
+/// Only public so that it may be used by the code that [`nal_switch!`](../macro.nal_switch.html)
+/// generates.
 #[derive(Debug)]
-enum NalSwitchState {
+pub enum NalSwitchState {
     Start,
     Handling(UnitType),
     Ignoring,
 }
-// TODO: generate enum at compile time rather than Vec<Box<>>
+/// A `NalReader` that passes each NAL unit to the handler registered for its `UnitType`.
+///
+/// Handlers are looked up at runtime; where the set of handlers is known at compile time, the
+/// [`nal_switch!`](../macro.nal_switch.html) macro can generate an equivalent type that avoids
+/// the boxing and `RefCell` borrow checks.
 pub struct NalSwitch<Ctx> {
     readers_by_id: Vec<Option<Box<RefCell<dyn NalHandler<Ctx=Ctx>>>>>,
     state: NalSwitchState,
@@ -188,41 +209,160 @@ impl<Ctx> NalSwitch<Ctx> {
 impl<Ctx> NalReader for NalSwitch<Ctx> {
     type Ctx = Ctx;
 
-    fn start(&mut self, _ctx: &mut Context<Ctx>) {
+    fn start(&mut self, _ctx: &mut Context<Ctx>) -> NalFlow {
         self.state = NalSwitchState::Start;
+        NalFlow::Continue
     }
 
-    fn push(&mut self, ctx: &mut Context<Ctx>, buf: &[u8]) {
+    fn push(&mut self, ctx: &mut Context<Ctx>, buf: &[u8]) -> NalFlow {
         if buf.is_empty() {
-            return;
+            return NalFlow::Continue;
         }
         match self.state {
             NalSwitchState::Start => {
-                let header = NalHeader::new(buf[0]).unwrap();
-                self.state = if let Some(ref handler) = self.get_handler(header.nal_unit_type()) {
-                    handler.borrow_mut().start(ctx, header);
-                    handler.borrow_mut().push(ctx, &buf[1..]);
-                    NalSwitchState::Handling(header.nal_unit_type())
+                let header = match NalHeader::new(buf[0]) {
+                    Ok(header) => header,
+                    Err(e) => {
+                        eprintln!("Bad NAL header: {:?}", e);
+                        self.state = NalSwitchState::Ignoring;
+                        return NalFlow::Skip;
+                    },
+                };
+                let (state, flow) = if let Some(ref handler) = self.get_handler(header.nal_unit_type()) {
+                    let flow = handler.borrow_mut().start(ctx, header);
+                    let flow = match flow {
+                        NalFlow::Continue => handler.borrow_mut().push(ctx, &buf[1..]),
+                        flow => flow,
+                    };
+                    (NalSwitchState::Handling(header.nal_unit_type()), flow)
                 } else {
-                    NalSwitchState::Ignoring
-                }
+                    // no handler wants this NAL unit, so the caller can avoid passing us the rest
+                    (NalSwitchState::Ignoring, NalFlow::Skip)
+                };
+                self.state = state;
+                flow
             },
-            NalSwitchState::Ignoring => (),
+            NalSwitchState::Ignoring => NalFlow::Skip,
             NalSwitchState::Handling(unit_type) => {
                 if let Some(ref handler) = self.get_handler(unit_type) {
-                    handler.borrow_mut().push(ctx, buf);
+                    handler.borrow_mut().push(ctx, buf)
+                } else {
+                    NalFlow::Skip
                 }
             }
         }
     }
 
-    fn end(&mut self, ctx: &mut Context<Ctx>) {
+    fn end(&mut self, ctx: &mut Context<Ctx>) -> NalFlow {
+        let mut flow = NalFlow::Continue;
         if let NalSwitchState::Handling(unit_type) = self.state {
             if let Some(ref handler) = self.get_handler(unit_type) {
-                handler.borrow_mut().end(ctx);
+                flow = handler.borrow_mut().end(ctx);
+            }
+        }
+        self.state = NalSwitchState::Ignoring;
+        flow
+    }
+}
+
+/// Generates a struct implementing `NalReader` which passes each NAL unit to the handler given
+/// for its `UnitType`, with each handler held in a field of the struct (named after the
+/// `UnitType` variant) so that dispatch is static.
+///
+/// ```ignore
+/// nal_switch!{
+///     pub MyNalSwitch<()> {
+///         SeqParameterSet: RbspDecoder<SeqParameterSetNalHandler<()>>
+///             => RbspDecoder::new(SeqParameterSetNalHandler::default()),
+///         PicParameterSet: RbspDecoder<PicParameterSetNalHandler<()>>
+///             => RbspDecoder::new(PicParameterSetNalHandler::default()),
+///     }
+/// }
+/// ```
+///
+/// The struct, and the handler fields, have the visibility given before the struct name (private
+/// if none is given).  NAL units of types not listed are ignored.
+#[macro_export]
+macro_rules! nal_switch {
+    (
+        $vis:vis $struct_name:ident<$ctx:ty> {
+            $( $name:ident : $t:ty => $v:expr ),* $(,)?
+        }
+    ) => {
+        #[allow(non_snake_case)]
+        $vis struct $struct_name {
+            state: $crate::nal::NalSwitchState,
+            $( $vis $name: $t, )*
+        }
+        impl Default for $struct_name {
+            fn default() -> $struct_name {
+                $struct_name {
+                    state: $crate::nal::NalSwitchState::Start,
+                    $( $name: $v, )*
+                }
+            }
+        }
+        impl $crate::annexb::NalReader for $struct_name {
+            type Ctx = $ctx;
+
+            fn start(&mut self, _ctx: &mut $crate::Context<Self::Ctx>) -> $crate::nal::NalFlow {
+                self.state = $crate::nal::NalSwitchState::Start;
+                $crate::nal::NalFlow::Continue
+            }
+
+            fn push(&mut self, ctx: &mut $crate::Context<Self::Ctx>, buf: &[u8]) -> $crate::nal::NalFlow {
+                if buf.is_empty() {
+                    return $crate::nal::NalFlow::Continue;
+                }
+                match self.state {
+                    $crate::nal::NalSwitchState::Start => {
+                        let (state, flow) = match $crate::nal::NalHeader::new(buf[0]) {
+                            Ok(header) => match header.nal_unit_type() {
+                                $(
+                                $crate::nal::UnitType::$name => {
+                                    let flow = match $crate::nal::NalHandler::start(&mut self.$name, ctx, header) {
+                                        $crate::nal::NalFlow::Continue => $crate::nal::NalHandler::push(&mut self.$name, ctx, &buf[1..]),
+                                        flow => flow,
+                                    };
+                                    ($crate::nal::NalSwitchState::Handling(header.nal_unit_type()), flow)
+                                },
+                                )*
+                                _ => ($crate::nal::NalSwitchState::Ignoring, $crate::nal::NalFlow::Skip),
+                            },
+                            Err(e) => {
+                                eprintln!("Bad NAL header: {:?}", e);
+                                ($crate::nal::NalSwitchState::Ignoring, $crate::nal::NalFlow::Skip)
+                            },
+                        };
+                        self.state = state;
+                        flow
+                    },
+                    $crate::nal::NalSwitchState::Ignoring => $crate::nal::NalFlow::Skip,
+                    $crate::nal::NalSwitchState::Handling(unit_type) => {
+                        match unit_type {
+                            $(
+                            $crate::nal::UnitType::$name => $crate::nal::NalHandler::push(&mut self.$name, ctx, buf),
+                            )*
+                            _ => $crate::nal::NalFlow::Skip,
+                        }
+                    },
+                }
+            }
+
+            fn end(&mut self, ctx: &mut $crate::Context<Self::Ctx>) -> $crate::nal::NalFlow {
+                let flow = match self.state {
+                    $crate::nal::NalSwitchState::Handling(unit_type) => match unit_type {
+                        $(
+                        $crate::nal::UnitType::$name => $crate::nal::NalHandler::end(&mut self.$name, ctx),
+                        )*
+                        _ => $crate::nal::NalFlow::Continue,
+                    },
+                    _ => $crate::nal::NalFlow::Continue,
+                };
+                self.state = $crate::nal::NalSwitchState::Ignoring;
+                flow
             }
         }
-        self.state = NalSwitchState::Ignoring
     }
 }
 
@@ -230,9 +370,161 @@ impl<Ctx> NalReader for NalSwitch<Ctx> {
 pub trait NalHandler {
     type Ctx;
 
-    fn start(&mut self, ctx: &mut Context<Self::Ctx>, header: NalHeader);
-    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]);
-    fn end(&mut self, ctx: &mut Context<Self::Ctx>);
+    fn start(&mut self, ctx: &mut Context<Self::Ctx>, header: NalHeader) -> NalFlow;
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow;
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) -> NalFlow;
+}
+
+/// Returned by the methods of `NalHandler` and `NalReader` to tell the caller how to proceed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalFlow {
+    /// Keep passing data to this handler as normal.
+    Continue,
+    /// The handler does not need any further data for the current NAL unit.  No more calls to
+    /// `push()` will be made for this NAL unit, but `end()` will still be called, and the next
+    /// NAL unit will be processed as usual.
+    Skip,
+    /// Stop processing the stream.  No further calls at all will be made for the current NAL
+    /// unit (not even to `end()`), and an `AnnexBReader` will discard any subsequent data until
+    /// its `start()` method is called again.
+    Abort,
+}
+
+#[derive(Debug)]
+pub enum NalError {
+    /// The NAL unit had no data at all
+    Empty,
+    BadHeader(NalHeaderError),
+    Sps(sps::SpsError),
+    SpsExtension(sps_extension::SpsExtensionError),
+    SubsetSps(subset_sps::SubsetSpsError),
+    Pps(pps::PpsError),
+    Sei(sei::SeiMessageError),
+    Slice(slice::SliceHeaderError),
+    Aud(aud::AudError),
+}
+impl From<NalHeaderError> for NalError {
+    fn from(e: NalHeaderError) -> Self {
+        NalError::BadHeader(e)
+    }
+}
+impl From<sps::SpsError> for NalError {
+    fn from(e: sps::SpsError) -> Self {
+        NalError::Sps(e)
+    }
+}
+impl From<subset_sps::SubsetSpsError> for NalError {
+    fn from(e: subset_sps::SubsetSpsError) -> Self {
+        NalError::SubsetSps(e)
+    }
+}
+impl From<sps_extension::SpsExtensionError> for NalError {
+    fn from(e: sps_extension::SpsExtensionError) -> Self {
+        NalError::SpsExtension(e)
+    }
+}
+impl From<pps::PpsError> for NalError {
+    fn from(e: pps::PpsError) -> Self {
+        NalError::Pps(e)
+    }
+}
+impl From<sei::SeiMessageError> for NalError {
+    fn from(e: sei::SeiMessageError) -> Self {
+        NalError::Sei(e)
+    }
+}
+impl From<slice::SliceHeaderError> for NalError {
+    fn from(e: slice::SliceHeaderError) -> Self {
+        NalError::Slice(e)
+    }
+}
+impl From<aud::AudError> for NalError {
+    fn from(e: aud::AudError) -> Self {
+        NalError::Aud(e)
+    }
+}
+
+/// An owned representation of a whole, parsed NAL unit, for callers that have complete NAL units
+/// to hand and don't need the incremental parsing provided by `NalHandler` implementations.
+#[derive(Debug, Clone)]
+pub enum Nal {
+    Sps(sps::SeqParameterSet),
+    SpsExtension(sps_extension::SeqParameterSetExtension),
+    SubsetSps(subset_sps::SubsetSeqParameterSet),
+    Pps(pps::PicParameterSet),
+    Sei(Vec<sei::SeiMessage>),
+    /// The header of a slice from a `SliceLayerWithoutPartitioningIdr`,
+    /// `SliceLayerWithoutPartitioningNonIdr` or `SliceDataPartitionALayer` NAL unit
+    Slice(slice::SliceHeader, NalHeader),
+    Aud(aud::AccessUnitDelimiter),
+    /// Any NAL unit type not covered by the other variants, with its RBSP data (i.e. the data
+    /// following the header, with emulation prevention bytes removed).  For NAL unit types with
+    /// a header extension, the data begins with the extension bytes as they appeared in the NAL
+    /// unit (see [`header_extension`](header_extension/index.html)).
+    Other(UnitType, Vec<u8>),
+}
+impl Nal {
+    /// Parses a complete NAL unit, given including its header byte, and still containing any
+    /// emulation prevention bytes (e.g. as delimited by start codes in an Annex B stream, or by
+    /// length prefixes in AVCC data).
+    ///
+    /// Parameter sets are also stored in the given `Context` (as `SeqParameterSetNalHandler` and
+    /// `PicParameterSetNalHandler` would do), so that NAL units which refer to them can be
+    /// parsed by subsequent calls.  SPS and PPS NAL units are retained as given, and are
+    /// available from `Context::sps_nal_by_id()` and `pps_nal_by_id()`.
+    ///
+    /// Parsing is lenient in the same way as `SeqParameterSet::from_bytes()` and the like: any
+    /// data following the syntax, in place of `rbsp_trailing_bits()`, is ignored.
+    ///
+    /// Parsing a slice does not change the active SPS; callers tracking access units should call
+    /// `Context::activate_sps()` for the first slice of each.
+    pub fn parse<Ctx>(ctx: &mut Context<Ctx>, nal_unit: &[u8]) -> Result<Nal, NalError> {
+        let header = NalHeader::new(*nal_unit.first().ok_or(NalError::Empty)?)?;
+        let rbsp = match nal_unit.get(1) {
+            // the header extension is not subject to emulation prevention, so must not be
+            // passed through decode_nal()
+            Some(&first_byte) if header.nal_unit_type().has_header_extension() => {
+                let ext_len = header_extension::NalHeaderExtension::byte_len_for(header.nal_unit_type(), first_byte)
+                    .map_or(0, |len| len.min(nal_unit.len() - 1));
+                let mut rbsp = nal_unit[1..1 + ext_len].to_vec();
+                rbsp.extend(crate::rbsp::decode_nal(&nal_unit[1 + ext_len..]));
+                rbsp
+            },
+            _ => crate::rbsp::decode_nal(&nal_unit[1..]),
+        };
+        Ok(match header.nal_unit_type() {
+            UnitType::SeqParameterSet => {
+                let sps = sps::SeqParameterSet::from_bytes(&rbsp[..])?;
+                ctx.put_seq_param_set_with_nal(sps.clone(), nal_unit);
+                Nal::Sps(sps)
+            },
+            UnitType::SeqParameterSetExtension => {
+                let ext = sps_extension::SeqParameterSetExtension::from_bytes(&rbsp[..])?;
+                ctx.put_seq_param_set_ext(ext.clone());
+                Nal::SpsExtension(ext)
+            },
+            UnitType::SubsetSeqParameterSet => {
+                let subset_sps = subset_sps::SubsetSeqParameterSet::from_bytes(&rbsp[..])?;
+                ctx.put_subset_seq_param_set(subset_sps.clone());
+                Nal::SubsetSps(subset_sps)
+            },
+            UnitType::PicParameterSet => {
+                let pps = pps::PicParameterSet::from_bytes(ctx, &rbsp[..])?;
+                ctx.put_pic_param_set_with_nal(pps.clone(), nal_unit);
+                Nal::Pps(pps)
+            },
+            UnitType::SEI => Nal::Sei(sei::SeiMessage::read_all(&rbsp[..])?.messages),
+            UnitType::SliceLayerWithoutPartitioningIdr
+            | UnitType::SliceLayerWithoutPartitioningNonIdr
+            | UnitType::SliceDataPartitionALayer => {
+                let mut r = crate::rbsp::RbspBitReader::new(&rbsp[..]);
+                let (slice_header, _, _) = slice::SliceHeader::read(ctx, &mut r, header)?;
+                Nal::Slice(slice_header, header)
+            },
+            UnitType::AccessUnitDelimiter => Nal::Aud(aud::AccessUnitDelimiter::from_bytes(&rbsp[..])?),
+            other => Nal::Other(other, rbsp),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -251,18 +543,21 @@ mod test {
     impl NalHandler for MockHandler {
         type Ctx = ();
 
-        fn start(&mut self, _ctx: &mut Context<Self::Ctx>, header: NalHeader) {
+        fn start(&mut self, _ctx: &mut Context<Self::Ctx>, header: NalHeader) -> NalFlow {
             assert_eq!(header.nal_unit_type(), UnitType::SeqParameterSet);
+            NalFlow::Continue
         }
 
-        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
             let expected = hex!(
                "64 00 0A AC 72 84 44 26 84 00 00
                 00 04 00 00 00 CA 3C 48 96 11 80");
-            assert_eq!(buf, &expected[..])
+            assert_eq!(buf, &expected[..]);
+            NalFlow::Continue
         }
 
-        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
+        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
+            NalFlow::Continue
         }
     }
 
@@ -277,4 +572,121 @@ mod test {
         let mut ctx = Context::default();
         s.push(&mut ctx, &data[..]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn switch_bad_header() {
+        let mut s = NalSwitch::default();
+        s.put_handler(UnitType::SeqParameterSet, Box::new(RefCell::new(MockHandler)));
+        let mut ctx = Context::default();
+        s.start(&mut ctx);
+        // forbidden_zero_bit is set
+        assert_eq!(s.push(&mut ctx, &[0xe7, 0x64][..]), NalFlow::Skip);
+        assert_eq!(s.end(&mut ctx), NalFlow::Continue);
+    }
+
+    #[derive(Default)]
+    struct CountingHandler {
+        started: u32,
+        data: Vec<u8>,
+        ended: u32,
+    }
+    impl NalHandler for CountingHandler {
+        type Ctx = ();
+
+        fn start(&mut self, _ctx: &mut Context<Self::Ctx>, _header: NalHeader) -> NalFlow {
+            self.started += 1;
+            NalFlow::Continue
+        }
+
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
+            self.data.extend_from_slice(buf);
+            NalFlow::Continue
+        }
+
+        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
+            self.ended += 1;
+            NalFlow::Continue
+        }
+    }
+
+    #[test]
+    fn macro_usage() {
+        nal_switch!{
+            pub(crate) TestNalSwitch<()> {
+                SeqParameterSet: MockHandler => MockHandler,
+                PicParameterSet: CountingHandler => CountingHandler::default()
+            }
+        }
+
+        let mut s = TestNalSwitch::default();
+        let mut ctx = Context::default();
+        let sps = hex!(
+           "67 64 00 0A AC 72 84 44 26 84 00 00
+            00 04 00 00 00 CA 3C 48 96 11 80");
+        s.start(&mut ctx);
+        s.push(&mut ctx, &sps[..]);
+        s.end(&mut ctx);
+        s.start(&mut ctx);
+        s.push(&mut ctx, &[0x68, 0xE8, 0x43][..]);
+        s.push(&mut ctx, &[0x8F, 0x13][..]);
+        s.end(&mut ctx);
+        // a NAL unit type without a handler,
+        s.start(&mut ctx);
+        assert_eq!(s.push(&mut ctx, &[0x65, 0x88, 0x81][..]), NalFlow::Skip);
+        s.end(&mut ctx);
+        assert_eq!(s.PicParameterSet.started, 1);
+        assert_eq!(&s.PicParameterSet.data[..], &[0xE8, 0x43, 0x8F, 0x13][..]);
+        assert_eq!(s.PicParameterSet.ended, 1);
+    }
+
+    #[test]
+    fn parse() {
+        let mut ctx = Context::default();
+        let sps = hex!("6742c01e b91061ff 78088000 00030080 00001971 3006d600 daf7bdc0 7c2211a8");
+        match Nal::parse(&mut ctx, &sps[..]) {
+            Ok(Nal::Sps(sps)) => assert_eq!(sps.seq_parameter_set_id.id(), 0),
+            other => panic!("unexpected {:?}", other),
+        }
+        // retained including its emulation prevention byte
+        assert_eq!(ctx.sps_nal_by_id(sps::SeqParamSetId::from_u32(0).unwrap()), Some(&sps[..]));
+        match Nal::parse(&mut ctx, &hex!("68 de3c80")[..]) {
+            Ok(Nal::Pps(pps)) => assert_eq!(pps.seq_parameter_set_id.id(), 0),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(ctx.pps_by_id(pps::PicParamSetId::from_u32(0).unwrap()).is_some());
+        match Nal::parse(&mut ctx, &hex!("06 05 01 ff 80")[..]) {
+            Ok(Nal::Sei(msgs)) => assert_eq!(msgs[0].payload_type, sei::HeaderType::UserDataUnregistered),
+            other => panic!("unexpected {:?}", other),
+        }
+        match Nal::parse(&mut ctx, &hex!("09 f0")[..]) {
+            Ok(Nal::Aud(aud)) => assert_eq!(aud.primary_pic_type, aud::PrimaryPicType::ISIPSPB),
+            other => panic!("unexpected {:?}", other),
+        }
+        // IDR slice: first_mb_in_slice=0, slice_type=7, pic_parameter_set_id=0, frame_num=0,
+        // idr_pic_id=0, pic_order_cnt_lsb=0, delta_pic_order_cnt_bottom=0,
+        // dec_ref_pic_marking(), slice_qp_delta=0, disable_deblocking_filter_idc=1
+        match Nal::parse(&mut ctx, &hex!("65 88 81 01 2a 80")[..]) {
+            Ok(Nal::Slice(slice_header, header)) => {
+                assert_eq!(header.nal_unit_type(), UnitType::SliceLayerWithoutPartitioningIdr);
+                assert_eq!(slice_header.slice_type.family, slice::SliceFamily::I);
+                assert_eq!(slice_header.idr_pic_id, Some(0));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        match Nal::parse(&mut ctx, &hex!("0c ff 00 00 03 01 80")[..]) {
+            Ok(Nal::Other(UnitType::FillerData, rbsp)) => assert_eq!(rbsp, hex!("ff 00 00 01 80")),
+            other => panic!("unexpected {:?}", other),
+        }
+        // MVC header extension `00 00 03` (view_id=0, inter_view_flag=1) is kept as-is
+        match Nal::parse(&mut ctx, &hex!("14 00 00 03 00 00 03 01")[..]) {
+            Ok(Nal::Other(UnitType::SliceExtension, rbsp)) => {
+                assert_eq!(rbsp, hex!("00 00 03 00 00 01"));
+                let ext = header_extension::NalHeaderExtension::read(UnitType::SliceExtension, &rbsp).unwrap();
+                assert!(matches!(ext, header_extension::NalHeaderExtension::Mvc(e) if e.view_id == 0 && e.inter_view_flag));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(Nal::parse(&mut ctx, &[][..]), Err(NalError::Empty)));
+        assert!(matches!(Nal::parse(&mut ctx, &[0x80][..]), Err(NalError::BadHeader(_))));
+    }
+}
@@ -1,7 +1,9 @@
 use bitreader;
 use super::NalHandler;
 use super::NalHeader;
+use super::NalFlow;
 use super::sps;
+use super::sps::SeqParamSetId;
 use std::marker;
 use crate::{rbsp, Context};
 use crate::rbsp::RbspBitReader;
@@ -12,9 +14,17 @@ pub enum PpsError {
     RbspReaderError(rbsp::RbspBitReaderError),
     InvalidSliceGroupMapType(u32),
     InvalidSliceGroupChangeType(u32),
-    UnknownSeqParamSetId(ParamSetId),
+    UnknownSeqParamSetId(SeqParamSetId),
     BadPicParamSetId(ParamSetIdError),
     BadSeqParamSetId(ParamSetIdError),
+    /// `num_slice_groups_minus1` must be between 0 and 7
+    InvalidNumSliceGroupsMinus1(u32),
+    /// `pic_size_in_map_units_minus1 + 1` did not match the size of the picture in map units given
+    /// by the SPS
+    IncorrectPicSizeInMapUnits { expected: u64, actual: u64 },
+    /// `num_ref_idx_l0_default_active_minus1` or `num_ref_idx_l1_default_active_minus1` was
+    /// outside the expected range of `0` to `31`
+    InvalidNumRefIdx(&'static str, u32),
 }
 
 impl From<bitreader::BitReaderError> for PpsError {
@@ -28,7 +38,7 @@ impl From<rbsp::RbspBitReaderError> for PpsError {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SliceGroupChangeType {
     BoxOut,
     RasterScan,
@@ -45,7 +55,7 @@ impl SliceGroupChangeType {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SliceRect {
     top_left: u32,
     bottom_right: u32,
@@ -59,7 +69,7 @@ impl SliceRect {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SliceGroup {
     Interleaved {
         run_length_minus1: Vec<u32>,
@@ -82,7 +92,7 @@ pub enum SliceGroup {
     },
 }
 impl SliceGroup {
-    fn read(r: &mut RbspBitReader<'_>, num_slice_groups_minus1: u32) -> Result<SliceGroup,PpsError> {
+    fn read(r: &mut RbspBitReader<'_>, num_slice_groups_minus1: u32, sps: &sps::SeqParameterSet) -> Result<SliceGroup,PpsError> {
         let slice_group_map_type = r.read_ue()?;
         match slice_group_map_type {
             0 => Ok(SliceGroup::Interleaved {
@@ -102,7 +112,7 @@ impl SliceGroup {
             }),
             6 => Ok(SliceGroup::ExplicitAssignment {
                 num_slice_groups_minus1,
-                slice_group_id: Self::read_group_ids(r, num_slice_groups_minus1)?,
+                slice_group_id: Self::read_group_ids(r, num_slice_groups_minus1, sps)?,
             }),
             _ => Err(PpsError::InvalidSliceGroupMapType(slice_group_map_type))
         }
@@ -124,21 +134,32 @@ impl SliceGroup {
         Ok(run_length_minus1)
     }
 
-    fn read_group_ids(r: &mut RbspBitReader<'_>, num_slice_groups_minus1: u32) -> Result<Vec<u32>,PpsError> {
+    fn read_group_ids(r: &mut RbspBitReader<'_>, num_slice_groups_minus1: u32, sps: &sps::SeqParameterSet) -> Result<Vec<u32>,PpsError> {
         let pic_size_in_map_units_minus1 = r.read_ue()?;
-        // TODO: avoid any panics due to failed conversions
-        let size = ((1f64+f64::from(pic_size_in_map_units_minus1)).log2()) as u8;
-        let mut run_length_minus1 = Vec::with_capacity(num_slice_groups_minus1 as usize + 1);
-        for _ in 0..num_slice_groups_minus1+1 {
-            run_length_minus1.push(r.read_u32(size)?);
+        let pic_size_in_map_units = u64::from(pic_size_in_map_units_minus1) + 1;
+        let expected = (u64::from(sps.pic_width_in_mbs_minus1) + 1) * (u64::from(sps.pic_height_in_map_units_minus1) + 1);
+        if pic_size_in_map_units != expected {
+            return Err(PpsError::IncorrectPicSizeInMapUnits { expected, actual: pic_size_in_map_units });
         }
-        Ok(run_length_minus1)
+        // Ceil( Log2( num_slice_groups_minus1 + 1 ) ) bits
+        let size = (32 - num_slice_groups_minus1.leading_zeros()) as u8;
+        // not preallocated, since the SPS could give a picture size far larger than the data
+        let mut slice_group_id = vec![];
+        for _ in 0..pic_size_in_map_units {
+            slice_group_id.push(r.read_u32(size)?);
+        }
+        Ok(slice_group_id)
     }
 }
 
-#[derive(Debug, Clone)]
-struct PicScalingMatrix {
-    // TODO
+/// The scaling lists of a PPS whose `pic_scaling_matrix_present_flag` is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PicScalingMatrix {
+    /// The six 4x4 lists, `None` where `pic_scaling_list_present_flag` is unset
+    pub scaling_list4x4: Vec<Option<sps::ScalingList>>,
+    /// The 8x8 lists (none unless `transform_8x8_mode_flag` is set, and otherwise two, or six for
+    /// 4:4:4), `None` where `pic_scaling_list_present_flag` is unset
+    pub scaling_list8x8: Vec<Option<sps::ScalingList>>,
 }
 impl PicScalingMatrix {
     fn read(r: &mut RbspBitReader<'_>, sps: &sps::SeqParameterSet, transform_8x8_mode_flag: bool) -> Result<Option<PicScalingMatrix>,PpsError> {
@@ -148,32 +169,35 @@ impl PicScalingMatrix {
             let mut scaling_list8x8 = vec!();
 
             let count = if transform_8x8_mode_flag {
-                if sps.chroma_info.chroma_format == sps::ChromaFormat::YUV444 { 12 } else { 8 }
+                if sps.chroma_info.chroma_format == sps::ChromaFormat::YUV444 { 6 } else { 2 }
             } else {
                 0
             };
             for i in 0..6+count {
-                let seq_scaling_list_present_flag = r.read_bool()?;
-                if seq_scaling_list_present_flag {
-                    if i < 6 {
-                        scaling_list4x4.push(sps::ScalingList::read(r, 16)?);
-                    } else {
-                        scaling_list8x8.push(sps::ScalingList::read(r, 64)?);
-                    }
+                let pic_scaling_list_present_flag = r.read_bool()?;
+                let list = if pic_scaling_list_present_flag {
+                    Some(sps::ScalingList::read(r, if i < 6 { 16 } else { 64 })?)
+                } else {
+                    None
+                };
+                if i < 6 {
+                    scaling_list4x4.push(list);
+                } else {
+                    scaling_list8x8.push(list);
                 }
             }
-            Some(PicScalingMatrix { })
+            Some(PicScalingMatrix { scaling_list4x4, scaling_list8x8 })
         } else {
             None
         })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PicParameterSetExtra {
-    transform_8x8_mode_flag: bool,
-    pic_scaling_matrix: Option<PicScalingMatrix>,
-    second_chroma_qp_index_offset: i32,
+    pub transform_8x8_mode_flag: bool,
+    pub pic_scaling_matrix: Option<PicScalingMatrix>,
+    pub second_chroma_qp_index_offset: i32,
 }
 impl PicParameterSetExtra {
     fn read(r: &mut RbspBitReader<'_>, sps: &sps::SeqParameterSet) -> Result<Option<PicParameterSetExtra>,PpsError> {
@@ -195,14 +219,15 @@ pub enum ParamSetIdError {
     IdTooLarge(u32)
 }
 
+/// A `pic_parameter_set_id` value, in the range `0` to `255`
 #[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
-pub struct ParamSetId(u8);
-impl ParamSetId {
-    pub fn from_u32(id: u32) -> Result<ParamSetId,ParamSetIdError> {
-        if id > 31 {
+pub struct PicParamSetId(u8);
+impl PicParamSetId {
+    pub fn from_u32(id: u32) -> Result<PicParamSetId,ParamSetIdError> {
+        if id > 255 {
             Err(ParamSetIdError::IdTooLarge(id))
         } else {
-            Ok(ParamSetId(id as u8))
+            Ok(PicParamSetId(id as u8))
         }
     }
     pub fn id(self) -> u8 {
@@ -210,10 +235,10 @@ impl ParamSetId {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PicParameterSet {
-    pub pic_parameter_set_id: ParamSetId,
-    pub seq_parameter_set_id: ParamSetId,
+    pub pic_parameter_set_id: PicParamSetId,
+    pub seq_parameter_set_id: SeqParamSetId,
     pub entropy_coding_mode_flag: bool,
     pub bottom_field_pic_order_in_frame_present_flag: bool,
     pub slice_groups: Option<SliceGroup>,
@@ -230,22 +255,36 @@ pub struct PicParameterSet {
     pub extension: Option<PicParameterSetExtra>,
 }
 impl PicParameterSet {
+    /// Parses the PPS from the given RBSP data, using the SPS it refers to from `ctx`.  Any data
+    /// following the PPS syntax is ignored; see `from_bytes_strict()`.
     pub fn from_bytes<Ctx>(ctx: &Context<Ctx>, buf: &[u8]) -> Result<PicParameterSet, PpsError> {
+        Self::read(ctx, &mut RbspBitReader::new(buf))
+    }
+
+    /// Like `from_bytes()`, but additionally requires that the PPS syntax is followed by
+    /// `rbsp_trailing_bits()`, failing with `PpsError::RbspReaderError` otherwise.
+    pub fn from_bytes_strict<Ctx>(ctx: &Context<Ctx>, buf: &[u8]) -> Result<PicParameterSet, PpsError> {
         let mut r = RbspBitReader::new(buf);
-        let pic_parameter_set_id = ParamSetId::from_u32(r.read_ue_named("pic_parameter_set_id")?)
+        let pps = Self::read(ctx, &mut r)?;
+        r.finish()?;
+        Ok(pps)
+    }
+
+    fn read<Ctx>(ctx: &Context<Ctx>, r: &mut RbspBitReader<'_>) -> Result<PicParameterSet, PpsError> {
+        let pic_parameter_set_id = PicParamSetId::from_u32(r.read_ue_named("pic_parameter_set_id")?)
             .map_err(PpsError::BadPicParamSetId)?;
-        let seq_parameter_set_id = ParamSetId::from_u32(r.read_ue_named("seq_parameter_set_id")?)
+        let seq_parameter_set_id = SeqParamSetId::from_u32(r.read_ue_named("seq_parameter_set_id")?)
             .map_err(PpsError::BadSeqParamSetId)?;
-        let _seq_parameter_set = ctx.sps_by_id(seq_parameter_set_id)
-            .ok_or_else(|| PpsError::UnknownSeqParamSetId(seq_parameter_set_id))?;
+        let seq_parameter_set = ctx.sps_by_id(seq_parameter_set_id)
+            .ok_or(PpsError::UnknownSeqParamSetId(seq_parameter_set_id))?;
         Ok(PicParameterSet {
             pic_parameter_set_id,
             seq_parameter_set_id,
             entropy_coding_mode_flag: r.read_bool()?,
             bottom_field_pic_order_in_frame_present_flag: r.read_bool()?,
-            slice_groups: Self::read_slice_groups(&mut r)?,
-            num_ref_idx_l0_default_active_minus1: r.read_ue()?,
-            num_ref_idx_l1_default_active_minus1: r.read_ue()?,
+            slice_groups: Self::read_slice_groups(r, seq_parameter_set)?,
+            num_ref_idx_l0_default_active_minus1: Self::read_num_ref_idx(r, "num_ref_idx_l0_default_active_minus1")?,
+            num_ref_idx_l1_default_active_minus1: Self::read_num_ref_idx(r, "num_ref_idx_l1_default_active_minus1")?,
             weighted_pred_flag: r.read_bool()?,
             weighted_bipred_idc: r.read_u8(2)?,
             pic_init_qp_minus26: r.read_se()?,
@@ -254,21 +293,53 @@ impl PicParameterSet {
             deblocking_filter_control_present_flag: r.read_bool()?,
             constrained_intra_pred_flag: r.read_bool()?,
             redundant_pic_cnt_present_flag: r.read_bool()?,
-            extension: None, // TODO: buggy?: PicParameterSetExtra::read(&mut r, seq_parameter_set)?,
+            extension: PicParameterSetExtra::read(r, seq_parameter_set)?,
         })
     }
 
-    fn read_slice_groups(r: &mut RbspBitReader<'_>) -> Result<Option<SliceGroup>,PpsError> {
+    /// The effective scaling matrices for pictures using this PPS and the given SPS (which should
+    /// be the one that this PPS refers to), after applying the fall-back rules of Table 7-2.
+    pub fn scaling_matrices(&self, sps: &sps::SeqParameterSet) -> sps::ScalingMatrices {
+        let seq = sps.scaling_matrices();
+        match self.extension.as_ref().and_then(|e| e.pic_scaling_matrix.as_ref()) {
+            Some(pic) => {
+                // fall-back rule B refers to the sequence-level lists, but if the SPS had none,
+                // rule A (the defaults) applies
+                let fallback = if sps.chroma_info.scaling_matrix.is_some() {
+                    seq
+                } else {
+                    sps::ScalingMatrices::default_matrices()
+                };
+                sps::ScalingMatrices::resolve(&pic.scaling_list4x4, &pic.scaling_list8x8, &fallback)
+            },
+            None => seq,
+        }
+    }
+
+    fn read_slice_groups(r: &mut RbspBitReader<'_>, sps: &sps::SeqParameterSet) -> Result<Option<SliceGroup>,PpsError> {
         let num_slice_groups_minus1 = r.read_ue()?;
+        if num_slice_groups_minus1 > 7 {
+            return Err(PpsError::InvalidNumSliceGroupsMinus1(num_slice_groups_minus1));
+        }
         Ok(if num_slice_groups_minus1 > 0 {
-            Some(SliceGroup::read(r, num_slice_groups_minus1)?)
+            Some(SliceGroup::read(r, num_slice_groups_minus1, sps)?)
         } else {
             None
         })
     }
+
+    fn read_num_ref_idx(r: &mut RbspBitReader<'_>, name: &'static str) -> Result<u32, PpsError> {
+        let val = r.read_ue_named(name)?;
+        if val > 31 {
+            Err(PpsError::InvalidNumRefIdx(name, val))
+        } else {
+            Ok(val)
+        }
+    }
 }
 
 pub struct PicParameterSetNalHandler<Ctx> {
+    header: Option<NalHeader>,
     buf: Vec<u8>,
     phantom: marker::PhantomData<Ctx>
 }
@@ -276,6 +347,7 @@ pub struct PicParameterSetNalHandler<Ctx> {
 impl<Ctx> Default for PicParameterSetNalHandler<Ctx> {
     fn default() -> Self {
         PicParameterSetNalHandler {
+            header: None,
             buf: Vec::new(),
             phantom: marker::PhantomData,
         }
@@ -284,41 +356,47 @@ impl<Ctx> Default for PicParameterSetNalHandler<Ctx> {
 impl<Ctx> NalHandler for PicParameterSetNalHandler<Ctx> {
     type Ctx = Ctx;
 
-    fn start(&mut self, _ctx: &mut Context<Ctx>, header: NalHeader) {
-        assert_eq!(header.nal_unit_type(), super::UnitType::PicParameterSet);
+    fn start(&mut self, _ctx: &mut Context<Ctx>, header: NalHeader) -> NalFlow {
+        if header.nal_unit_type() != super::UnitType::PicParameterSet {
+            eprintln!("PicParameterSetNalHandler handler given NAL unit type {:?}", header.nal_unit_type());
+            return NalFlow::Skip;
+        }
+        self.header = Some(header);
+        NalFlow::Continue
     }
 
-    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) {
+    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) -> NalFlow {
         self.buf.extend_from_slice(buf);
+        NalFlow::Continue
     }
 
-    fn end(&mut self, ctx: &mut Context<Ctx>) {
+    fn end(&mut self, ctx: &mut Context<Ctx>) -> NalFlow {
         let pps = PicParameterSet::from_bytes(ctx, &self.buf[..]);
-        self.buf.clear();
         match pps {
             Ok(pps) => {
-                ctx.put_pic_param_set(pps);
+                match self.header.take() {
+                    Some(header) => ctx.put_pic_param_set_with_rbsp(pps, header, &self.buf[..]),
+                    None => ctx.put_pic_param_set(pps),
+                };
             },
             Err(e) => {
                 eprintln!("pps: {:?}", e);
             },
         }
+        self.buf.clear();
+        NalFlow::Continue
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::nal::sps::test::{ctx_with_sps, HIGH_PROFILE_SPS};
     use hex_literal::*;
 
     #[test]
     fn test_it() {
-        let sps_data = hex!(
-           "64 00 0A AC 72 84 44 26 84 00 00
-            00 04 00 00 00 CA 3C 48 96 11 80");
-        let sps = super::sps::SeqParameterSet::from_bytes(&sps_data[..]).expect("unexpected test data");
-        let mut ctx = Context::default();
-        ctx.put_seq_param_set(sps);
+        let mut ctx = ctx_with_sps(&HIGH_PROFILE_SPS);
         let data = hex!("E8 43 8F 13 21 30");
         match PicParameterSet::from_bytes(&mut ctx, &data[..]) {
             Err(e) => panic!("failed: {:?}", e),
@@ -329,4 +407,39 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn high_pic_parameter_set_id() {
+        let mut ctx = ctx_with_sps(&HIGH_PROFILE_SPS);
+        // pic_parameter_set_id=200
+        let pps = PicParameterSet::from_bytes(&ctx, &hex!("01 93 b8 f2")).unwrap();
+        assert_eq!(pps.pic_parameter_set_id.id(), 200);
+        ctx.put_pic_param_set(pps);
+        assert!(ctx.pps_by_id(PicParamSetId::from_u32(200).unwrap()).is_some());
+        assert!(PicParamSetId::from_u32(256).is_err());
+        assert!(SeqParamSetId::from_u32(32).is_err());
+    }
+
+    #[test]
+    fn extension() {
+        let ctx = ctx_with_sps(&HIGH_PROFILE_SPS);
+        // transform_8x8_mode_flag=1, pic_scaling_matrix_present_flag=1 with only the Intra Y 4x4
+        // list present (using the default), second_chroma_qp_index_offset=-1
+        let pps = PicParameterSet::from_bytes(&ctx, &hex!("ee 3c e1 10 0e")).unwrap();
+        let ext = pps.extension.as_ref().expect("extension should be present");
+        assert!(ext.transform_8x8_mode_flag);
+        assert_eq!(ext.second_chroma_qp_index_offset, -1);
+        let matrix = ext.pic_scaling_matrix.as_ref().unwrap();
+        assert_eq!(matrix.scaling_list4x4.len(), 6);
+        assert_eq!(matrix.scaling_list8x8.len(), 2);
+        // the SPS has no scaling matrix, so absent lists fall back to the defaults (rule A)
+        let m = pps.scaling_matrices(ctx.sps_by_id(pps.seq_parameter_set_id).unwrap());
+        assert_eq!(m.scaling_list_4x4[2], super::sps::DEFAULT_4X4_INTRA);
+        assert_eq!(m.scaling_list_4x4[3], super::sps::DEFAULT_4X4_INTER);
+        assert_eq!(m.scaling_list_8x8[1], super::sps::DEFAULT_8X8_INTER);
+
+        // trailing cabac_zero_words do not count as more RBSP data
+        let pps = PicParameterSet::from_bytes(&ctx, &hex!("ee 3c 80 00 00")).unwrap();
+        assert!(pps.extension.is_none());
+    }
 }
@@ -1,15 +1,14 @@
 use super::SeiCompletePayloadReader;
 use bitreader;
-use std::marker;
-use crate::nal::pps;
+use crate::nal::{pps, sps};
 use crate::rbsp::RbspBitReader;
 use crate::Context;
 use crate::nal::sei::HeaderType;
 
 #[derive(Debug)]
-enum BufferingPeriodError {
+pub enum BufferingPeriodError {
     ReaderError(bitreader::BitReaderError),
-    UndefinedSeqParamSetId(pps::ParamSetId),
+    UndefinedSeqParamSetId(sps::SeqParamSetId),
     InvalidSeqParamSetId(pps::ParamSetIdError),
 }
 impl From<bitreader::BitReaderError> for BufferingPeriodError {
@@ -23,15 +22,36 @@ impl From<pps::ParamSetIdError> for BufferingPeriodError {
     }
 }
 
-#[derive(Debug)]
-struct InitialCpbRemoval {
+/// The initial CPB removal delay and offset for one of the `SchedSelIdx` values of an HRD.  Both
+/// values are in units of a 90kHz clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InitialCpbRemoval {
     initial_cpb_removal_delay: u32,
     initial_cpb_removal_delay_offset: u32,
 }
+impl InitialCpbRemoval {
+    pub fn new(initial_cpb_removal_delay: u32, initial_cpb_removal_delay_offset: u32) -> InitialCpbRemoval {
+        InitialCpbRemoval {
+            initial_cpb_removal_delay,
+            initial_cpb_removal_delay_offset,
+        }
+    }
+    /// The delay between the arrival in the CPB of the first bit of the first access unit
+    /// following the buffering period, and its removal, in units of a 90kHz clock
+    pub fn initial_cpb_removal_delay(&self) -> u32 {
+        self.initial_cpb_removal_delay
+    }
+    /// Used in combination with the `cpb_removal_delay` to give the initial delivery time of
+    /// access units to the CPB, in units of a 90kHz clock
+    pub fn initial_cpb_removal_delay_offset(&self) -> u32 {
+        self.initial_cpb_removal_delay_offset
+    }
+}
 
-fn read_cpb_removal_delay_list(r: &mut RbspBitReader<'_>, count: usize, length: u8) -> Result<Vec<InitialCpbRemoval>,bitreader::BitReaderError> {
+fn read_cpb_removal_delay_list(r: &mut RbspBitReader<'_>, hrd: &sps::HrdParameters) -> Result<Vec<InitialCpbRemoval>,bitreader::BitReaderError> {
+    let length = hrd.initial_cpb_removal_delay_length_minus1 + 1;
     let mut res = vec!();
-    for _ in 0..count {
+    for _ in 0..hrd.cpb_specs.len() {
         res.push(InitialCpbRemoval {
             initial_cpb_removal_delay: r.read_u32(length)?,
             initial_cpb_removal_delay_offset: r.read_u32(length)?,
@@ -40,64 +60,133 @@ fn read_cpb_removal_delay_list(r: &mut RbspBitReader<'_>, count: usize, length:
     Ok(res)
 }
 
-#[derive(Debug)]
-struct BufferingPeriod {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferingPeriod {
+    seq_parameter_set_id: sps::SeqParamSetId,
     nal_hrd_bp: Option<Vec<InitialCpbRemoval>>,
     vcl_hrd_bp: Option<Vec<InitialCpbRemoval>>,
 }
 impl BufferingPeriod {
-    fn read<Ctx>(ctx: &Context<Ctx>, buf: &[u8]) -> Result<BufferingPeriod,BufferingPeriodError> {
+    /// Reads a `buffering_period` message, activating the SPS it refers to.
+    pub fn read<Ctx>(ctx: &mut Context<Ctx>, buf: &[u8]) -> Result<BufferingPeriod,BufferingPeriodError> {
         let mut r = RbspBitReader::new(buf);
-        let seq_parameter_set_id = pps::ParamSetId::from_u32(r.read_ue()?)?;
+        let seq_parameter_set_id = sps::SeqParamSetId::from_u32(r.read_ue()?)?;
+        if ctx.sps_by_id(seq_parameter_set_id).is_some() {
+            ctx.activate_sps(seq_parameter_set_id);
+        }
         match ctx.sps_by_id(seq_parameter_set_id) {
             None => Err(BufferingPeriodError::UndefinedSeqParamSetId(seq_parameter_set_id)),
             Some(sps) => {
                 let vui = sps.vui_parameters.as_ref();
-                let nal_hrd_bp = if let Some((cpb_removal_delay_length_minus1, nal_cpb_cnt)) = vui
-                    .and_then(|vui_params| vui_params.nal_hrd_parameters.as_ref() )
-                    .map(|nal_hrd_params| (nal_hrd_params.cpb_removal_delay_length_minus1, nal_hrd_params.cpb_specs.len()) )
-                {
-                    Some(read_cpb_removal_delay_list(&mut r, nal_cpb_cnt, cpb_removal_delay_length_minus1+1)?)
-                } else {
-                    None
+                let nal_hrd_bp = match vui.and_then(|vui_params| vui_params.nal_hrd_parameters.as_ref()) {
+                    Some(hrd) => Some(read_cpb_removal_delay_list(&mut r, hrd)?),
+                    None => None,
                 };
-                let vcl_hrd_bp = if let Some((cpb_removal_delay_length_minus1, vcl_cpb_cnt)) = vui
-                    .and_then(|vui_params| vui_params.vcl_hrd_parameters.as_ref() )
-                    .map(|vcl_hrd_params| (vcl_hrd_params.cpb_removal_delay_length_minus1, vcl_hrd_params.cpb_specs.len()) )
-                {
-                    Some(read_cpb_removal_delay_list(&mut r, vcl_cpb_cnt, cpb_removal_delay_length_minus1+1)?)
-                } else {
-                    None
+                let vcl_hrd_bp = match vui.and_then(|vui_params| vui_params.vcl_hrd_parameters.as_ref()) {
+                    Some(hrd) => Some(read_cpb_removal_delay_list(&mut r, hrd)?),
+                    None => None,
                 };
 
                 Ok(BufferingPeriod {
+                    seq_parameter_set_id,
                     nal_hrd_bp,
                     vcl_hrd_bp,
                 })
             }
         }
     }
+
+    /// The id of the SPS that this message refers to (which it makes the active SPS)
+    pub fn seq_parameter_set_id(&self) -> sps::SeqParamSetId {
+        self.seq_parameter_set_id
+    }
+    /// The values for each `SchedSelIdx` of the NAL HRD, if the SPS has NAL HRD parameters
+    pub fn nal_hrd_bp(&self) -> Option<&[InitialCpbRemoval]> {
+        self.nal_hrd_bp.as_deref()
+    }
+    /// The values for each `SchedSelIdx` of the VCL HRD, if the SPS has VCL HRD parameters
+    pub fn vcl_hrd_bp(&self) -> Option<&[InitialCpbRemoval]> {
+        self.vcl_hrd_bp.as_deref()
+    }
 }
-pub struct BufferingPeriodPayloadReader<Ctx> {
-    phantom: marker::PhantomData<Ctx>,
+
+pub trait BufferingPeriodHandler {
+    type Ctx;
+    fn handle(&mut self, ctx: &mut Context<Self::Ctx>, buffering_period: BufferingPeriod);
 }
-impl<Ctx> Default for BufferingPeriodPayloadReader<Ctx> {
-    fn default() -> Self {
+pub struct BufferingPeriodPayloadReader<H: BufferingPeriodHandler> {
+    handler: H,
+}
+impl<H: BufferingPeriodHandler> BufferingPeriodPayloadReader<H> {
+    pub fn new(handler: H) -> Self {
         BufferingPeriodPayloadReader {
-            phantom: marker::PhantomData
+            handler,
         }
     }
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
 }
-impl<Ctx> SeiCompletePayloadReader for BufferingPeriodPayloadReader<Ctx> {
-    type Ctx = Ctx;
+impl<H: BufferingPeriodHandler> SeiCompletePayloadReader for BufferingPeriodPayloadReader<H> {
+    type Ctx = H::Ctx;
 
-    fn header(&mut self, ctx: &mut Context<Ctx>, payload_type: HeaderType, buf: &[u8]) {
-        assert_eq!(payload_type, HeaderType::BufferingPeriod);
+    fn header(&mut self, ctx: &mut Context<Self::Ctx>, payload_type: HeaderType, buf: &[u8]) {
+        if payload_type != HeaderType::BufferingPeriod {
+            eprintln!("Expected buffering_period payload, got {:?}", payload_type);
+            return;
+        }
         match BufferingPeriod::read(ctx, buf) {
             Err(e) => eprintln!("Failure reading buffering_period: {:?}", e),
             Ok(buffering_period) => {
-println!("buffering_period {:#?}", buffering_period);
+                self.handler.handle(ctx, buffering_period);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nal::sps::SeqParameterSet;
+    use crate::nal::sps::test::VCL_HRD_SPS;
+    use hex_literal::*;
+
+    #[test]
+    fn activates_sps() {
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(SeqParameterSet::from_bytes(&hex!("42 00 1e da 0b 13 90")).unwrap());
+        // VCL HRD parameters only
+        ctx.put_seq_param_set(SeqParameterSet::from_bytes(&VCL_HRD_SPS).unwrap());
+        assert!(ctx.active_sps().is_none());
+        // seq_parameter_set_id=1
+        let bp = BufferingPeriod::read(&mut ctx, &hex!("42 46 80 02 10")).unwrap();
+        assert_eq!(ctx.active_sps().map(|sps| sps.seq_parameter_set_id.id()), Some(1));
+        assert_eq!(bp.seq_parameter_set_id().id(), 1);
+        assert!(bp.nal_hrd_bp().is_none());
+        // 16-bit values, as given by initial_cpb_removal_delay_length_minus1 (rather than the
+        // 8 bits of cpb_removal_delay_length_minus1)
+        assert_eq!(bp.vcl_hrd_bp(), Some(&[InitialCpbRemoval::new(0x1234, 0x10)][..]));
+    }
+
+    #[test]
+    fn handler() {
+        struct H(Vec<BufferingPeriod>);
+        impl BufferingPeriodHandler for H {
+            type Ctx = ();
+            fn handle(&mut self, _ctx: &mut Context<()>, buffering_period: BufferingPeriod) {
+                self.0.push(buffering_period);
             }
         }
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(SeqParameterSet::from_bytes(&VCL_HRD_SPS).unwrap());
+        let mut r = BufferingPeriodPayloadReader::new(H(vec![]));
+        r.header(&mut ctx, HeaderType::BufferingPeriod, &hex!("42 46 80 02 10"));
+        r.header(&mut ctx, HeaderType::PicTiming, &hex!("42 46 80 02 10"));
+        // only the BufferingPeriod message is parsed
+        let bps = &r.handler().0;
+        assert_eq!(bps.len(), 1);
+        assert_eq!(bps[0].seq_parameter_set_id().id(), 1);
+        assert!(bps[0].nal_hrd_bp().is_none());
+        assert_eq!(bps[0].vcl_hrd_bp(), Some(&[InitialCpbRemoval::new(0x1234, 0x10)][..]));
     }
-}
\ No newline at end of file
+}
@@ -5,6 +5,7 @@ pub mod pic_timing;
 use crate::Context;
 use crate::nal::NalHandler;
 use crate::nal::NalHeader;
+use crate::nal::NalFlow;
 use crate::rbsp::RbspDecoder;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -152,8 +153,8 @@ macro_rules! sei_switch {
             $( $name: $crate::nal::sei::SeiBuffer<$t>, )*
         }
         impl Default for $struct_name {
-            fn default() -> SeiSwitch {
-                SeiSwitch {
+            fn default() -> $struct_name {
+                $struct_name {
                     current_type: None,
                     $( $name: $crate::nal::sei::SeiBuffer::new($v), )*
                 }
@@ -178,7 +179,7 @@ macro_rules! sei_switch {
                     Some($crate::nal::sei::HeaderType::$name) => self.$name.push(ctx, buf),
                     )*
                     Some(_) => (),
-                    None => panic!("no previous call to start()"),
+                    None => eprintln!("{}: no previous call to start()", stringify!($struct_name)),
                 }
             }
 
@@ -188,7 +189,7 @@ macro_rules! sei_switch {
                     Some($crate::nal::sei::HeaderType::$name) => self.$name.end(ctx),
                     )*
                     Some(_) => (),
-                    None => panic!("no previous call to start()"),
+                    None => eprintln!("{}: no previous call to start()", stringify!($struct_name)),
                 }
                 self.current_type = None;
             }
@@ -255,9 +256,11 @@ impl<R: SeiCompletePayloadReader> SeiIncrementalPayloadReader for SeiBuffer<R> {
     }
 
     fn end(&mut self, ctx: &mut Context<Self::Ctx>) {
-        self.reader.header(ctx, self.payload_type.unwrap(), &self.buf[..]);
+        match self.payload_type.take() {
+            Some(payload_type) => self.reader.header(ctx, payload_type, &self.buf[..]),
+            None => eprintln!("SeiBuffer: no previous call to start()"),
+        }
         self.buf.clear();
-        self.payload_type = None;
     }
 
     fn reset(&mut self, _ctx: &mut Context<Self::Ctx>) {
@@ -280,14 +283,22 @@ impl<R: SeiIncrementalPayloadReader> SeiHeaderReader<R> {
 impl<R: SeiIncrementalPayloadReader> NalHandler for SeiHeaderReader<R> {
     type Ctx = R::Ctx;
 
-    fn start(&mut self, _ctx: &mut Context<Self::Ctx>, header: NalHeader) {
-        assert_eq!(header.nal_unit_type(), crate::nal::UnitType::SEI);
+    fn start(&mut self, _ctx: &mut Context<Self::Ctx>, header: NalHeader) -> NalFlow {
+        if header.nal_unit_type() != crate::nal::UnitType::SEI {
+            eprintln!("SeiHeaderReader given NAL unit type {:?}", header.nal_unit_type());
+            self.state = SeiHeaderState::End;
+            return NalFlow::Skip;
+        }
         self.state = SeiHeaderState::Begin;
+        NalFlow::Continue
     }
 
-    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
-        assert!(!buf.is_empty());
-        let mut input = &buf[..];
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
+        if let SeiHeaderState::End = self.state {
+            eprintln!("SeiHeaderReader: no preceding call to start()");
+            return NalFlow::Skip;
+        }
+        let mut input = buf;
         loop {
             if input.is_empty() {
                 break;
@@ -296,7 +307,9 @@ impl<R: SeiIncrementalPayloadReader> NalHandler for SeiHeaderReader<R> {
             let mut exit = false;
             self.state = match self.state {
                 SeiHeaderState::End => {
-                    panic!("SeiHeaderReader no preceding call to start()");
+                    // checked above
+                    exit = true;
+                    SeiHeaderState::End
                 },
                 SeiHeaderState::Begin => {
                     match b {
@@ -309,7 +322,7 @@ impl<R: SeiIncrementalPayloadReader> NalHandler for SeiHeaderReader<R> {
                     }
                 },
                 SeiHeaderState::PayloadType { payload_type } => {
-                    let new_type = b as u32 + payload_type;
+                    let new_type = payload_type.saturating_add(b as u32);
                     match b {
                         0xff => {
                             SeiHeaderState::PayloadType { payload_type: new_type }
@@ -320,7 +333,7 @@ impl<R: SeiIncrementalPayloadReader> NalHandler for SeiHeaderReader<R> {
                     }
                 },
                 SeiHeaderState::PayloadSize { payload_type, payload_size } => {
-                    let new_size = b as u32 + payload_size;
+                    let new_size = payload_size.saturating_add(b as u32);
                     match b {
                         0xff => {
                             SeiHeaderState::PayloadSize { payload_type, payload_size: new_size }
@@ -359,12 +372,13 @@ impl<R: SeiIncrementalPayloadReader> NalHandler for SeiHeaderReader<R> {
                 input = &input[1..];
             }
         }
+        NalFlow::Continue
     }
 
-    fn end(&mut self, ctx: &mut Context<Self::Ctx>) {
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) -> NalFlow {
         match self.state {
             SeiHeaderState::Begin => (),
-            SeiHeaderState::End => panic!("SeiHeaderReader already ended and end() called again"),
+            SeiHeaderState::End => eprintln!("SeiHeaderReader: end() called without preceding start()"),
             SeiHeaderState::PayloadSize { payload_type: HeaderType::ReservedSeiMessage(0x80), payload_size: 0 } => {
                 // TODO: this is a bit of a hack to ignore rbsp_trailing_bits (which will always
                 //       be 0b10000000 in an SEI payload since SEI messages are byte-aligned).
@@ -383,6 +397,7 @@ impl<R: SeiIncrementalPayloadReader> NalHandler for SeiHeaderReader<R> {
             },
         }
         self.state = SeiHeaderState::End;
+        NalFlow::Continue
     }
 }
 
@@ -400,17 +415,93 @@ impl<R: SeiIncrementalPayloadReader> SeiNalHandler<R> {
 impl<R: SeiIncrementalPayloadReader> NalHandler for SeiNalHandler<R> {
     type Ctx = R::Ctx;
 
-    fn start(&mut self, ctx: &mut Context<Self::Ctx>, header: NalHeader) {
-        assert_eq!(header.nal_unit_type(), super::UnitType::SEI);
-        self.reader.start(ctx, header);
+    fn start(&mut self, ctx: &mut Context<Self::Ctx>, header: NalHeader) -> NalFlow {
+        self.reader.start(ctx, header)
     }
 
-    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
-        self.reader.push(ctx, buf);
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
+        self.reader.push(ctx, buf)
     }
 
-    fn end(&mut self, ctx: &mut Context<Self::Ctx>) {
-        self.reader.end(ctx);
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) -> NalFlow {
+        self.reader.end(ctx)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SeiMessageError {
+    /// The data ended part way through a `payloadType` value
+    EndOfDataInPayloadType,
+    /// The data ended part way through a `payloadSize` value
+    EndOfDataInPayloadSize,
+    /// The data ended before the full `payloadSize` bytes of the payload
+    PayloadTruncated { payload_type: HeaderType, payload_size: u32, available: usize },
+}
+
+/// The messages read by `SeiMessage::read_all()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeiMessages {
+    pub messages: Vec<SeiMessage>,
+    /// `true` if the data following the final message was not the `rbsp_trailing_bits()` which
+    /// should end the `sei_rbsp()`.  The messages are returned regardless.
+    pub missing_trailing_bits: bool,
+}
+
+/// A single SEI message, with its payload held in a buffer of its own
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeiMessage {
+    pub payload_type: HeaderType,
+    pub payload: Vec<u8>,
+}
+impl SeiMessage {
+    /// Reads all the messages from the given `sei_rbsp()` data (i.e. following the NAL header,
+    /// with emulation prevention bytes already removed).
+    ///
+    /// For large payloads arriving in pieces, `SeiHeaderReader` avoids having to buffer the whole
+    /// NAL unit first.
+    pub fn read_all(buf: &[u8]) -> Result<SeiMessages, SeiMessageError> {
+        let mut result = Vec::new();
+        let mut input = buf;
+        // since messages are byte-aligned, more_rbsp_data() is true until only the byte 0x80
+        // (rbsp_trailing_bits()) remains, possibly followed by zero bytes (cabac_zero_words)
+        let missing_trailing_bits = loop {
+            match input.iter().rposition(|&b| b != 0) {
+                None => break true,
+                Some(0) if input[0] == 0x80 => break false,
+                Some(_) => (),
+            }
+            let (payload_type, rest) = Self::read_ff_coded(input)
+                .ok_or(SeiMessageError::EndOfDataInPayloadType)?;
+            let (payload_size, rest) = Self::read_ff_coded(rest)
+                .ok_or(SeiMessageError::EndOfDataInPayloadSize)?;
+            let payload_type = HeaderType::from_id(payload_type);
+            if (payload_size as usize) > rest.len() {
+                return Err(SeiMessageError::PayloadTruncated { payload_type, payload_size, available: rest.len() });
+            }
+            let (payload, rest) = rest.split_at(payload_size as usize);
+            result.push(SeiMessage {
+                payload_type,
+                payload: payload.to_vec(),
+            });
+            input = rest;
+        };
+        Ok(SeiMessages {
+            messages: result,
+            missing_trailing_bits,
+        })
+    }
+
+    /// reads a value coded as a run of `0xff` bytes followed by a final byte, as used for both
+    /// `payloadType` and `payloadSize`
+    fn read_ff_coded(buf: &[u8]) -> Option<(u32, &[u8])> {
+        let mut value = 0u32;
+        for (i, &b) in buf.iter().enumerate() {
+            value = value.saturating_add(u32::from(b));
+            if b != 0xff {
+                return Some((value, &buf[i + 1..]));
+            }
+        }
+        None
     }
 }
 
@@ -425,6 +516,7 @@ mod test {
         started: u32,
         ended: u32,
         data: Vec<u8>,
+        payload_types: Vec<HeaderType>,
     }
     struct MockReader {
         state: Rc<RefCell<State>>
@@ -432,8 +524,10 @@ mod test {
     impl SeiIncrementalPayloadReader for MockReader {
         type Ctx = ();
 
-        fn start(&mut self, _ctx: &mut Context<Self::Ctx>, _payload_type: HeaderType, _payload_size: u32) {
-            self.state.borrow_mut().started += 1;
+        fn start(&mut self, _ctx: &mut Context<Self::Ctx>, payload_type: HeaderType, _payload_size: u32) {
+            let mut state = self.state.borrow_mut();
+            state.started += 1;
+            state.payload_types.push(payload_type);
         }
 
         fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
@@ -496,4 +590,74 @@ mod test {
         assert_eq!(&st.data[..], [0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
         assert_eq!(st.ended, 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn misuse() {
+        let state = Rc::new(RefCell::new(State::default()));
+        let mut r = SeiHeaderReader::new(MockReader{ state: state.clone() });
+        let ctx = &mut Context::default();
+        // no call to start()
+        assert_eq!(r.push(ctx, &[0x01, 0x01, 0x01][..]), NalFlow::Continue);
+        r.end(ctx);
+        assert_eq!(r.push(ctx, &[0x01, 0x01, 0x01][..]), NalFlow::Skip);
+        r.end(ctx);
+        // wrong NAL unit type
+        assert_eq!(r.start(ctx, NalHeader::new(7).unwrap()), NalFlow::Skip);
+        r.end(ctx);
+        // empty buffer
+        r.start(ctx, NalHeader::new(6).unwrap());
+        assert_eq!(r.push(ctx, &[][..]), NalFlow::Continue);
+        r.end(ctx);
+        let st = state.borrow();
+        assert_eq!(st.started, 1);
+        assert_eq!(st.ended, 1);
+    }
+
+    #[test]
+    fn huge_payload_type() {
+        let state = Rc::new(RefCell::new(State::default()));
+        let mut r = SeiHeaderReader::new(MockReader{ state: state.clone() });
+        let ctx = &mut Context::default();
+        r.start(ctx, NalHeader::new(6).unwrap());
+        // rather than pushing ~17 million 0xff bytes, start from a payloadType close to overflow
+        r.state = SeiHeaderState::PayloadType { payload_type: u32::MAX - 0x100 };
+        r.push(ctx, &[0xff, 0xff, 0x00, 0x01, 0xaa][..]);
+        r.end(ctx);
+        let st = state.borrow();
+        assert_eq!(&st.payload_types[..], &[HeaderType::ReservedSeiMessage(u32::MAX)][..]);
+        assert_eq!(&st.data[..], &[0xaa][..]);
+        assert_eq!(st.ended, 1);
+    }
+
+    #[test]
+    fn read_all() {
+        let data = [
+            0x01, 0x01, 0x01,
+            0xff, 0x02, 0x02, 0x02, 0x02,
+            0x80,  // rbsp_trailing_bits
+        ];
+        assert_eq!(SeiMessage::read_all(&data[..]), Ok(SeiMessages {
+            messages: vec![
+                SeiMessage { payload_type: HeaderType::PicTiming, payload: vec![0x01] },
+                SeiMessage { payload_type: HeaderType::ReservedSeiMessage(257), payload: vec![0x02, 0x02] },
+            ],
+            missing_trailing_bits: false,
+        }));
+        assert_eq!(SeiMessage::read_all(&[0x05, 0x03, 0x00][..]), Err(SeiMessageError::PayloadTruncated {
+            payload_type: HeaderType::UserDataUnregistered,
+            payload_size: 3,
+            available: 1,
+        }));
+        assert_eq!(SeiMessage::read_all(&[0xff][..]), Err(SeiMessageError::EndOfDataInPayloadType));
+        assert_eq!(SeiMessage::read_all(&[0x05, 0xff][..]), Err(SeiMessageError::EndOfDataInPayloadSize));
+        // the message is still returned when the trailing bits are missing
+        assert_eq!(SeiMessage::read_all(&[0x01, 0x01, 0x01][..]), Ok(SeiMessages {
+            messages: vec![SeiMessage { payload_type: HeaderType::PicTiming, payload: vec![0x01] }],
+            missing_trailing_bits: true,
+        }));
+        assert_eq!(SeiMessage::read_all(&[0x01, 0x00, 0x80, 0x00, 0x00][..]), Ok(SeiMessages {
+            messages: vec![SeiMessage { payload_type: HeaderType::PicTiming, payload: vec![] }],
+            missing_trailing_bits: false,
+        }));
+    }
+}
@@ -1,22 +1,18 @@
 use crate::nal::sei::SeiCompletePayloadReader;
 use crate::Context;
 use crate::nal::sei::HeaderType;
-use crate::nal::pps::ParamSetId;
 use crate::rbsp::RbspBitReader;
 use crate::nal::sps;
 use crate::rbsp::RbspBitReaderError;
 use bitreader::BitReaderError;
 
-// FIXME: SPS selection
-//      We should really wait until we know what SPS is in use by the frame which follows the
-//      pic_timing header, before trying to decode the pic_timing header.  This would require
-//      storing away the header bytes.  As a bodge, for now we assume frames always use SPS 0.
-
 #[derive(Debug)]
 pub enum PicTimingError {
     ReaderError(BitReaderError),
     RbspError(RbspBitReaderError),
-    UndefinedSeqParamSetId(ParamSetId),
+    /// No SPS has been activated (by a `buffering_period` message or a slice), and the `Context`
+    /// does not hold exactly one SPS which could be assumed to be in use
+    NoActiveSeqParamSet,
     InvalidPicStructId(u8),
 }
 impl From<BitReaderError> for PicTimingError {
@@ -30,13 +26,13 @@ impl From<RbspBitReaderError> for PicTimingError {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Delays {
-    cpb_removal_delay: u32,
-    dpb_output_delay: u32,
+    pub cpb_removal_delay: u32,
+    pub dpb_output_delay: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PicStructType {
     Frame,
     TopField,
@@ -80,6 +76,23 @@ impl PicStructType {
             PicStructType::Reserved(_) => 0,
         }
     }
+
+    /// The `DeltaTfiDivisor` of Table E-6, giving the number of clock ticks (usually field
+    /// periods) for which a picture with this structure is displayed.  `None` for reserved values.
+    pub fn delta_tfi_divisor(&self) -> Option<u32> {
+        match self {
+            PicStructType::Frame => Some(2),
+            PicStructType::TopField => Some(1),
+            PicStructType::BottomField => Some(1),
+            PicStructType::TopFieldBottomField => Some(2),
+            PicStructType::BottomFieldTopField => Some(2),
+            PicStructType::TopFieldBottomFieldTopFieldRepeated => Some(3),
+            PicStructType::BottomFieldTopFieldBottomFieldRepeated => Some(3),
+            PicStructType::FrameDoubling => Some(4),
+            PicStructType::FrameTripling => Some(6),
+            PicStructType::Reserved(_) => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -95,8 +108,7 @@ impl CtType {
             0 => CtType::Progressive,
             1 => CtType::Interlaced,
             2 => CtType::Unknown,
-            3 => CtType::Reserved,
-            _ => panic!("unexpected ct_type {}", id),
+            _ => CtType::Reserved,
         }
     }
 }
@@ -129,8 +141,7 @@ impl CountingType {
             4 => CountingType::DroppingTwoLowest,
             5 => CountingType::DroppingIndividual,
             6 => CountingType::Dropping,
-            7..=31 => CountingType::Reserved(id),
-            _ => panic!("unexpected counting_type {}", id),
+            _ => CountingType::Reserved(id),
         }
     }
 }
@@ -252,23 +263,38 @@ pub struct PicTiming {
     pub pic_struct: Option<PicStruct>,
 }
 impl PicTiming {
+    /// Reads a `pic_timing` message, whose syntax depends on the SPS which is active for the
+    /// access unit it appears in.
+    ///
+    /// A `buffering_period` message in the same access unit (which must precede `pic_timing`)
+    /// activates the SPS it refers to, as does `AccessUnitReader` for the first slice of each
+    /// access unit.  If no SPS has been activated yet, the only SPS held by the `Context` is
+    /// assumed to be in use.
     pub fn read<Ctx>(ctx: &mut Context<Ctx>, buf: &[u8]) -> Result<PicTiming, PicTimingError> {
         let mut r = RbspBitReader::new(buf);
-        let seq_parameter_set_id = ParamSetId::from_u32(0).unwrap();
-        match ctx.sps_by_id(seq_parameter_set_id) {
-            None => Err(PicTimingError::UndefinedSeqParamSetId(seq_parameter_set_id)),
-            Some(sps) => {
-                Ok(PicTiming {
-                    delays: Self::read_delays(&mut r, sps)?,
-                    pic_struct: Self::read_pic_struct(&mut r, sps)?,
-                })
+        let sps = Self::active_sps(ctx).ok_or(PicTimingError::NoActiveSeqParamSet)?;
+        Ok(PicTiming {
+            delays: Self::read_delays(&mut r, sps)?,
+            pic_struct: Self::read_pic_struct(&mut r, sps)?,
+        })
+    }
+
+    fn active_sps<Ctx>(ctx: &Context<Ctx>) -> Option<&sps::SeqParameterSet> {
+        ctx.active_sps().or_else(|| {
+            let mut all = (0..32)
+                .filter_map(|id| ctx.sps_by_id(sps::SeqParamSetId::from_u32(id).unwrap()));
+            match (all.next(), all.next()) {
+                (Some(sps), None) => Some(sps),
+                _ => None,
             }
-        }
+        })
     }
 
     fn read_delays(r: &mut RbspBitReader<'_>, sps: &sps::SeqParameterSet) -> Result<Option<Delays>,PicTimingError> {
         Ok(if let Some(ref vui_params) = sps.vui_parameters {
-            if let Some(ref hrd) = vui_params.nal_hrd_parameters.as_ref().or_else(|| vui_params.nal_hrd_parameters.as_ref() ) {
+            // the lengths in the NAL and VCL HRD parameters are required to be equal, if both are
+            // present
+            if let Some(hrd) = vui_params.nal_hrd_parameters.as_ref().or(vui_params.vcl_hrd_parameters.as_ref()) {
                 Some(Delays {
                     cpb_removal_delay: r.read_u32(hrd.cpb_removal_delay_length_minus1+1)?,
                     dpb_output_delay: r.read_u32(hrd.dpb_output_delay_length_minus1+1)?,
@@ -329,7 +355,10 @@ impl<H: PicTimingHandler> SeiCompletePayloadReader for PicTimingReader<H> {
     type Ctx = H::Ctx;
 
     fn header(&mut self, ctx: &mut Context<Self::Ctx>, payload_type: HeaderType, buf: &[u8]) {
-        assert_eq!(payload_type, HeaderType::PicTiming);
+        if payload_type != HeaderType::PicTiming {
+            eprintln!("Expected pic_timing payload, got {:?}", payload_type);
+            return;
+        }
         match PicTiming::read(ctx, buf) {
             Err(e) => eprintln!("Failure reading pic_timing: {:?}", e),
             Ok(pic_timing) => {
@@ -338,3 +367,35 @@ impl<H: PicTimingHandler> SeiCompletePayloadReader for PicTimingReader<H> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nal::sps::{SeqParameterSet, SeqParamSetId};
+    use crate::nal::sps::test::VCL_HRD_SPS;
+    use hex_literal::*;
+
+    #[test]
+    fn active_sps() {
+        let mut ctx = Context::default();
+        // no VUI
+        ctx.put_seq_param_set(SeqParameterSet::from_bytes(&hex!("42 00 1e da 0b 13 90")).unwrap());
+        ctx.put_seq_param_set(SeqParameterSet::from_bytes(&VCL_HRD_SPS).unwrap());
+        let data = hex!("12 34 04");
+        assert!(matches!(PicTiming::read(&mut ctx, &data), Err(PicTimingError::NoActiveSeqParamSet)));
+
+        ctx.activate_sps(SeqParamSetId::from_u32(1).unwrap());
+        let pic_timing = PicTiming::read(&mut ctx, &data).unwrap();
+        let delays = pic_timing.delays.unwrap();
+        assert_eq!(delays.cpb_removal_delay, 0x12);
+        assert_eq!(delays.dpb_output_delay, 0x34);
+        let pic_struct = pic_timing.pic_struct.unwrap();
+        assert_eq!(pic_struct.pic_struct, PicStructType::Frame);
+        assert!(pic_struct.clock_timestamps[0].is_none());
+
+        ctx.activate_sps(SeqParamSetId::from_u32(0).unwrap());
+        let pic_timing = PicTiming::read(&mut ctx, &data).unwrap();
+        assert!(pic_timing.delays.is_none());
+        assert!(pic_timing.pic_struct.is_none());
+    }
+}
@@ -441,7 +441,10 @@ impl<R: Register> SeiCompletePayloadReader for UserDataRegisteredItuTT35Reader<R
     type Ctx = R::Ctx;
 
     fn header(&mut self, ctx: &mut Context<Self::Ctx>, payload_type: HeaderType, buf: &[u8]) {
-        assert_eq!(payload_type, HeaderType::UserDataRegisteredItuTT35);
+        if payload_type != HeaderType::UserDataRegisteredItuTT35 {
+            eprintln!("Expected user_data_registered_itu_t_t35 payload, got {:?}", payload_type);
+            return;
+        }
         match ItuTT35::read(buf) {
             Ok( (country_code, payload) ) => {
                 self.register.handle(ctx, country_code, payload);
@@ -2,13 +2,14 @@
 use crate::Context;
 use crate::rbsp::RbspBitReader;
 use crate::rbsp::RbspBitReaderError;
-use crate::nal::pps::{ParamSetId, PicParameterSet};
+use crate::nal::pps::{PicParamSetId, PicParameterSet};
 use crate::nal::pps;
 use bitreader::BitReaderError;
 use crate::nal::sps;
 use std::marker;
 use crate::nal::sps::SeqParameterSet;
 use crate::nal::NalHeader;
+use crate::nal::NalFlow;
 
 enum ParseState {
     Unstarted,
@@ -16,28 +17,29 @@ enum ParseState {
     Continue(NalHeader),
 }
 
-#[derive(Debug,PartialEq)]
-enum SliceFamily {
+/// The kinds of slice, ignoring whether all other slices of the picture are of the same kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceFamily {
     P,
     B,
     I,
     SP,
     SI
 }
-#[derive(Debug,PartialEq)]
-enum SliceExclusive {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceExclusive {
     /// All slices in the picture have the same type
     Exclusive,
     /// Other slices in the picture may have a different type than the current slice
     NonExclusive,
 }
-#[derive(Debug, PartialEq)]
-struct SliceType {
-    family: SliceFamily,
-    exclusive: SliceExclusive,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SliceType {
+    pub family: SliceFamily,
+    pub exclusive: SliceExclusive,
 }
 impl SliceType {
-    fn from_id(id: u32) -> Result<SliceType, SliceHeaderError> {
+    pub fn from_id(id: u32) -> Result<SliceType, SliceHeaderError> {
         match id {
             0 => Ok(SliceType { family: SliceFamily::P, exclusive: SliceExclusive::NonExclusive }),
             1 => Ok(SliceType { family: SliceFamily::B, exclusive: SliceExclusive::NonExclusive }),
@@ -59,9 +61,9 @@ pub enum SliceHeaderError {
     ReaderError(BitReaderError),
     RbspError(RbspBitReaderError),
     InvalidSliceType(u32),
-    InvalidSeqParamSetId(pps::ParamSetIdError),
-    UndefinedPicParamSetId(pps::ParamSetId),
-    UndefinedSeqParamSetId(pps::ParamSetId),
+    InvalidPicParamSetId(pps::ParamSetIdError),
+    UndefinedPicParamSetId(PicParamSetId),
+    UndefinedSeqParamSetId(sps::SeqParamSetId),
     ColourPlaneError(ColourPlaneError),
     InvalidModificationOfPicNumIdc(u32),
     InvalidMemoryManagementControlOperation(u32),
@@ -70,6 +72,9 @@ pub enum SliceHeaderError {
     InvalidDisableDeblockingFilterIdc(u32),
     /// `slice_alpha_c0_offset_div2` was outside the expected range of `-6` to `+6`
     InvalidSliceAlphaC0OffsetDiv2(i32),
+    /// `num_ref_idx_l0_active_minus1` or `num_ref_idx_l1_active_minus1` was outside the expected
+    /// range of `0` to `31`
+    InvalidNumRefIdx(&'static str, u32),
     /// The header contained syntax elements that the parser isn't able to handle yet
     UnsupportedSyntax(&'static str),
 }
@@ -85,7 +90,7 @@ impl From<RbspBitReaderError> for SliceHeaderError {
 }
 impl From<pps::ParamSetIdError> for SliceHeaderError {
     fn from(e: pps::ParamSetIdError) -> Self {
-        SliceHeaderError::InvalidSeqParamSetId(e)
+        SliceHeaderError::InvalidPicParamSetId(e)
     }
 }
 impl From<ColourPlaneError> for SliceHeaderError {
@@ -94,8 +99,8 @@ impl From<ColourPlaneError> for SliceHeaderError {
     }
 }
 
-#[derive(Debug)]
-enum ColourPlane {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColourPlane {
     /// Indicates the _chroma_ colour plane
     Y,
     /// Indicates the _blue-difference_ colour plane
@@ -108,7 +113,7 @@ pub enum ColourPlaneError {
     InvalidId(u8),
 }
 impl ColourPlane {
-    fn from_id(id: u8) -> Result<ColourPlane, ColourPlaneError> {
+    pub fn from_id(id: u8) -> Result<ColourPlane, ColourPlaneError> {
         match id {
             0 => Ok(ColourPlane::Y),
             1 => Ok(ColourPlane::Cb),
@@ -118,47 +123,55 @@ impl ColourPlane {
     }
 }
 
-#[derive(Debug,PartialEq)]
-enum Field {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
     Top,
     Bottom,
 }
 
-#[derive(Debug,PartialEq)]
-enum FieldPic {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldPic {
     Frame,
     Field(Field),
 }
 
-#[derive(Debug,PartialEq)]
-enum PicOrderCountLsb {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PicOrderCountLsb {
     Frame(u32),
+    /// `pic_order_cnt_lsb` and `delta_pic_order_cnt_bottom`
     FieldsAbsolute { top: u32, bottom_delta: i32 },
+    /// `delta_pic_order_cnt[0]` and `delta_pic_order_cnt[1]`, for `pic_order_cnt_type` `1`
     FieldsDelta([i32; 2]),
 }
 
-#[derive(Debug)]
-enum NumRefIdxActive {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumRefIdxActive {
     P{ num_ref_idx_l0_active_minus1: u32 },
     B{ num_ref_idx_l0_active_minus1: u32, num_ref_idx_l1_active_minus1: u32 }
 }
 impl NumRefIdxActive {
-    fn num_ref_idx_l0_active_minus1(&self) -> u32 {
+    pub fn num_ref_idx_l0_active_minus1(&self) -> u32 {
         match *self {
             NumRefIdxActive::P { num_ref_idx_l0_active_minus1 } => num_ref_idx_l0_active_minus1,
             NumRefIdxActive::B { num_ref_idx_l0_active_minus1, .. } => num_ref_idx_l0_active_minus1,
         }
     }
+    pub fn num_ref_idx_l1_active_minus1(&self) -> Option<u32> {
+        match *self {
+            NumRefIdxActive::P { .. } => None,
+            NumRefIdxActive::B { num_ref_idx_l1_active_minus1, .. } => Some(num_ref_idx_l1_active_minus1),
+        }
+    }
 }
 
-#[derive(Debug)]
-enum ModificationOfPicNums {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModificationOfPicNums {
     Subtract(u32),
     Add(u32),
     LongTermRef(u32),
 }
-#[derive(Debug)]
-enum RefPicListModifications {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefPicListModifications {
     I,
     P {
         ref_pic_list_modification_l0: Vec<ModificationOfPicNums>,
@@ -202,27 +215,27 @@ impl RefPicListModifications {
     }
 }
 
-#[derive(Debug)]
-struct PredWeight {
-    weight: i32,
-    offset: i32,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PredWeight {
+    pub weight: i32,
+    pub offset: i32,
 }
-#[derive(Debug)]
-struct PredWeightTable {
-    luma_log2_weight_denom: u32,
-    chroma_log2_weight_denom: Option<u32>,
-    luma_weights: Vec<Option<PredWeight>>,
-    chroma_weights: Vec<Vec<PredWeight>>,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PredWeightTable {
+    pub luma_log2_weight_denom: u32,
+    pub chroma_log2_weight_denom: Option<u32>,
+    pub luma_weights: Vec<Option<PredWeight>>,
+    pub chroma_weights: Vec<Vec<PredWeight>>,
+    /// empty unless this is a B slice
+    pub luma_weights_l1: Vec<Option<PredWeight>>,
+    /// empty unless this is a B slice
+    pub chroma_weights_l1: Vec<Vec<PredWeight>>,
 }
+/// luma and chroma weights for a single reference picture list
+type ListWeights = (Vec<Option<PredWeight>>, Vec<Vec<PredWeight>>);
+
 impl PredWeightTable {
-    fn read(r: &mut RbspBitReader<'_>, slice_type: &SliceType, pps: &pps::PicParameterSet, sps: &sps::SeqParameterSet, num_ref_active: &Option<NumRefIdxActive>) -> Result<PredWeightTable, SliceHeaderError> {
-        let chroma_array_type = if sps.chroma_info.separate_colour_plane_flag {
-            // TODO: "Otherwise (separate_colour_plane_flag is equal to 1), ChromaArrayType is
-            //       set equal to 0."  ...does this mean ChromaFormat::Monochrome then?
-            sps::ChromaFormat::Monochrome
-        } else {
-            sps.chroma_info.chroma_format
-        };
+    fn read(r: &mut RbspBitReader<'_>, slice_type: &SliceType, pps: &pps::PicParameterSet, chroma_array_type: sps::ChromaFormat, num_ref_active: &Option<NumRefIdxActive>) -> Result<PredWeightTable, SliceHeaderError> {
         let luma_log2_weight_denom = r.read_ue_named("luma_log2_weight_denom")?;
         let chroma_log2_weight_denom = if chroma_array_type != sps::ChromaFormat::Monochrome {
             Some(r.read_ue_named("chroma_log2_weight_denom")?)
@@ -232,27 +245,50 @@ impl PredWeightTable {
         let num_ref_idx_l0_active_minus1 = num_ref_active
             .as_ref()
             .map(|n| n.num_ref_idx_l0_active_minus1() )
-            .unwrap_or_else(|| {
-                pps.num_ref_idx_l0_default_active_minus1
-            });
-        let mut luma_weights = Vec::with_capacity((num_ref_idx_l0_active_minus1 + 1) as usize);
-        let mut chroma_weights = Vec::with_capacity((num_ref_idx_l0_active_minus1 + 1) as usize);
-        for _ in 0..=num_ref_idx_l0_active_minus1 {
-            if r.read_bool_named("luma_weight_l0_flag")? {
+            .unwrap_or(pps.num_ref_idx_l0_default_active_minus1);
+        let (luma_weights, chroma_weights) = Self::read_weights(r, chroma_array_type, num_ref_idx_l0_active_minus1)?;
+        let (luma_weights_l1, chroma_weights_l1) = if slice_type.family == SliceFamily::B {
+            let num_ref_idx_l1_active_minus1 = num_ref_active
+                .as_ref()
+                .and_then(|n| n.num_ref_idx_l1_active_minus1() )
+                .unwrap_or(pps.num_ref_idx_l1_default_active_minus1);
+            Self::read_weights(r, chroma_array_type, num_ref_idx_l1_active_minus1)?
+        } else {
+            (vec![], vec![])
+        };
+        Ok(PredWeightTable {
+            luma_log2_weight_denom,
+            chroma_log2_weight_denom,
+            luma_weights,
+            chroma_weights,
+            luma_weights_l1,
+            chroma_weights_l1,
+        })
+    }
+
+    /// reads the weights for one of the reference picture lists, `l0` or `l1`
+    fn read_weights(r: &mut RbspBitReader<'_>, chroma_array_type: sps::ChromaFormat, num_ref_idx_active_minus1: u32) -> Result<ListWeights, SliceHeaderError> {
+        if num_ref_idx_active_minus1 > 31 {
+            return Err(SliceHeaderError::InvalidNumRefIdx("num_ref_idx_active_minus1", num_ref_idx_active_minus1));
+        }
+        let mut luma_weights = Vec::with_capacity((num_ref_idx_active_minus1 + 1) as usize);
+        let mut chroma_weights = Vec::with_capacity((num_ref_idx_active_minus1 + 1) as usize);
+        for _ in 0..=num_ref_idx_active_minus1 {
+            if r.read_bool_named("luma_weight_lX_flag")? {
                 luma_weights.push(Some(PredWeight {
-                    weight: r.read_se_named("luma_weight_l0")?,
-                    offset: r.read_se_named("luma_offset_l0")?,
+                    weight: r.read_se_named("luma_weight_lX")?,
+                    offset: r.read_se_named("luma_offset_lX")?,
                 }));
             } else {
                 luma_weights.push(None);
             }
             if chroma_array_type != sps::ChromaFormat::Monochrome {
                 let mut weights = Vec::with_capacity(2); // TODO: just an array?
-                if r.read_bool_named("chroma_weight_l0_flag")? {
+                if r.read_bool_named("chroma_weight_lX_flag")? {
                     for _j in 0..2 {
                         weights.push(PredWeight {
-                            weight: r.read_se_named("chroma_weight_l0")?,
-                            offset: r.read_se_named("chroma_offset_l0")?,
+                            weight: r.read_se_named("chroma_weight_lX")?,
+                            offset: r.read_se_named("chroma_offset_lX")?,
                         });
 
                     }
@@ -260,20 +296,12 @@ impl PredWeightTable {
                 chroma_weights.push(weights);
             }
         }
-        if slice_type.family == SliceFamily::B {
-            unimplemented!("B Frame")
-        }
-        Ok(PredWeightTable {
-            luma_log2_weight_denom,
-            chroma_log2_weight_denom,
-            luma_weights,
-            chroma_weights,
-        })
+        Ok((luma_weights, chroma_weights))
     }
 }
 
-#[derive(Debug)]
-enum MemoryManagementControlOperation {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryManagementControlOperation {
     /// `memory_management_control_operation` value of `1`
     ShortTermUnusedForRef { difference_of_pic_nums_minus1: u32 },
     /// `memory_management_control_operation` value of `2`
@@ -289,8 +317,8 @@ enum MemoryManagementControlOperation {
 }
 
 /// Decoded reference picture marking
-#[derive(Debug)]
-enum DecRefPicMarking {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecRefPicMarking {
     Idr {
         no_output_of_prior_pics_flag: bool,
         long_term_reference_flag: bool,
@@ -301,8 +329,8 @@ enum DecRefPicMarking {
     Adaptive(Vec<MemoryManagementControlOperation>),
 }
 impl DecRefPicMarking {
-    fn read(r: &mut RbspBitReader<'_>, header: NalHeader) -> Result<DecRefPicMarking, SliceHeaderError> {
-        Ok(if header.nal_unit_type() == crate::nal::UnitType::SliceLayerWithoutPartitioningIdr {
+    fn read(r: &mut RbspBitReader<'_>, idr_pic_flag: bool) -> Result<DecRefPicMarking, SliceHeaderError> {
+        Ok(if idr_pic_flag {
             DecRefPicMarking::Idr {
                 no_output_of_prior_pics_flag: r.read_bool_named("no_output_of_prior_pics_flag")?,
                 long_term_reference_flag: r.read_bool_named("long_term_reference_flag")?,
@@ -347,37 +375,62 @@ impl DecRefPicMarking {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SliceHeader {
-    first_mb_in_slice: u32,
-    slice_type: SliceType,
-    colour_plane: Option<ColourPlane>,
-    frame_num: u16,
-    field_pic: FieldPic,
-    idr_pic_id: Option<u32>,
-    pic_order_cnt_lsb: Option<PicOrderCountLsb>,
-    redundant_pic_cnt: Option<u32>,
-    direct_spatial_mv_pred_flag: Option<bool>,
-    num_ref_idx_active: Option<NumRefIdxActive>,
-    ref_pic_list_modification: Option<RefPicListModifications>,  // may become an enum rather than Option in future (for ref_pic_list_mvc_modification)
-    pred_weight_table: Option<PredWeightTable>,
-    dec_ref_pic_marking: Option<DecRefPicMarking>,
-    cabac_init_idc: Option<u32>,
-    slice_qp_delta: i32,
-    sp_for_switch_flag: Option<bool>,
-    slice_qs: Option<u32>,
-    disable_deblocking_filter_idc: u8,
+    pub first_mb_in_slice: u32,
+    pub slice_type: SliceType,
+    pub pic_parameter_set_id: PicParamSetId,
+    pub colour_plane: Option<ColourPlane>,
+    pub frame_num: u16,
+    pub field_pic: FieldPic,
+    pub idr_pic_id: Option<u32>,
+    pub pic_order_cnt_lsb: Option<PicOrderCountLsb>,
+    pub redundant_pic_cnt: Option<u32>,
+    pub direct_spatial_mv_pred_flag: Option<bool>,
+    pub num_ref_idx_active: Option<NumRefIdxActive>,
+    pub ref_pic_list_modification: Option<RefPicListModifications>,  // may become an enum rather than Option in future (for ref_pic_list_mvc_modification)
+    pub pred_weight_table: Option<PredWeightTable>,
+    pub dec_ref_pic_marking: Option<DecRefPicMarking>,
+    pub cabac_init_idc: Option<u32>,
+    pub slice_qp_delta: i32,
+    pub sp_for_switch_flag: Option<bool>,
+    pub slice_qs: Option<u32>,
+    pub disable_deblocking_filter_idc: u8,
 }
 impl SliceHeader {
     pub fn read<'a, Ctx>(ctx: &'a mut Context<Ctx>, r: &mut RbspBitReader<'_>, header: NalHeader) -> Result<(SliceHeader, &'a SeqParameterSet, &'a PicParameterSet), SliceHeaderError> {
+        let idr_pic_flag = header.nal_unit_type() == crate::nal::UnitType::SliceLayerWithoutPartitioningIdr;
+        Self::read_with(ctx, r, header, idr_pic_flag, false)
+    }
+
+    /// Reads the header of a slice of an auxiliary coded picture (from a
+    /// `SliceLayerWithoutPartitioningAux` NAL unit).
+    ///
+    /// The syntax is the same as for the primary coded picture, except that auxiliary pictures are
+    /// always monochrome, and that whether the picture is an IDR picture is not given by the NAL
+    /// unit type but follows that of the primary coded picture in the same access unit, so must be
+    /// supplied as `primary_idr`.
+    pub fn read_auxiliary<'a, Ctx>(ctx: &'a mut Context<Ctx>, r: &mut RbspBitReader<'_>, header: NalHeader, primary_idr: bool) -> Result<(SliceHeader, &'a SeqParameterSet, &'a PicParameterSet), SliceHeaderError> {
+        Self::read_with(ctx, r, header, primary_idr, true)
+    }
+
+    fn read_with<'a, Ctx>(ctx: &'a mut Context<Ctx>, r: &mut RbspBitReader<'_>, header: NalHeader, idr_pic_flag: bool, auxiliary: bool) -> Result<(SliceHeader, &'a SeqParameterSet, &'a PicParameterSet), SliceHeaderError> {
         let first_mb_in_slice = r.read_ue_named("first_mb_in_slice")?;
         let slice_type = SliceType::from_id(r.read_ue_named("slice_type")?)?;
-        let pic_parameter_set_id = ParamSetId::from_u32(r.read_ue_named("pic_parameter_set_id")?)?;
+        let pic_parameter_set_id = PicParamSetId::from_u32(r.read_ue_named("pic_parameter_set_id")?)?;
+        let seq_parameter_set_id = ctx.pps_by_id(pic_parameter_set_id)
+            .ok_or(SliceHeaderError::UndefinedPicParamSetId(pic_parameter_set_id))?
+            .seq_parameter_set_id;
         let pps = ctx.pps_by_id(pic_parameter_set_id)
             .ok_or(SliceHeaderError::UndefinedPicParamSetId(pic_parameter_set_id))?;
-        let sps = ctx.sps_by_id(pps.seq_parameter_set_id)
-            .ok_or(SliceHeaderError::UndefinedSeqParamSetId(pps.seq_parameter_set_id))?;
-        let colour_plane = if sps.chroma_info.separate_colour_plane_flag {
+        let sps = ctx.sps_by_id(seq_parameter_set_id)
+            .ok_or(SliceHeaderError::UndefinedSeqParamSetId(seq_parameter_set_id))?;
+        let chroma_array_type = if auxiliary || sps.chroma_info.separate_colour_plane_flag {
+            sps::ChromaFormat::Monochrome
+        } else {
+            sps.chroma_info.chroma_format
+        };
+        let colour_plane = if sps.chroma_info.separate_colour_plane_flag && !auxiliary {
             Some(ColourPlane::from_id(r.read_u8(2)?)?)
         } else {
             None
@@ -396,7 +449,7 @@ impl SliceHeader {
         } else {
             FieldPic::Frame
         };
-        let idr_pic_id = if header.nal_unit_type() == crate::nal::UnitType::SliceLayerWithoutPartitioningIdr {
+        let idr_pic_id = if idr_pic_flag {
             Some(r.read_ue_named("idr_pic_id")?)
         } else {
             None
@@ -408,7 +461,7 @@ impl SliceHeader {
                     let delta_pic_order_cnt_bottom = r.read_se_named("delta_pic_order_cnt_bottom")?;
                     PicOrderCountLsb::FieldsAbsolute {
                         top: pic_order_cnt_lsb,
-                        bottom_delta: pic_order_cnt_lsb as i32 + delta_pic_order_cnt_bottom,
+                        bottom_delta: delta_pic_order_cnt_bottom,
                     }
                 } else {
                     PicOrderCountLsb::Frame(pic_order_cnt_lsb)
@@ -418,11 +471,14 @@ impl SliceHeader {
                 if delta_pic_order_always_zero_flag {
                     None
                 } else {
-                    Some(PicOrderCountLsb::FieldsDelta([
-                        // TODO: can't remember what field names these are in the spec, to give for debugging
-                        r.read_se_named("FieldsDelta[0]")?,
-                        r.read_se_named("FieldsDelta[1]")?
-                    ]))
+                    let delta_pic_order_cnt0 = r.read_se_named("delta_pic_order_cnt[0]")?;
+                    // when not present, delta_pic_order_cnt[1] is inferred to be 0
+                    let delta_pic_order_cnt1 = if pps.bottom_field_pic_order_in_frame_present_flag && field_pic == FieldPic::Frame {
+                        r.read_se_named("delta_pic_order_cnt[1]")?
+                    } else {
+                        0
+                    };
+                    Some(PicOrderCountLsb::FieldsDelta([delta_pic_order_cnt0, delta_pic_order_cnt1]))
                 }
             },
             sps::PicOrderCntType::TypeTwo => None,
@@ -439,11 +495,11 @@ impl SliceHeader {
         };
         let num_ref_idx_active = if slice_type.family == SliceFamily::P || slice_type.family == SliceFamily::SP || slice_type.family == SliceFamily::B {
             if r.read_bool_named("num_ref_idx_active_override_flag")? {
-                let num_ref_idx_l0_active_minus1 = r.read_ue_named("num_ref_idx_l0_active_minus1")?;
+                let num_ref_idx_l0_active_minus1 = Self::read_num_ref_idx(r, "num_ref_idx_l0_active_minus1")?;
                 Some(if slice_type.family == SliceFamily::B {
                     NumRefIdxActive::B {
                         num_ref_idx_l0_active_minus1,
-                        num_ref_idx_l1_active_minus1: r.read_ue_named("num_ref_idx_l1_active_minus1")?,
+                        num_ref_idx_l1_active_minus1: Self::read_num_ref_idx(r, "num_ref_idx_l1_active_minus1")?,
                     }
                 } else {
                     NumRefIdxActive::P { num_ref_idx_l0_active_minus1 }
@@ -459,16 +515,16 @@ impl SliceHeader {
         } else {
             RefPicListModifications::read(&slice_type.family, r)?
         };
-        let pred_weight_table = if (pps.weighted_pred_flag && slice_type.family == SliceFamily::P || slice_type.family == SliceFamily::SP)
+        let pred_weight_table = if (pps.weighted_pred_flag && (slice_type.family == SliceFamily::P || slice_type.family == SliceFamily::SP))
             || (pps.weighted_bipred_idc == 1 && slice_type.family == SliceFamily::B) {
-            Some(PredWeightTable::read(r, &slice_type, pps, sps, &num_ref_idx_active)?)
+            Some(PredWeightTable::read(r, &slice_type, pps, chroma_array_type, &num_ref_idx_active)?)
         } else {
             None
         };
         let dec_ref_pic_marking = if header.nal_ref_idc() == 0 {
             None
         } else {
-            Some(DecRefPicMarking::read(r, header)?)
+            Some(DecRefPicMarking::read(r, idr_pic_flag)?)
         };
         let cabac_init_idc = if pps.entropy_coding_mode_flag && slice_type.family != SliceFamily::I && slice_type.family != SliceFamily::SI {
             Some(r.read_ue_named("cabac_init_idc")?)
@@ -485,7 +541,7 @@ impl SliceHeader {
                 sp_for_switch_flag = Some(r.read_bool_named("sp_for_switch_flag")?);
             }
             let slice_qs_delta = r.read_se_named("slice_qs_delta")?;
-            let qs_y = 26 + pps.pic_init_qs_minus26 + slice_qs_delta;
+            let qs_y = 26 + i64::from(pps.pic_init_qs_minus26) + i64::from(slice_qs_delta);
             if qs_y < 0 || 51 < qs_y {
                 return Err(SliceHeaderError::InvalidSliceQsDelta(slice_qs_delta))
             }
@@ -513,6 +569,7 @@ impl SliceHeader {
         let header = SliceHeader {
             first_mb_in_slice,
             slice_type,
+            pic_parameter_set_id,
             colour_plane,
             frame_num,
             field_pic,
@@ -532,6 +589,15 @@ impl SliceHeader {
         };
         Ok((header, sps, pps))
     }
+
+    fn read_num_ref_idx(r: &mut RbspBitReader<'_>, name: &'static str) -> Result<u32, SliceHeaderError> {
+        let val = r.read_ue_named(name)?;
+        if val > 31 {
+            Err(SliceHeaderError::InvalidNumRefIdx(name, val))
+        } else {
+            Ok(val)
+        }
+    }
 }
 
 pub struct SliceLayerWithoutPartitioningRbsp<Ctx> {
@@ -541,14 +607,18 @@ pub struct SliceLayerWithoutPartitioningRbsp<Ctx> {
 impl<Ctx> super::NalHandler for SliceLayerWithoutPartitioningRbsp<Ctx> {
     type Ctx = Ctx;
 
-    fn start(&mut self, _ctx: &mut Context<Ctx>, header: NalHeader) {
+    fn start(&mut self, _ctx: &mut Context<Ctx>, header: NalHeader) -> NalFlow {
         println!("SliceLayerWithoutPartitioningRbsp: start()");
         self.state = ParseState::Start(header);
+        NalFlow::Continue
     }
 
-    fn push(&mut self, ctx: &mut Context<Ctx>, buf: &[u8]) {
+    fn push(&mut self, ctx: &mut Context<Ctx>, buf: &[u8]) -> NalFlow {
         match self.state {
-            ParseState::Unstarted => panic!("start() not yet called"),
+            ParseState::Unstarted => {
+                eprintln!("SliceLayerWithoutPartitioningRbsp: start() not yet called");
+                NalFlow::Skip
+            },
             ParseState::Start(header) => {
                 let mut r = RbspBitReader::new(buf);
                 match SliceHeader::read(ctx, &mut r, header) {
@@ -556,15 +626,19 @@ impl<Ctx> super::NalHandler for SliceLayerWithoutPartitioningRbsp<Ctx> {
                     Err(e) => println!("slice_header() error: SliceHeaderError::{:?}", e),
                 }
                 self.state = ParseState::Continue(header);
+                // slice_data() is not yet parsed, so there's no need to see the rest of the NAL
+                NalFlow::Skip
             },
             ParseState::Continue(_header) => {
                 // TODO
+                NalFlow::Skip
             }
         }
     }
 
-    fn end(&mut self, _ctx: &mut Context<Ctx>) {
+    fn end(&mut self, _ctx: &mut Context<Ctx>) -> NalFlow {
         println!("SliceLayerWithoutPartitioningRbsp: end()");
+        NalFlow::Continue
     }
 }
 impl<Ctx> Default for SliceLayerWithoutPartitioningRbsp<Ctx> {
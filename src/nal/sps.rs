@@ -2,12 +2,13 @@
 use crate::rbsp::RbspBitReader;
 use super::NalHandler;
 use super::NalHeader;
+use super::NalFlow;
 use bitreader;
 use crate::Context;
 use crate::rbsp::RbspBitReaderError;
 use std::{marker, fmt};
-use crate::nal::pps::ParamSetId;
 use crate::nal::pps::ParamSetIdError;
+use crate::nal::sei::pic_timing::PicStructType;
 use std::fmt::Debug;
 
 #[derive(Debug, PartialEq)]
@@ -38,7 +39,24 @@ impl From<RbspBitReaderError> for SpsError {
     }
 }
 
+/// A `seq_parameter_set_id` value, in the range `0` to `31`
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct SeqParamSetId(u8);
+impl SeqParamSetId {
+    pub fn from_u32(id: u32) -> Result<SeqParamSetId,ParamSetIdError> {
+        if id > 31 {
+            Err(ParamSetIdError::IdTooLarge(id))
+        } else {
+            Ok(SeqParamSetId(id as u8))
+        }
+    }
+    pub fn id(self) -> u8 {
+        self.0
+    }
+}
+
 pub struct SeqParameterSetNalHandler<Ctx> {
+    header: Option<NalHeader>,
     buf: Vec<u8>,
     phantom: marker::PhantomData<Ctx>
 }
@@ -46,6 +64,7 @@ pub struct SeqParameterSetNalHandler<Ctx> {
 impl<Ctx> Default for SeqParameterSetNalHandler<Ctx> {
     fn default() -> Self {
         SeqParameterSetNalHandler {
+            header: None,
             buf: Vec::new(),
             phantom: marker::PhantomData,
         }
@@ -54,20 +73,30 @@ impl<Ctx> Default for SeqParameterSetNalHandler<Ctx> {
 impl<Ctx> NalHandler for SeqParameterSetNalHandler<Ctx> {
     type Ctx = Ctx;
 
-    fn start(&mut self, _ctx: &mut Context<Ctx>, header: NalHeader) {
-        assert_eq!(header.nal_unit_type(), super::UnitType::SeqParameterSet);
+    fn start(&mut self, _ctx: &mut Context<Ctx>, header: NalHeader) -> NalFlow {
+        if header.nal_unit_type() != super::UnitType::SeqParameterSet {
+            eprintln!("SeqParameterSetNalHandler handler given NAL unit type {:?}", header.nal_unit_type());
+            return NalFlow::Skip;
+        }
+        self.header = Some(header);
+        NalFlow::Continue
     }
 
-    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) {
+    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) -> NalFlow {
         self.buf.extend_from_slice(buf);
+        NalFlow::Continue
     }
 
-    fn end(&mut self, ctx: &mut Context<Ctx>) {
+    fn end(&mut self, ctx: &mut Context<Ctx>) -> NalFlow {
         let sps = SeqParameterSet::from_bytes(&self.buf[..]);
-        self.buf.clear();
         if let Ok(sps) = sps {
-            ctx.put_seq_param_set(sps);
+            match self.header.take() {
+                Some(header) => ctx.put_seq_param_set_with_rbsp(sps, header, &self.buf[..]),
+                None => ctx.put_seq_param_set(sps),
+            };
         }
+        self.buf.clear();
+        NalFlow::Continue
     }
 }
 
@@ -132,7 +161,7 @@ impl Profile {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct ConstraintFlags(u8);
 impl From<u8> for ConstraintFlags {
     fn from(v: u8) -> Self {
@@ -162,7 +191,7 @@ impl Debug for ConstraintFlags {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum Level {
     Unknown(u8),
@@ -183,13 +212,29 @@ pub enum Level {
     L5,
     L5_1,
     L5_2,
+    L6,
+    L6_1,
+    L6_2,
 }
 impl Level {
+    /// Interprets `level_idc` assuming one of the Baseline, Main or Extended profiles, in which
+    /// `constraint_set3_flag` together with `level_idc` of `11` indicates level 1b.  Prefer
+    /// `from_profile_constraint_flags_and_level_idc()` where the profile is known.
     pub fn from_constraint_flags_and_level_idc(constraint_flags: ConstraintFlags, level_idc: u8) -> Level {
+        Self::from_profile_constraint_flags_and_level_idc(ProfileIdc(66), constraint_flags, level_idc)
+    }
+
+    /// Interprets `level_idc` for the given profile.  `level_idc` of `11` indicates level 1b only
+    /// when `constraint_set3_flag` is set in the Baseline, Main and Extended profiles; other
+    /// profiles use `constraint_set3_flag` for other purposes (e.g. the High 10 Intra profile)
+    /// and signal level 1b with `level_idc` of `9`.
+    pub fn from_profile_constraint_flags_and_level_idc(profile_idc: ProfileIdc, constraint_flags: ConstraintFlags, level_idc: u8) -> Level {
         match level_idc {
+            // used for level 1b in profiles where constraint_set3_flag has another meaning
+            9 => Level::L1_b,
             10 => Level::L1,
             11 => {
-                if constraint_flags.flag3() {
+                if constraint_flags.flag3() && matches!(profile_idc.0, 66 | 77 | 88) {
                     Level::L1_b
                 } else {
                     Level::L1_1
@@ -209,6 +254,9 @@ impl Level {
             50 => Level::L5,
             51 => Level::L5_1,
             52 => Level::L5_2,
+            60 => Level::L6,
+            61 => Level::L6_1,
+            62 => Level::L6_2,
             _  => Level::Unknown(level_idc)
         }
     }
@@ -230,12 +278,98 @@ impl Level {
             Level::L5    => 50,
             Level::L5_1  => 51,
             Level::L5_2  => 52,
+            Level::L6    => 60,
+            Level::L6_1  => 61,
+            Level::L6_2  => 62,
             Level::Unknown(level_idc) => level_idc,
         }
     }
+
+    /// The limits that Table A-1 of the spec gives for this level, or `None` for an unknown
+    /// level.
+    pub fn limits(&self) -> Option<LevelLimits> {
+        let (max_mbps, max_fs, max_dpb_mbs, max_br, max_cpb, max_vmv_r, min_cr, max_mvs_per_2mb) = match *self {
+            Level::L1   => (    1485,     99,    396,     64,    175,   64, 2, None),
+            Level::L1_b => (    1485,     99,    396,    128,    350,   64, 2, None),
+            Level::L1_1 => (    3000,    396,    900,    192,    500,  128, 2, None),
+            Level::L1_2 => (    6000,    396,   2376,    384,   1000,  128, 2, None),
+            Level::L1_3 => (   11880,    396,   2376,    768,   2000,  128, 2, None),
+            Level::L2   => (   11880,    396,   2376,   2000,   2000,  128, 2, None),
+            Level::L2_1 => (   19800,    792,   4752,   4000,   4000,  256, 2, None),
+            Level::L2_2 => (   20250,   1620,   8100,   4000,   4000,  256, 2, None),
+            Level::L3   => (   40500,   1620,   8100,  10000,  10000,  256, 2, Some(32)),
+            Level::L3_1 => (  108000,   3600,  18000,  14000,  14000,  512, 4, Some(16)),
+            Level::L3_2 => (  216000,   5120,  20480,  20000,  20000,  512, 4, Some(16)),
+            Level::L4   => (  245760,   8192,  32768,  20000,  25000,  512, 4, Some(16)),
+            Level::L4_1 => (  245760,   8192,  32768,  50000,  62500,  512, 2, Some(16)),
+            Level::L4_2 => (  522240,   8704,  34816,  50000,  62500,  512, 2, Some(16)),
+            Level::L5   => (  589824,  22080, 110400, 135000, 135000,  512, 2, Some(16)),
+            Level::L5_1 => (  983040,  36864, 184320, 240000, 240000,  512, 2, Some(16)),
+            Level::L5_2 => ( 2073600,  36864, 184320, 240000, 240000,  512, 2, Some(16)),
+            Level::L6   => ( 4177920, 139264, 696320, 240000, 240000, 8192, 2, Some(16)),
+            Level::L6_1 => ( 8355840, 139264, 696320, 480000, 480000, 8192, 2, Some(16)),
+            Level::L6_2 => (16711680, 139264, 696320, 800000, 800000, 8192, 2, Some(16)),
+            Level::Unknown(_) => return None,
+        };
+        Some(LevelLimits {
+            max_mbps,
+            max_fs,
+            max_dpb_mbs,
+            max_br,
+            max_cpb,
+            max_vmv_r,
+            min_cr,
+            max_mvs_per_2mb,
+        })
+    }
 }
 
-#[derive(Debug,PartialEq,Clone,Copy)]
+/// Limits on the decoding process imposed by a level (Table A-1 of the spec).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelLimits {
+    /// Maximum macroblock processing rate, in macroblocks per second
+    pub max_mbps: u32,
+    /// Maximum frame size, in macroblocks
+    pub max_fs: u32,
+    /// Maximum decoded picture buffer size, in macroblocks
+    pub max_dpb_mbs: u32,
+    /// Maximum video bit rate, in units of `cpbBrVclFactor` bits per second (see
+    /// `ProfileIdc::cpb_br_vcl_factor()`)
+    pub max_br: u32,
+    /// Maximum CPB size, in units of `cpbBrVclFactor` bits
+    pub max_cpb: u32,
+    /// Vertical motion vector components must be in the range `-max_vmv_r` to
+    /// `max_vmv_r - 0.25`, in luma frame samples
+    pub max_vmv_r: u32,
+    /// Minimum compression ratio
+    pub min_cr: u32,
+    /// Maximum number of motion vectors per two consecutive macroblocks, where limited
+    pub max_mvs_per_2mb: Option<u32>,
+}
+impl LevelLimits {
+    /// The `MaxBR` limit in bits per second of the VCL HRD, for the given profile, or `None`
+    /// if Table A-2 gives no factor for the profile.
+    pub fn max_vcl_bit_rate(&self, profile_idc: ProfileIdc) -> Option<u64> {
+        profile_idc.cpb_br_vcl_factor().map(|f| u64::from(self.max_br) * u64::from(f))
+    }
+
+    /// The `MaxBR` limit in bits per second of the NAL HRD, for the given profile
+    pub fn max_nal_bit_rate(&self, profile_idc: ProfileIdc) -> Option<u64> {
+        profile_idc.cpb_br_nal_factor().map(|f| u64::from(self.max_br) * u64::from(f))
+    }
+
+    /// The `MaxCPB` limit in bits of the VCL HRD, for the given profile
+    pub fn max_vcl_cpb_size(&self, profile_idc: ProfileIdc) -> Option<u64> {
+        profile_idc.cpb_br_vcl_factor().map(|f| u64::from(self.max_cpb) * u64::from(f))
+    }
+
+    /// The `MaxCPB` limit in bits of the NAL HRD, for the given profile
+    pub fn max_nal_cpb_size(&self, profile_idc: ProfileIdc) -> Option<u64> {
+        profile_idc.cpb_br_nal_factor().map(|f| u64::from(self.max_cpb) * u64::from(f))
+    }
+}
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
 pub enum ChromaFormat {
     Monochrome,
     YUV420,
@@ -256,13 +390,35 @@ impl ChromaFormat {
 }
 
 // _Profile Indication_ value
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ProfileIdc(u8);
 impl ProfileIdc {
+    /// True for the profiles whose SPS includes `chroma_format_idc` and related fields
     pub fn has_chroma_info(self) -> bool {
+        matches!(self.0, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135)
+    }
+
+    /// The `cpbBrVclFactor` of Table A-2, by which the `MaxBR` and `MaxCPB` level limits are
+    /// scaled for the VCL HRD.  `None` for profiles that the table doesn't cover.
+    pub fn cpb_br_vcl_factor(self) -> Option<u32> {
+        match self.0 {
+            66 | 77 | 88 => Some(1000),
+            100 => Some(1250),
+            110 => Some(3000),
+            122 | 244 | 44 => Some(4000),
+            _ => None,
+        }
+    }
+
+    /// The `cpbBrNalFactor` of Table A-2, by which the `MaxBR` and `MaxCPB` level limits are
+    /// scaled for the NAL HRD.
+    pub fn cpb_br_nal_factor(self) -> Option<u32> {
         match self.0 {
-            100 | 110 | 122 | 244 | 44 | 83 | 86 => true,
-            _ => false,
+            66 | 77 | 88 => Some(1200),
+            100 => Some(1500),
+            110 => Some(3600),
+            122 | 244 | 44 => Some(4800),
+            _ => None,
         }
     }
 }
@@ -275,36 +431,166 @@ impl From<ProfileIdc> for u8 {
     fn from(v: ProfileIdc) -> Self { v.0 }
 }
 
+/// `Default_4x4_Intra` from Table 7-3, in zig-zag scan order
+pub const DEFAULT_4X4_INTRA: [u8; 16] = [6, 13, 13, 20, 20, 20, 28, 28, 28, 28, 32, 32, 32, 37, 37, 42];
+/// `Default_4x4_Inter` from Table 7-3, in zig-zag scan order
+pub const DEFAULT_4X4_INTER: [u8; 16] = [10, 14, 14, 20, 20, 20, 24, 24, 24, 24, 27, 27, 27, 30, 30, 34];
+/// `Default_8x8_Intra` from Table 7-4, in zig-zag scan order
+pub const DEFAULT_8X8_INTRA: [u8; 64] = [
+     6, 10, 10, 13, 11, 13, 16, 16, 16, 16, 18, 18, 18, 18, 18, 23,
+    23, 23, 23, 23, 23, 25, 25, 25, 25, 25, 25, 25, 27, 27, 27, 27,
+    27, 27, 27, 27, 29, 29, 29, 29, 29, 29, 29, 31, 31, 31, 31, 31,
+    31, 33, 33, 33, 33, 33, 36, 36, 36, 36, 38, 38, 38, 40, 40, 42,
+];
+/// `Default_8x8_Inter` from Table 7-4, in zig-zag scan order
+pub const DEFAULT_8X8_INTER: [u8; 64] = [
+     9, 13, 13, 15, 13, 15, 17, 17, 17, 17, 19, 19, 19, 19, 19, 21,
+    21, 21, 21, 21, 21, 22, 22, 22, 22, 22, 22, 22, 24, 24, 24, 24,
+    24, 24, 24, 24, 25, 25, 25, 25, 25, 25, 25, 27, 27, 27, 27, 27,
+    27, 28, 28, 28, 28, 28, 30, 30, 30, 30, 32, 32, 32, 33, 33, 35,
+];
+
+/// Raster position of each coefficient of a 4x4 block, in zig-zag scan order
+const ZIGZAG_4X4: [usize; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+/// Raster position of each coefficient of an 8x8 block, in zig-zag scan order
+const ZIGZAG_8X8: [usize; 64] = [
+     0,  1,  8, 16,  9,  2,  3, 10, 17, 24, 32, 25, 18, 11,  4,  5,
+    12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13,  6,  7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// A `scaling_list()` as given in an SPS or PPS.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ScalingList {
-    // TODO
+    /// The list values in zig-zag scan order.  Not meaningful if
+    /// `use_default_scaling_matrix_flag` is set.
+    pub values: Vec<u8>,
+    /// Set if the list signals that the default matrix for its position should be used instead
+    pub use_default_scaling_matrix_flag: bool,
 }
 impl ScalingList {
     pub fn read(r: &mut RbspBitReader<'_>, size: u8) -> Result<ScalingList,bitreader::BitReaderError> {
-        let mut scaling_list = vec!();
+        let mut scaling_list = Vec::with_capacity(size as usize);
         let mut last_scale = 8;
         let mut next_scale = 8;
-        let mut _use_default_scaling_matrix_flag = false;
+        let mut use_default_scaling_matrix_flag = false;
         for j in 0..size {
             if next_scale != 0 {
                 let delta_scale = r.read_se()?;
                 next_scale = (last_scale + delta_scale + 256) % 256;
-                _use_default_scaling_matrix_flag = j == 0 && next_scale == 0;
+                use_default_scaling_matrix_flag = j == 0 && next_scale == 0;
             }
             let new_value = if next_scale == 0 { last_scale } else { next_scale };
-            scaling_list.push(new_value);
+            scaling_list.push(new_value as u8);
             last_scale = new_value;
         }
-        Ok(ScalingList { })
+        Ok(ScalingList {
+            values: scaling_list,
+            use_default_scaling_matrix_flag,
+        })
+    }
+
+    fn copy_to(&self, dest: &mut [u8], default: &[u8]) {
+        let src = if self.use_default_scaling_matrix_flag { default } else { &self.values[..] };
+        for (d, s) in dest.iter_mut().zip(src) {
+            *d = *s;
+        }
     }
 }
-#[derive(Debug, Clone)]
-pub struct SeqScalingMatrix {
-    // TODO
+
+/// The effective scaling matrices for a picture, after the fall-back rules of Table 7-2 have
+/// been applied.  Values are in zig-zag scan order, as in the bitstream;
+/// `weight_scale_4x4()` and `weight_scale_8x8()` give them in raster order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScalingMatrices {
+    /// Indexed as Intra Y, Intra Cb, Intra Cr, Inter Y, Inter Cb, Inter Cr
+    pub scaling_list_4x4: [[u8; 16]; 6],
+    /// Indexed as Intra Y, Inter Y, Intra Cb, Inter Cb, Intra Cr, Inter Cr
+    pub scaling_list_8x8: [[u8; 64]; 6],
 }
-impl Default for SeqScalingMatrix {
-    fn default() -> Self {
-        SeqScalingMatrix { }
+impl ScalingMatrices {
+    /// The `Flat_4x4_16` and `Flat_8x8_16` matrices used when no scaling matrix is present.
+    pub fn flat() -> ScalingMatrices {
+        ScalingMatrices {
+            scaling_list_4x4: [[16; 16]; 6],
+            scaling_list_8x8: [[16; 64]; 6],
+        }
+    }
+
+    /// The default matrices of Tables 7-3 and 7-4, as used by fall-back rule A.
+    pub fn default_matrices() -> ScalingMatrices {
+        let mut m = ScalingMatrices::flat();
+        for i in 0..6 {
+            m.scaling_list_4x4[i] = Self::default_4x4(i);
+            m.scaling_list_8x8[i] = Self::default_8x8(i);
+        }
+        m
+    }
+
+    fn default_4x4(i: usize) -> [u8; 16] {
+        if i < 3 { DEFAULT_4X4_INTRA } else { DEFAULT_4X4_INTER }
+    }
+
+    fn default_8x8(i: usize) -> [u8; 64] {
+        if i & 1 == 0 { DEFAULT_8X8_INTRA } else { DEFAULT_8X8_INTER }
+    }
+
+    /// Applies the lists given in an SPS or PPS, where `fallback` supplies the matrices for the
+    /// first intra and inter lists of each size when these are absent (i.e. the defaults for
+    /// fall-back rule A, or the sequence-level matrices for fall-back rule B).
+    pub(crate) fn resolve(lists_4x4: &[Option<ScalingList>], lists_8x8: &[Option<ScalingList>], fallback: &ScalingMatrices) -> ScalingMatrices {
+        let mut m = ScalingMatrices::flat();
+        for i in 0..6 {
+            match lists_4x4.get(i) {
+                Some(Some(list)) => list.copy_to(&mut m.scaling_list_4x4[i], &Self::default_4x4(i)),
+                _ => m.scaling_list_4x4[i] = if i == 0 || i == 3 {
+                    fallback.scaling_list_4x4[i]
+                } else {
+                    m.scaling_list_4x4[i - 1]
+                },
+            }
+        }
+        for i in 0..6 {
+            match lists_8x8.get(i) {
+                Some(Some(list)) => list.copy_to(&mut m.scaling_list_8x8[i], &Self::default_8x8(i)),
+                _ => m.scaling_list_8x8[i] = if i < 2 {
+                    fallback.scaling_list_8x8[i]
+                } else {
+                    m.scaling_list_8x8[i - 2]
+                },
+            }
+        }
+        m
+    }
+
+    /// The `weightScale4x4` matrix for the given 4x4 list index, in raster order.
+    pub fn weight_scale_4x4(&self, i: usize) -> [u8; 16] {
+        let mut result = [0; 16];
+        for (k, &pos) in ZIGZAG_4X4.iter().enumerate() {
+            result[pos] = self.scaling_list_4x4[i][k];
+        }
+        result
     }
+
+    /// The `weightScale8x8` matrix for the given 8x8 list index, in raster order.
+    pub fn weight_scale_8x8(&self, i: usize) -> [u8; 64] {
+        let mut result = [0; 64];
+        for (k, &pos) in ZIGZAG_8X8.iter().enumerate() {
+            result[pos] = self.scaling_list_8x8[i][k];
+        }
+        result
+    }
+}
+
+/// The scaling lists of an SPS whose `seq_scaling_matrix_present_flag` is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqScalingMatrix {
+    /// The six 4x4 lists, `None` where `seq_scaling_list_present_flag` is unset
+    pub scaling_list4x4: Vec<Option<ScalingList>>,
+    /// The two (or for 4:4:4, six) 8x8 lists, `None` where `seq_scaling_list_present_flag` is
+    /// unset
+    pub scaling_list8x8: Vec<Option<ScalingList>>,
 }
 impl SeqScalingMatrix {
     fn read(r: &mut RbspBitReader<'_>, chroma_format_idc: u32) -> Result<SeqScalingMatrix,bitreader::BitReaderError> {
@@ -314,26 +600,38 @@ impl SeqScalingMatrix {
         let count = if chroma_format_idc == 3 { 12 } else { 8 };
         for i in 0..count {
             let seq_scaling_list_present_flag = r.read_bool()?;
-            if seq_scaling_list_present_flag {
-                if i < 6 {
-                    scaling_list4x4.push(ScalingList::read(r, 16)?);
-                } else {
-                    scaling_list8x8.push(ScalingList::read(r, 64)?);
-                }
+            let list = if seq_scaling_list_present_flag {
+                Some(ScalingList::read(r, if i < 6 { 16 } else { 64 })?)
+            } else {
+                None
+            };
+            if i < 6 {
+                scaling_list4x4.push(list);
+            } else {
+                scaling_list8x8.push(list);
             }
         }
-        Ok(SeqScalingMatrix { })
+        Ok(SeqScalingMatrix {
+            scaling_list4x4,
+            scaling_list8x8,
+        })
+    }
+
+    /// The sequence-level scaling matrices, applying fall-back rule A for absent lists.
+    pub fn matrices(&self) -> ScalingMatrices {
+        ScalingMatrices::resolve(&self.scaling_list4x4, &self.scaling_list8x8, &ScalingMatrices::default_matrices())
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChromaInfo {
     pub chroma_format: ChromaFormat,
     pub separate_colour_plane_flag: bool,
     pub bit_depth_luma_minus8: u8,
     pub bit_depth_chroma_minus8: u8,
     pub qpprime_y_zero_transform_bypass_flag: bool,
-    pub scaling_matrix: SeqScalingMatrix,
+    /// `None` if `seq_scaling_matrix_present_flag` is unset
+    pub scaling_matrix: Option<SeqScalingMatrix>,
 }
 impl ChromaInfo {
     pub fn read(r: &mut RbspBitReader<'_>, profile_idc: ProfileIdc) -> Result<ChromaInfo, SpsError> {
@@ -354,7 +652,7 @@ impl ChromaInfo {
                 bit_depth_luma_minus8: 0,
                 bit_depth_chroma_minus8: 0,
                 qpprime_y_zero_transform_bypass_flag: false,
-                scaling_matrix: SeqScalingMatrix::default(),
+                scaling_matrix: None,
             })
         }
     }
@@ -366,12 +664,12 @@ impl ChromaInfo {
             Ok(value as u8)
         }
     }
-    fn read_scaling_matrix(r: &mut RbspBitReader<'_>, chroma_format_idc: u32) -> Result<SeqScalingMatrix, SpsError> {
+    fn read_scaling_matrix(r: &mut RbspBitReader<'_>, chroma_format_idc: u32) -> Result<Option<SeqScalingMatrix>, SpsError> {
         let scaling_matrix_present_flag = r.read_bool()?;
         if scaling_matrix_present_flag {
-            SeqScalingMatrix::read(r, chroma_format_idc).map_err(|e| e.into())
+            Ok(Some(SeqScalingMatrix::read(r, chroma_format_idc)?))
         } else {
-            Ok(SeqScalingMatrix::default())
+            Ok(None)
         }
     }
 }
@@ -382,6 +680,8 @@ pub enum PicOrderCntError {
     ReaderError(bitreader::BitReaderError),
     /// log2_max_pic_order_cnt_lsb_minus4 must be between 0 and 12
     Log2MaxPicOrderCntLsbMinus4OutOfRange(u32),
+    /// num_ref_frames_in_pic_order_cnt_cycle must be between 0 and 255
+    NumRefFramesInPicOrderCntCycleOutOfRange(u32),
 }
 
 impl From<bitreader::BitReaderError> for PicOrderCntError {
@@ -390,7 +690,7 @@ impl From<bitreader::BitReaderError> for PicOrderCntError {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PicOrderCntType {
     TypeZero {
         log2_max_pic_order_cnt_lsb_minus4: u8
@@ -440,6 +740,9 @@ impl PicOrderCntType {
 
     fn read_offsets_for_ref_frame(r: &mut RbspBitReader<'_>) -> Result<Vec<i32>, PicOrderCntError> {
         let num_ref_frames_in_pic_order_cnt_cycle = r.read_ue()?;
+        if num_ref_frames_in_pic_order_cnt_cycle > 255 {
+            return Err(PicOrderCntError::NumRefFramesInPicOrderCntCycleOutOfRange(num_ref_frames_in_pic_order_cnt_cycle));
+        }
         let mut offsets = Vec::with_capacity(num_ref_frames_in_pic_order_cnt_cycle as usize);
         for _ in 0..num_ref_frames_in_pic_order_cnt_cycle {
             offsets.push(r.read_se()?);
@@ -448,7 +751,7 @@ impl PicOrderCntType {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FrameMbsFlags {
     Frames,
     Fields {
@@ -468,7 +771,7 @@ impl FrameMbsFlags {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FrameCropping {
     pub left_offset: u32,
     pub right_offset: u32,
@@ -491,7 +794,7 @@ impl FrameCropping {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AspectRatioInfo {
     Unspecified,
     Ratio1_1,
@@ -580,7 +883,7 @@ impl AspectRatioInfo {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OverscanAppropriate {
     Unspecified,
     Appropriate,
@@ -602,7 +905,7 @@ impl OverscanAppropriate {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VideoFormat {
     Component,
     PAL,
@@ -621,38 +924,305 @@ impl VideoFormat {
             3 => VideoFormat::SECAM,
             4 => VideoFormat::MAC,
             5 => VideoFormat::Unspecified,
-            6|7 => VideoFormat::Reserved(video_format),
-            _ => panic!("unsupported video_format value {}", video_format),
+            _ => VideoFormat::Reserved(video_format),
+        }
+    }
+}
+
+/// The chromaticity of the source primaries and white point (`colour_primaries`, Table 2 of
+/// ITU-T H.273).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColourPrimaries {
+    /// Rec. ITU-R BT.709, also sRGB
+    Bt709,
+    Unspecified,
+    /// Rec. ITU-R BT.470 System M
+    Bt470M,
+    /// Rec. ITU-R BT.601 625-line (also BT.470 System B, G)
+    Bt601_625,
+    /// Rec. ITU-R BT.601 525-line (also SMPTE 170M)
+    Bt601_525,
+    /// SMPTE 240M
+    Smpte240M,
+    /// Generic film, using Illuminant C
+    GenericFilm,
+    /// Rec. ITU-R BT.2020 and BT.2100
+    Bt2020,
+    /// SMPTE ST 428-1 (CIE 1931 XYZ)
+    Smpte428,
+    /// SMPTE RP 431-2 (DCI-P3)
+    Smpte431,
+    /// SMPTE EG 432-1 (Display P3)
+    Smpte432,
+    /// EBU Tech. 3213-E
+    Ebu3213,
+    Reserved(u8),
+}
+impl From<u8> for ColourPrimaries {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => ColourPrimaries::Bt709,
+            2 => ColourPrimaries::Unspecified,
+            4 => ColourPrimaries::Bt470M,
+            5 => ColourPrimaries::Bt601_625,
+            6 => ColourPrimaries::Bt601_525,
+            7 => ColourPrimaries::Smpte240M,
+            8 => ColourPrimaries::GenericFilm,
+            9 => ColourPrimaries::Bt2020,
+            10 => ColourPrimaries::Smpte428,
+            11 => ColourPrimaries::Smpte431,
+            12 => ColourPrimaries::Smpte432,
+            22 => ColourPrimaries::Ebu3213,
+            _ => ColourPrimaries::Reserved(v),
+        }
+    }
+}
+impl From<ColourPrimaries> for u8 {
+    fn from(v: ColourPrimaries) -> Self {
+        match v {
+            ColourPrimaries::Bt709 => 1,
+            ColourPrimaries::Unspecified => 2,
+            ColourPrimaries::Bt470M => 4,
+            ColourPrimaries::Bt601_625 => 5,
+            ColourPrimaries::Bt601_525 => 6,
+            ColourPrimaries::Smpte240M => 7,
+            ColourPrimaries::GenericFilm => 8,
+            ColourPrimaries::Bt2020 => 9,
+            ColourPrimaries::Smpte428 => 10,
+            ColourPrimaries::Smpte431 => 11,
+            ColourPrimaries::Smpte432 => 12,
+            ColourPrimaries::Ebu3213 => 22,
+            ColourPrimaries::Reserved(v) => v,
+        }
+    }
+}
+
+/// The opto-electronic transfer characteristic of the source (`transfer_characteristics`,
+/// Table 3 of ITU-T H.273).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferCharacteristics {
+    /// Rec. ITU-R BT.709
+    Bt709,
+    Unspecified,
+    /// Assumed display gamma 2.2 (Rec. ITU-R BT.470 System M)
+    Gamma22,
+    /// Assumed display gamma 2.8 (Rec. ITU-R BT.470 System B, G)
+    Gamma28,
+    /// Rec. ITU-R BT.601 (also SMPTE 170M)
+    Bt601,
+    /// SMPTE 240M
+    Smpte240M,
+    Linear,
+    /// Logarithmic, 100:1 range
+    Log100,
+    /// Logarithmic, 100 * Sqrt(10):1 range
+    Log316,
+    /// IEC 61966-2-4 (xvYCC)
+    Iec61966_2_4,
+    /// Rec. ITU-R BT.1361 extended colour gamut
+    Bt1361,
+    /// IEC 61966-2-1 (sRGB / sYCC)
+    Srgb,
+    /// Rec. ITU-R BT.2020 for 10 bit systems
+    Bt2020_10,
+    /// Rec. ITU-R BT.2020 for 12 bit systems
+    Bt2020_12,
+    /// SMPTE ST 2084 perceptual quantizer, as used for HDR10 (also Rec. ITU-R BT.2100 PQ)
+    Smpte2084,
+    /// SMPTE ST 428-1
+    Smpte428,
+    /// ARIB STD-B67 hybrid log-gamma (also Rec. ITU-R BT.2100 HLG)
+    Hlg,
+    Reserved(u8),
+}
+impl From<u8> for TransferCharacteristics {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => TransferCharacteristics::Bt709,
+            2 => TransferCharacteristics::Unspecified,
+            4 => TransferCharacteristics::Gamma22,
+            5 => TransferCharacteristics::Gamma28,
+            6 => TransferCharacteristics::Bt601,
+            7 => TransferCharacteristics::Smpte240M,
+            8 => TransferCharacteristics::Linear,
+            9 => TransferCharacteristics::Log100,
+            10 => TransferCharacteristics::Log316,
+            11 => TransferCharacteristics::Iec61966_2_4,
+            12 => TransferCharacteristics::Bt1361,
+            13 => TransferCharacteristics::Srgb,
+            14 => TransferCharacteristics::Bt2020_10,
+            15 => TransferCharacteristics::Bt2020_12,
+            16 => TransferCharacteristics::Smpte2084,
+            17 => TransferCharacteristics::Smpte428,
+            18 => TransferCharacteristics::Hlg,
+            _ => TransferCharacteristics::Reserved(v),
+        }
+    }
+}
+impl From<TransferCharacteristics> for u8 {
+    fn from(v: TransferCharacteristics) -> Self {
+        match v {
+            TransferCharacteristics::Bt709 => 1,
+            TransferCharacteristics::Unspecified => 2,
+            TransferCharacteristics::Gamma22 => 4,
+            TransferCharacteristics::Gamma28 => 5,
+            TransferCharacteristics::Bt601 => 6,
+            TransferCharacteristics::Smpte240M => 7,
+            TransferCharacteristics::Linear => 8,
+            TransferCharacteristics::Log100 => 9,
+            TransferCharacteristics::Log316 => 10,
+            TransferCharacteristics::Iec61966_2_4 => 11,
+            TransferCharacteristics::Bt1361 => 12,
+            TransferCharacteristics::Srgb => 13,
+            TransferCharacteristics::Bt2020_10 => 14,
+            TransferCharacteristics::Bt2020_12 => 15,
+            TransferCharacteristics::Smpte2084 => 16,
+            TransferCharacteristics::Smpte428 => 17,
+            TransferCharacteristics::Hlg => 18,
+            TransferCharacteristics::Reserved(v) => v,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// The matrix used to derive luma and chroma from RGB primaries (`matrix_coefficients`, Table 4
+/// of ITU-T H.273).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatrixCoefficients {
+    /// The identity matrix, i.e. samples are GBR (or XYZ)
+    Identity,
+    /// Rec. ITU-R BT.709
+    Bt709,
+    Unspecified,
+    /// United States Federal Communications Commission
+    Fcc,
+    /// Rec. ITU-R BT.601 625-line (also BT.470 System B, G)
+    Bt601_625,
+    /// Rec. ITU-R BT.601 525-line (also SMPTE 170M)
+    Bt601_525,
+    /// SMPTE 240M
+    Smpte240M,
+    YCgCo,
+    /// Rec. ITU-R BT.2020 non-constant luminance
+    Bt2020Ncl,
+    /// Rec. ITU-R BT.2020 constant luminance
+    Bt2020Cl,
+    /// SMPTE ST 2085 (Y'D'zD'x)
+    Smpte2085,
+    ChromaticityDerivedNcl,
+    ChromaticityDerivedCl,
+    /// Rec. ITU-R BT.2100 ICtCp
+    ICtCp,
+    Reserved(u8),
+}
+impl From<u8> for MatrixCoefficients {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => MatrixCoefficients::Identity,
+            1 => MatrixCoefficients::Bt709,
+            2 => MatrixCoefficients::Unspecified,
+            4 => MatrixCoefficients::Fcc,
+            5 => MatrixCoefficients::Bt601_625,
+            6 => MatrixCoefficients::Bt601_525,
+            7 => MatrixCoefficients::Smpte240M,
+            8 => MatrixCoefficients::YCgCo,
+            9 => MatrixCoefficients::Bt2020Ncl,
+            10 => MatrixCoefficients::Bt2020Cl,
+            11 => MatrixCoefficients::Smpte2085,
+            12 => MatrixCoefficients::ChromaticityDerivedNcl,
+            13 => MatrixCoefficients::ChromaticityDerivedCl,
+            14 => MatrixCoefficients::ICtCp,
+            _ => MatrixCoefficients::Reserved(v),
+        }
+    }
+}
+impl From<MatrixCoefficients> for u8 {
+    fn from(v: MatrixCoefficients) -> Self {
+        match v {
+            MatrixCoefficients::Identity => 0,
+            MatrixCoefficients::Bt709 => 1,
+            MatrixCoefficients::Unspecified => 2,
+            MatrixCoefficients::Fcc => 4,
+            MatrixCoefficients::Bt601_625 => 5,
+            MatrixCoefficients::Bt601_525 => 6,
+            MatrixCoefficients::Smpte240M => 7,
+            MatrixCoefficients::YCgCo => 8,
+            MatrixCoefficients::Bt2020Ncl => 9,
+            MatrixCoefficients::Bt2020Cl => 10,
+            MatrixCoefficients::Smpte2085 => 11,
+            MatrixCoefficients::ChromaticityDerivedNcl => 12,
+            MatrixCoefficients::ChromaticityDerivedCl => 13,
+            MatrixCoefficients::ICtCp => 14,
+            MatrixCoefficients::Reserved(v) => v,
+        }
+    }
+}
+
+/// A broad classification of the colour description, as used to pick a processing path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColourSummary {
+    /// A conventional standard dynamic range transfer function
+    Sdr,
+    /// SMPTE ST 2084 (PQ) transfer with BT.2020 primaries.  Note that the HDR10 format also calls
+    /// for 10 bit samples and static metadata in SEI messages, which are not checked.
+    Hdr10,
+    /// SMPTE ST 2084 (PQ) transfer with primaries other than BT.2020
+    Pq,
+    /// Hybrid log-gamma transfer
+    Hlg,
+    /// The transfer characteristics are unspecified, reserved, or not of the above kinds
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ColourDescription {
-    colour_primaries: u8,
-    transfer_characteristics: u8,
-    matrix_coefficients: u8,
+    pub colour_primaries: ColourPrimaries,
+    pub transfer_characteristics: TransferCharacteristics,
+    pub matrix_coefficients: MatrixCoefficients,
 }
 impl ColourDescription {
     fn read(r: &mut RbspBitReader<'_>) -> Result<Option<ColourDescription>,bitreader::BitReaderError> {
         let colour_description_present_flag = r.read_bool()?;
         Ok(if colour_description_present_flag {
             Some(ColourDescription {
-                colour_primaries: r.read_u8(8)?,
-                transfer_characteristics: r.read_u8(8)?,
-                matrix_coefficients: r.read_u8(8)?,
+                colour_primaries: r.read_u8(8)?.into(),
+                transfer_characteristics: r.read_u8(8)?.into(),
+                matrix_coefficients: r.read_u8(8)?.into(),
             })
         } else {
             None
         })
     }
+
+    /// Classifies the colour description as SDR, HDR10, HLG etc. according to its transfer
+    /// characteristics and primaries.
+    pub fn summary(&self) -> ColourSummary {
+        match self.transfer_characteristics {
+            TransferCharacteristics::Smpte2084 => if self.colour_primaries == ColourPrimaries::Bt2020 {
+                ColourSummary::Hdr10
+            } else {
+                ColourSummary::Pq
+            },
+            TransferCharacteristics::Hlg => ColourSummary::Hlg,
+            TransferCharacteristics::Bt709
+            | TransferCharacteristics::Gamma22
+            | TransferCharacteristics::Gamma28
+            | TransferCharacteristics::Bt601
+            | TransferCharacteristics::Smpte240M
+            | TransferCharacteristics::Iec61966_2_4
+            | TransferCharacteristics::Bt1361
+            | TransferCharacteristics::Srgb
+            | TransferCharacteristics::Bt2020_10
+            | TransferCharacteristics::Bt2020_12 => ColourSummary::Sdr,
+            _ => ColourSummary::Unknown,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct VideoSignalType {
-    video_format: VideoFormat,
-    video_full_range_flag: bool,
-    colour_description: Option<ColourDescription>,
+    pub video_format: VideoFormat,
+    pub video_full_range_flag: bool,
+    pub colour_description: Option<ColourDescription>,
 }
 impl VideoSignalType {
     fn read(r: &mut RbspBitReader<'_>) -> Result<Option<VideoSignalType>,bitreader::BitReaderError> {
@@ -669,7 +1239,7 @@ impl VideoSignalType {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChromaLocInfo {
     chroma_sample_loc_type_top_field: u32,
     chroma_sample_loc_type_bottom_field: u32,
@@ -688,14 +1258,88 @@ impl ChromaLocInfo {
     }
 }
 
-#[derive(Debug, Clone)]
+/// A non-negative rational number, always held in lowest terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rational {
+    pub num: u64,
+    pub den: u64,
+}
+impl Rational {
+    /// `None` if `den` is zero
+    pub fn new(num: u64, den: u64) -> Option<Rational> {
+        if den == 0 {
+            return None;
+        }
+        let g = gcd(num, den);
+        Some(Rational { num: num / g, den: den / g })
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TimingInfo {
-    num_units_in_tick: u32,
-    time_scale: u32,
-    fixed_frame_rate_flag: bool,
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+    pub fixed_frame_rate_flag: bool,
 }
 impl TimingInfo {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<Option<TimingInfo>,bitreader::BitReaderError> {
+    /// The rate of clock ticks per second.  For interlaced content, this is the field rate.
+    pub fn tick_rate(&self) -> Option<Rational> {
+        Rational::new(u64::from(self.time_scale), u64::from(self.num_units_in_tick))
+    }
+
+    /// The nominal frame rate, being half the tick rate since a frame is displayed for two
+    /// ticks (`DeltaTfiDivisor` of `2`).
+    ///
+    /// e.g. for `time_scale` of `60000` and `num_units_in_tick` of `1001`, the frame rate is
+    /// `30000/1001`, not `60000/1001`.
+    pub fn frame_rate(&self) -> Option<Rational> {
+        Rational::new(u64::from(self.time_scale), u64::from(self.num_units_in_tick) * 2)
+    }
+
+    /// The rate at which pictures with the given `pic_struct` would be displayed, if all pictures
+    /// had that structure.  `None` for reserved `pic_struct` values.
+    pub fn picture_rate(&self, pic_struct: &PicStructType) -> Option<Rational> {
+        self.average_picture_rate(std::iter::once(pic_struct))
+    }
+
+    /// The average display rate of a run of pictures with the given `pic_struct` values, as
+    /// signalled in their `pic_timing` SEI messages.  This accounts for patterns of repeated
+    /// fields and frames, so that for example 3:2 pull-down of film content in a 60 field per
+    /// second stream gives a rate of 24 pictures per second.
+    ///
+    /// Returns `None` if there are no pictures, or any `pic_struct` is a reserved value.
+    pub fn average_picture_rate<'a, I>(&self, pic_structs: I) -> Option<Rational>
+    where
+        I: IntoIterator<Item = &'a PicStructType>,
+    {
+        let mut pictures = 0u64;
+        let mut ticks = 0u64;
+        for pic_struct in pic_structs {
+            pictures += 1;
+            ticks += u64::from(pic_struct.delta_tfi_divisor()?);
+        }
+        if pictures == 0 {
+            return None;
+        }
+        let num = pictures.checked_mul(u64::from(self.time_scale))?;
+        let den = ticks.checked_mul(u64::from(self.num_units_in_tick))?;
+        Rational::new(num, den)
+    }
+
+    pub(crate) fn read(r: &mut RbspBitReader<'_>) -> Result<Option<TimingInfo>,bitreader::BitReaderError> {
         let timing_info_present_flag = r.read_bool()?;
         Ok(if timing_info_present_flag {
             Some(TimingInfo {
@@ -709,11 +1353,11 @@ impl TimingInfo {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CpbSpec {
-    bit_rate_value_minus1: u32,
-    cpb_size_value_minus1: u32,
-    cbr_flag: bool,
+    pub bit_rate_value_minus1: u32,
+    pub cpb_size_value_minus1: u32,
+    pub cbr_flag: bool,
 }
 impl CpbSpec {
     fn read(r: &mut RbspBitReader<'_>) -> Result<CpbSpec,RbspBitReaderError> {
@@ -726,7 +1370,7 @@ impl CpbSpec {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HrdParameters {
     pub bit_rate_scale: u8,
     pub cpb_size_scale: u8,
@@ -737,11 +1381,14 @@ pub struct HrdParameters {
     pub time_offset_length: u8,
 }
 impl HrdParameters {
-    fn read(r: &mut RbspBitReader<'_>, hrd_parameters_present: &mut bool) -> Result<Option<HrdParameters>,RbspBitReaderError> {
+    pub(crate) fn read(r: &mut RbspBitReader<'_>, hrd_parameters_present: &mut bool) -> Result<Option<HrdParameters>,SpsError> {
         let hrd_parameters_present_flag = r.read_bool_named("hrd_parameters_present_flag")?;
         *hrd_parameters_present |= hrd_parameters_present_flag;
         Ok(if hrd_parameters_present_flag {
             let cpb_cnt_minus1 = r.read_ue_named("cpb_cnt_minus1")?;
+            if cpb_cnt_minus1 > 31 {
+                return Err(SpsError::FieldValueTooLarge { name: "cpb_cnt_minus1", value: cpb_cnt_minus1 });
+            }
             let cpb_cnt = cpb_cnt_minus1 + 1;
             Some(HrdParameters {
                 bit_rate_scale: r.read_u8(4)?,
@@ -756,6 +1403,30 @@ impl HrdParameters {
             None
         })
     }
+    /// `BitRate[SchedSelIdx]` in bits per second (equation E-37), or `None` if there is no such
+    /// `SchedSelIdx`
+    pub fn bit_rate(&self, sched_sel_idx: usize) -> Option<u64> {
+        self.cpb_specs.get(sched_sel_idx)
+            .map(|spec| (u64::from(spec.bit_rate_value_minus1) + 1) << (6 + self.bit_rate_scale))
+    }
+
+    /// `CpbSize[SchedSelIdx]` in bits (equation E-38), or `None` if there is no such
+    /// `SchedSelIdx`
+    pub fn cpb_size(&self, sched_sel_idx: usize) -> Option<u64> {
+        self.cpb_specs.get(sched_sel_idx)
+            .map(|spec| (u64::from(spec.cpb_size_value_minus1) + 1) << (4 + self.cpb_size_scale))
+    }
+
+    /// True if, as clause A.3.1 requires, both `BitRate[SchedSelIdx]` and `CpbSize[SchedSelIdx]`
+    /// are within the given limits for at least one `SchedSelIdx`.  The limits are those given by
+    /// `LevelLimits::max_nal_bit_rate()` and `max_nal_cpb_size()` for NAL HRD parameters, or by
+    /// `max_vcl_bit_rate()` and `max_vcl_cpb_size()` for VCL HRD parameters.
+    pub fn within_limits(&self, max_bit_rate: u64, max_cpb_size: u64) -> bool {
+        (0..self.cpb_specs.len()).any(|i| {
+            self.bit_rate(i).map(|r| r <= max_bit_rate).unwrap_or(false)
+                && self.cpb_size(i).map(|s| s <= max_cpb_size).unwrap_or(false)
+        })
+    }
     fn read_cpb_specs(r: &mut RbspBitReader<'_>, cpb_cnt: u32) -> Result<Vec<CpbSpec>,RbspBitReaderError> {
         let mut cpb_specs = Vec::with_capacity(cpb_cnt as usize);
         for _ in 0..cpb_cnt {
@@ -765,15 +1436,15 @@ impl HrdParameters {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BitstreamRestrictions {
-    motion_vectors_over_pic_boundaries_flag: bool,
-    max_bytes_per_pic_denom: u32,
-    max_bits_per_mb_denom: u32,
-    log2_max_mv_length_horizontal: u32,
-    log2_max_mv_length_vertical: u32,
-    max_num_reorder_frames: u32,
-    max_dec_frame_buffering: u32,
+    pub motion_vectors_over_pic_boundaries_flag: bool,
+    pub max_bytes_per_pic_denom: u32,
+    pub max_bits_per_mb_denom: u32,
+    pub log2_max_mv_length_horizontal: u32,
+    pub log2_max_mv_length_vertical: u32,
+    pub max_num_reorder_frames: u32,
+    pub max_dec_frame_buffering: u32,
 }
 impl BitstreamRestrictions {
     fn read(r: &mut RbspBitReader<'_>) -> Result<Option<BitstreamRestrictions>,RbspBitReaderError> {
@@ -794,7 +1465,7 @@ impl BitstreamRestrictions {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct VuiParameters {
     pub aspect_ratio_info: Option<AspectRatioInfo>,
     pub overscan_appropriate: OverscanAppropriate,
@@ -808,7 +1479,7 @@ pub struct VuiParameters {
     pub bitstream_restrictions: Option<BitstreamRestrictions>,
 }
 impl VuiParameters {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<Option<VuiParameters>,RbspBitReaderError> {
+    fn read(r: &mut RbspBitReader<'_>) -> Result<Option<VuiParameters>,SpsError> {
         let vui_parameters_present_flag = r.read_bool()?;
         Ok(if vui_parameters_present_flag {
             let mut hrd_parameters_present = false;
@@ -830,12 +1501,12 @@ impl VuiParameters {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SeqParameterSet {
     pub profile_idc: ProfileIdc,
     pub constraint_flags: ConstraintFlags,
     pub level_idc: u8,
-    pub seq_parameter_set_id: ParamSetId,
+    pub seq_parameter_set_id: SeqParamSetId,
     pub chroma_info: ChromaInfo,
     pub log2_max_frame_num_minus4: u8,
     pub pic_order_cnt: PicOrderCntType,
@@ -849,29 +1520,61 @@ pub struct SeqParameterSet {
     pub vui_parameters: Option<VuiParameters>,
 }
 impl SeqParameterSet {
+    /// Parses the SPS from the given RBSP data.  Any data following the SPS syntax is ignored;
+    /// see `from_bytes_strict()`.
     pub fn from_bytes(buf: &[u8]) -> Result<SeqParameterSet, SpsError> {
+        Self::read(&mut RbspBitReader::new(buf))
+    }
+
+    /// Like `from_bytes()`, but additionally requires that the SPS syntax is followed by
+    /// `rbsp_trailing_bits()`, failing with `SpsError::RbspReaderError` otherwise.
+    pub fn from_bytes_strict(buf: &[u8]) -> Result<SeqParameterSet, SpsError> {
         let mut r = RbspBitReader::new(buf);
+        let sps = Self::read(&mut r)?;
+        r.finish()?;
+        Ok(sps)
+    }
+
+    /// Reads `seq_parameter_set_data()`, leaving the reader positioned at any following syntax
+    /// (as in the case of a subset SPS).
+    pub fn read(r: &mut RbspBitReader<'_>) -> Result<SeqParameterSet, SpsError> {
         let profile_idc = r.read_u8(8)?.into();
         let sps = SeqParameterSet {
             profile_idc,
             constraint_flags: r.read_u8(8)?.into(),
             level_idc: r.read_u8(8)?,
-            seq_parameter_set_id: ParamSetId::from_u32(r.read_ue_named("seq_parameter_set_id")?).map_err(SpsError::BadSeqParamSetId)?,
-            chroma_info: ChromaInfo::read(&mut r, profile_idc)?,
-            log2_max_frame_num_minus4: Self::read_log2_max_frame_num_minus4(&mut r)?,
-            pic_order_cnt: PicOrderCntType::read(&mut r).map_err(SpsError::PicOrderCnt)?,
+            seq_parameter_set_id: SeqParamSetId::from_u32(r.read_ue_named("seq_parameter_set_id")?).map_err(SpsError::BadSeqParamSetId)?,
+            chroma_info: ChromaInfo::read(r, profile_idc)?,
+            log2_max_frame_num_minus4: Self::read_log2_max_frame_num_minus4(r)?,
+            pic_order_cnt: PicOrderCntType::read(r).map_err(SpsError::PicOrderCnt)?,
             max_num_ref_frames: r.read_ue()?,
             gaps_in_frame_num_value_allowed_flag: r.read_bool()?,
             pic_width_in_mbs_minus1: r.read_ue()?,
             pic_height_in_map_units_minus1: r.read_ue()?,
-            frame_mbs_flags: FrameMbsFlags::read(&mut r)?,
+            frame_mbs_flags: FrameMbsFlags::read(r)?,
             direct_8x8_inference_flag: r.read_bool()?,
-            frame_cropping: FrameCropping::read(&mut r)?,
-            vui_parameters: VuiParameters::read(&mut r)?,
+            frame_cropping: FrameCropping::read(r)?,
+            vui_parameters: VuiParameters::read(r)?,
         };
         Ok(sps)
     }
 
+    /// The `ChromaArrayType` variable, which is `0` when colour planes are coded separately, and
+    /// otherwise equal to `chroma_format_idc`
+    pub fn chroma_array_type(&self) -> u8 {
+        if self.chroma_info.separate_colour_plane_flag {
+            0
+        } else {
+            match self.chroma_info.chroma_format {
+                ChromaFormat::Monochrome => 0,
+                ChromaFormat::YUV420 => 1,
+                ChromaFormat::YUV422 => 2,
+                ChromaFormat::YUV444 => 3,
+                ChromaFormat::Invalid(v) => v as u8,
+            }
+        }
+    }
+
     fn read_log2_max_frame_num_minus4(r: &mut RbspBitReader<'_>) -> Result<u8, SpsError> {
         let val = r.read_ue()?;
         if val > 12 {
@@ -886,17 +1589,119 @@ impl SeqParameterSet {
     }
 
     pub fn level(&self) -> Level {
-        Level::from_constraint_flags_and_level_idc(self.constraint_flags, self.level_idc)
+        Level::from_profile_constraint_flags_and_level_idc(self.profile_idc, self.constraint_flags, self.level_idc)
     }
     /// returned value will be in the range 4 to 16 inclusive
     pub fn log2_max_frame_num(&self) -> u8 {
         self.log2_max_frame_num_minus4 + 4
     }
 
+    /// The `PicWidthInMbs` variable
+    pub fn pic_width_in_mbs(&self) -> u32 {
+        self.pic_width_in_mbs_minus1.saturating_add(1)
+    }
+
+    /// The `FrameHeightInMbs` variable, which for streams that may contain fields is double the
+    /// number of map units
+    pub fn frame_height_in_mbs(&self) -> u32 {
+        let map_units = self.pic_height_in_map_units_minus1.saturating_add(1);
+        match self.frame_mbs_flags {
+            FrameMbsFlags::Frames => map_units,
+            FrameMbsFlags::Fields { .. } => map_units.saturating_mul(2),
+        }
+    }
+
+    /// The size of a frame in macroblocks (`PicWidthInMbs * FrameHeightInMbs`)
+    pub fn frame_size_in_mbs(&self) -> u32 {
+        self.pic_width_in_mbs().saturating_mul(self.frame_height_in_mbs())
+    }
+
+    /// The `MaxDpbFrames` variable of clause A.3.1, giving the maximum number of frames the
+    /// decoded picture buffer can hold at this SPS's level and frame size.  `None` if the level
+    /// is unknown.
+    pub fn max_dpb_frames(&self) -> Option<u32> {
+        let limits = self.level().limits()?;
+        Some(std::cmp::min(limits.max_dpb_mbs / self.frame_size_in_mbs(), 16))
+    }
+
+    /// Checks the NAL HRD parameters against the `MaxBR` and `MaxCPB` limits of this SPS's level,
+    /// scaled by the profile's `cpbBrNalFactor`.  `None` if there are no NAL HRD parameters, or if
+    /// the level or profile is not one for which the limits are known.
+    pub fn nal_hrd_within_level_limits(&self) -> Option<bool> {
+        let hrd = self.vui_parameters.as_ref()?.nal_hrd_parameters.as_ref()?;
+        let limits = self.level().limits()?;
+        Some(hrd.within_limits(
+            limits.max_nal_bit_rate(self.profile_idc)?,
+            limits.max_nal_cpb_size(self.profile_idc)?,
+        ))
+    }
+
+    /// Checks the VCL HRD parameters against the `MaxBR` and `MaxCPB` limits of this SPS's level,
+    /// scaled by the profile's `cpbBrVclFactor`.  `None` if there are no VCL HRD parameters, or if
+    /// the level or profile is not one for which the limits are known.
+    pub fn vcl_hrd_within_level_limits(&self) -> Option<bool> {
+        let hrd = self.vui_parameters.as_ref()?.vcl_hrd_parameters.as_ref()?;
+        let limits = self.level().limits()?;
+        Some(hrd.within_limits(
+            limits.max_vcl_bit_rate(self.profile_idc)?,
+            limits.max_vcl_cpb_size(self.profile_idc)?,
+        ))
+    }
+
+    /// The value of `max_dec_frame_buffering`, either as given in the VUI bitstream
+    /// restrictions, or otherwise as inferred by clause E.2.1.  `None` if the value is not given
+    /// and can't be inferred because the level is unknown.
+    pub fn max_dec_frame_buffering(&self) -> Option<u32> {
+        let restrictions = self.vui_parameters.as_ref().and_then(|v| v.bitstream_restrictions.as_ref());
+        if let Some(restrictions) = restrictions {
+            return Some(restrictions.max_dec_frame_buffering);
+        }
+        // intra-only profiles don't need to buffer frames for reference
+        if matches!(self.profile_idc.0, 44 | 86 | 100 | 110 | 122 | 244) && self.constraint_flags.flag3() {
+            Some(0)
+        } else {
+            self.max_dpb_frames()
+        }
+    }
+
+    /// The highest frame rate that the level allows for frames of this SPS's size, according to
+    /// the `MaxMBPS` limit and the minimum interval between pictures of clause A.3.1.  `None` if
+    /// the level is unknown.
+    pub fn max_frame_rate(&self) -> Option<f64> {
+        let level = self.level();
+        let limits = level.limits()?;
+        let mb_rate_limited = f64::from(limits.max_mbps) / f64::from(self.frame_size_in_mbs());
+        let max_picture_rate = if level.level_idc() >= 60 { 300.0 } else { 172.0 };
+        Some(mb_rate_limited.min(max_picture_rate))
+    }
+
     /// Helper to calculate the pixel-dimensions of the video image specified by this SPS, taking
     /// into account sample-format, interlacing and cropping.
     pub fn pixel_dimensions(&self) -> Result<(u32, u32), SpsError> {
-        let width = (self.pic_width_in_mbs_minus1 + 1) * 16;
+        let crop = self.crop_rect()?;
+        Ok((crop.width, crop.height))
+    }
+
+    /// The size of the decoded frame in luma samples, before any cropping is applied
+    pub fn coded_dimensions(&self) -> Result<(u32, u32), SpsError> {
+        let width = self.pic_width_in_mbs_minus1.checked_add(1)
+            .and_then(|w| w.checked_mul(16) )
+            .ok_or(SpsError::FieldValueTooLarge { name:"pic_width_in_mbs_minus1", value: self.pic_width_in_mbs_minus1 })?;
+        let mul = match self.frame_mbs_flags {
+            FrameMbsFlags::Fields { .. } => 2,
+            FrameMbsFlags::Frames => 1,
+        };
+        let height = self.pic_height_in_map_units_minus1.checked_add(1)
+            .and_then(|h| h.checked_mul(mul * 16) )
+            .ok_or(SpsError::FieldValueTooLarge { name:"pic_height_in_map_units_minus1", value: self.pic_height_in_map_units_minus1 })?;
+        Ok((width, height))
+    }
+
+    /// The region of the decoded frame which is to be output, in luma samples.  The frame
+    /// cropping offsets are given in units that depend on the chroma format and on whether the
+    /// stream may contain fields, and this converts them.
+    pub fn crop_rect(&self) -> Result<CropRect, SpsError> {
+        let (width, height) = self.coded_dimensions()?;
         let mul = match self.frame_mbs_flags {
             FrameMbsFlags::Fields { .. } => 2,
             FrameMbsFlags::Frames => 1,
@@ -917,9 +1722,6 @@ impl SeqParameterSet {
         let step_x = 1 << hsub;
         let step_y = mul << vsub;
 
-        let height = (self.pic_height_in_map_units_minus1 + 1)
-            .checked_mul(mul * 16)
-            .ok_or_else(|| SpsError::FieldValueTooLarge { name:"pic_height_in_map_units_minus1", value: self.pic_height_in_map_units_minus1 })?;
         if let Some(ref crop) = self.frame_cropping {
             let left_offset = crop.left_offset.checked_mul(step_x)
                 .ok_or_else(|| SpsError::FieldValueTooLarge { name:"left_offset", value: crop.left_offset })?;
@@ -936,27 +1738,112 @@ impl SeqParameterSet {
                 .checked_sub(top_offset)
                 .and_then(|w| w.checked_sub(bottom_offset) );
             if let (Some(width), Some(height)) = (width, height) {
-                Ok((width, height))
+                Ok(CropRect { left: left_offset, top: top_offset, width, height })
             } else {
                 Err(SpsError::CroppingError(crop.clone()))
             }
         } else {
-            Ok((width, height))
+            Ok(CropRect { left: 0, top: 0, width, height })
         }
     }
+
+    /// The sequence-level scaling matrices (`Flat_4x4_16` and `Flat_8x8_16` if the SPS has no
+    /// scaling matrix).
+    pub fn scaling_matrices(&self) -> ScalingMatrices {
+        match self.chroma_info.scaling_matrix {
+            Some(ref m) => m.matrices(),
+            None => ScalingMatrices::flat(),
+        }
+    }
+
+    /// The sample aspect ratio given in the VUI parameters, if any.
+    pub fn sample_aspect_ratio(&self) -> Option<Rational> {
+        self.vui_parameters.as_ref()
+            .and_then(|vui| vui.aspect_ratio_info.clone())
+            .and_then(|info| info.get())
+            .and_then(|(w, h)| Rational::new(u64::from(w), u64::from(h)))
+    }
+
+    /// Gathers together the sizes and aspect ratios needed to display the decoded pictures.
+    pub fn display_geometry(&self) -> Result<DisplayGeometry, SpsError> {
+        let (coded_width, coded_height) = self.coded_dimensions()?;
+        let crop = self.crop_rect()?;
+        let sample_aspect_ratio = self.sample_aspect_ratio();
+        let sar = sample_aspect_ratio.unwrap_or(Rational { num: 1, den: 1 });
+        let display_aspect_ratio = Rational::new(
+            u64::from(crop.width) * sar.num,
+            u64::from(crop.height) * sar.den,
+        );
+        Ok(DisplayGeometry {
+            width_in_mbs: self.pic_width_in_mbs(),
+            height_in_mbs: self.frame_height_in_mbs(),
+            coded_width,
+            coded_height,
+            crop,
+            sample_aspect_ratio,
+            display_aspect_ratio,
+        })
+    }
+}
+
+/// A rectangle within the decoded frame, in luma samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CropRect {
+    pub left: u32,
+    pub top: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The geometry of the pictures of a sequence, as returned by
+/// `SeqParameterSet::display_geometry()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayGeometry {
+    /// `PicWidthInMbs`
+    pub width_in_mbs: u32,
+    /// `FrameHeightInMbs`
+    pub height_in_mbs: u32,
+    /// Width of the decoded frame in luma samples
+    pub coded_width: u32,
+    /// Height of the decoded frame in luma samples
+    pub coded_height: u32,
+    /// The part of the decoded frame to be displayed
+    pub crop: CropRect,
+    /// `None` if the VUI parameters don't specify the sample aspect ratio
+    pub sample_aspect_ratio: Option<Rational>,
+    /// The aspect ratio of the cropped picture when displayed, assuming square samples where the
+    /// sample aspect ratio is not specified.  `None` only if the cropped height is zero.
+    pub display_aspect_ratio: Option<Rational>,
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
     use hex_literal::*;
 
+    /// High profile, level 1, 64x64
+    pub(crate) const HIGH_PROFILE_SPS: [u8; 22] = hex!(
+       "64 00 0A AC 72 84 44 26 84 00 00
+        00 04 00 00 00 CA 3C 48 96 11 80");
+    /// Baseline profile, level 3, `seq_parameter_set_id=1`, with VCL (but not NAL) HRD
+    /// parameters giving 16-bit initial CPB removal delays, 8-bit `cpb_removal_delay` and
+    /// `dpb_output_delay`, and `pic_struct_present_flag=1`
+    pub(crate) const VCL_HRD_SPS: [u8; 17] = hex!("42 00 1e 56 82 c4 e8 18 00 03 e8 00 7d 07 9c f8 50");
+    /// Baseline profile, level 3, with VCL HRD parameters giving BitRate=64000 and CpbSize=16000
+    /// (VBR, both from values of 999 with a scale of zero), 8-bit CPB and DPB delays, a clock
+    /// tick of 1/50 second, and `max_dec_frame_buffering=1`
+    pub(crate) const HRD_SPS: [u8; 28] = hex!("42 00 1e da 0b 13 a1 00 00 00 01 00 00 00 32 b0 00 07 d0 00 fa 0f 39 f0 78 44 22 94");
+
+    /// A `Context` holding the SPS parsed from the given data
+    pub(crate) fn ctx_with_sps(data: &[u8]) -> Context<()> {
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(SeqParameterSet::from_bytes(data).unwrap());
+        ctx
+    }
+
     #[test]
     fn test_it() {
-        let data = hex!(
-           "64 00 0A AC 72 84 44 26 84 00 00
-            00 04 00 00 00 CA 3C 48 96 11 80");
-        let sps = SeqParameterSet::from_bytes(&data[..]).unwrap();
+        let sps = SeqParameterSet::from_bytes(&HIGH_PROFILE_SPS).unwrap();
         println!("sps: {:#?}", sps);
         assert_eq!(100, sps.profile_idc.0);
         assert_eq!(0, sps.constraint_flags.reserved_zero_two_bits());
@@ -977,13 +1864,160 @@ mod test {
         assert_eq!(sps.vui_parameters.unwrap().aspect_ratio_info.unwrap().get(), Some((40, 33)));
     }
 
+    #[test]
+    fn anamorphic_display_geometry() {
+        // the Dahua sub stream SPS of test_dahua, with a sample aspect ratio of 40:33
+        let data = hex!(
+          "64 00 16 AC 1B 1A 80 B0 3D FF FF
+           00 28 00 21 6E 0C 0C 0C 80 00 01
+           F4 00 00 27 10 74 30 07 D0 00 07
+           A1 25 DE 5C 68 60 0F A0 00 0F 42
+           4B BC B8 50");
+        let sps = SeqParameterSet::from_bytes(&data[..]).unwrap();
+        let geometry = sps.display_geometry().unwrap();
+        assert_eq!((geometry.coded_width, geometry.coded_height), (704, 480));
+        assert_eq!(geometry.crop, CropRect { left: 0, top: 0, width: 704, height: 480 });
+        assert_eq!(geometry.sample_aspect_ratio, Rational::new(40, 33));
+        // 704*40 : 480*33
+        assert_eq!(geometry.display_aspect_ratio, Rational::new(16, 9));
+    }
+
+    #[test]
+    fn strict() {
+        let data = HIGH_PROFILE_SPS;
+        assert!(SeqParameterSet::from_bytes_strict(&data[..]).is_ok());
+        // without rbsp_trailing_bits(), or with unparsed data in their place, the SPS is only
+        // accepted by from_bytes()
+        let truncated = &data[..data.len() - 1];
+        assert!(SeqParameterSet::from_bytes(truncated).is_ok());
+        assert!(matches!(
+            SeqParameterSet::from_bytes_strict(truncated),
+            Err(SpsError::RbspReaderError(RbspBitReaderError::MissingTrailingBits { .. }))
+        ));
+        let mut extra = data.to_vec();
+        extra.insert(data.len() - 1, 0xff);
+        assert!(SeqParameterSet::from_bytes(&extra[..]).is_ok());
+        assert!(matches!(
+            SeqParameterSet::from_bytes_strict(&extra[..]),
+            Err(SpsError::RbspReaderError(RbspBitReaderError::UnconsumedData { bits: 8 }))
+        ));
+    }
+
+    #[test]
+    fn level_limits() {
+        let sps = SeqParameterSet::from_bytes(&HIGH_PROFILE_SPS).unwrap();
+        assert_eq!(sps.level(), Level::L1);
+        assert_eq!(sps.frame_size_in_mbs(), 16);
+        // MaxDpbMbs of 396 would allow 24 frames, but the limit is 16
+        assert_eq!(sps.max_dpb_frames(), Some(16));
+        assert_eq!(sps.max_frame_rate(), Some(1485.0 / 16.0));
+        assert_eq!(sps.max_dec_frame_buffering(), Some(16));
+
+        let limits = Level::L4_1.limits().unwrap();
+        assert_eq!(limits.max_fs, 8192);
+        assert_eq!(limits.max_vcl_bit_rate(ProfileIdc(100)), Some(62_500_000));
+        assert_eq!(limits.max_nal_cpb_size(ProfileIdc(66)), Some(75_000_000));
+        assert_eq!(limits.max_vcl_bit_rate(ProfileIdc(83)), None);
+        assert_eq!(Level::from_constraint_flags_and_level_idc(ConstraintFlags(0), 62), Level::L6_2);
+        assert_eq!(Level::L6_2.limits().unwrap().max_mbps, 16_711_680);
+        // constraint_set3_flag with level_idc 11 means level 1b only in Baseline, Main and
+        // Extended profiles; in e.g. High 10 Intra it is level 1.1
+        let flag3 = ConstraintFlags(0b0001_0000);
+        assert_eq!(Level::from_profile_constraint_flags_and_level_idc(ProfileIdc(77), flag3, 11), Level::L1_b);
+        assert_eq!(Level::from_profile_constraint_flags_and_level_idc(ProfileIdc(110), flag3, 11), Level::L1_1);
+        assert_eq!(Level::from_profile_constraint_flags_and_level_idc(ProfileIdc(110), flag3, 9), Level::L1_b);
+        assert_eq!(Level::Unknown(99).limits(), None);
+    }
+
+    #[test]
+    fn frame_rate() {
+        let timing = TimingInfo {
+            num_units_in_tick: 1001,
+            time_scale: 60000,
+            fixed_frame_rate_flag: true,
+        };
+        assert_eq!(timing.tick_rate(), Rational::new(60000, 1001));
+        assert_eq!(timing.frame_rate(), Some(Rational { num: 30000, den: 1001 }));
+        assert_eq!(timing.picture_rate(&PicStructType::TopField), Rational::new(60000, 1001));
+        assert_eq!(timing.picture_rate(&PicStructType::FrameDoubling), Rational::new(15000, 1001));
+        let pulldown = [
+            PicStructType::TopFieldBottomFieldTopFieldRepeated,
+            PicStructType::BottomFieldTopField,
+            PicStructType::BottomFieldTopFieldBottomFieldRepeated,
+            PicStructType::TopFieldBottomField,
+        ];
+        assert_eq!(timing.average_picture_rate(&pulldown), Rational::new(24000, 1001));
+        assert_eq!(timing.average_picture_rate(&[]), None);
+        assert_eq!(timing.picture_rate(&PicStructType::Reserved(9)), None);
+        let bad = TimingInfo { num_units_in_tick: 0, time_scale: 1, fixed_frame_rate_flag: false };
+        assert_eq!(bad.frame_rate(), None);
+    }
+
+    #[test]
+    fn colour_description() {
+        for v in 0..=255u8 {
+            assert_eq!(u8::from(ColourPrimaries::from(v)), v);
+            assert_eq!(u8::from(TransferCharacteristics::from(v)), v);
+            assert_eq!(u8::from(MatrixCoefficients::from(v)), v);
+        }
+        let hdr10 = ColourDescription {
+            colour_primaries: 9.into(),
+            transfer_characteristics: 16.into(),
+            matrix_coefficients: 9.into(),
+        };
+        assert_eq!(hdr10.matrix_coefficients, MatrixCoefficients::Bt2020Ncl);
+        assert_eq!(hdr10.summary(), ColourSummary::Hdr10);
+        let hlg = ColourDescription { transfer_characteristics: TransferCharacteristics::Hlg, ..hdr10 };
+        assert_eq!(hlg.summary(), ColourSummary::Hlg);
+        let sdr = ColourDescription {
+            colour_primaries: ColourPrimaries::Bt709,
+            transfer_characteristics: TransferCharacteristics::Bt709,
+            matrix_coefficients: MatrixCoefficients::Bt709,
+        };
+        assert_eq!(sdr.summary(), ColourSummary::Sdr);
+        assert_eq!(ColourDescription { transfer_characteristics: 2.into(), ..sdr }.summary(), ColourSummary::Unknown);
+    }
+
+    #[test]
+    fn scaling_list() {
+        // delta_scale=-8 on the first value signals useDefaultScalingMatrixFlag
+        let list = ScalingList::read(&mut RbspBitReader::new(&[0x08, 0x80]), 16).unwrap();
+        assert!(list.use_default_scaling_matrix_flag);
+        // sixteen delta_scale=0 values
+        let list = ScalingList::read(&mut RbspBitReader::new(&[0xff, 0xff]), 16).unwrap();
+        assert!(!list.use_default_scaling_matrix_flag);
+        assert_eq!(list.values, vec![8; 16]);
+        // delta_scale=+1, then delta_scale=-9 ends the list, repeating the last value
+        let list = ScalingList::read(&mut RbspBitReader::new(&[0x41, 0x38]), 16).unwrap();
+        assert_eq!(list.values, vec![9; 16]);
+    }
+
+    #[test]
+    fn scaling_matrix_fall_back() {
+        let eights = ScalingList { values: vec![8; 16], use_default_scaling_matrix_flag: false };
+        let use_default = ScalingList { values: vec![], use_default_scaling_matrix_flag: true };
+        let m = SeqScalingMatrix {
+            scaling_list4x4: vec![Some(eights), None, None, None, Some(use_default), None],
+            scaling_list8x8: vec![None, None],
+        }.matrices();
+        // rule A: absent lists copy the previous list of the same kind, or use the default
+        assert_eq!(m.scaling_list_4x4[1], [8; 16]);
+        assert_eq!(m.scaling_list_4x4[2], [8; 16]);
+        assert_eq!(m.scaling_list_4x4[3], DEFAULT_4X4_INTER);
+        assert_eq!(m.scaling_list_4x4[4], DEFAULT_4X4_INTER);
+        assert_eq!(m.scaling_list_8x8[0], DEFAULT_8X8_INTRA);
+        assert_eq!(m.scaling_list_8x8[5], DEFAULT_8X8_INTER);
+        assert_eq!(m.weight_scale_4x4(3)[..4], [10, 14, 20, 24]);
+        assert_eq!(m.weight_scale_8x8(0)[..8], [6, 10, 13, 16, 18, 23, 25, 27]);
+    }
+
     #[test]
     fn crop_removes_all_pixels() {
         let sps = SeqParameterSet {
             profile_idc: ProfileIdc(0),
             constraint_flags: ConstraintFlags(0),
             level_idc: 0,
-            seq_parameter_set_id: ParamSetId::from_u32(0).unwrap(),
+            seq_parameter_set_id: SeqParamSetId::from_u32(0).unwrap(),
             chroma_info: ChromaInfo {
                 chroma_format: ChromaFormat::Monochrome,
                 separate_colour_plane_flag: false,
@@ -1012,4 +2046,64 @@ mod test {
         let dim = sps.pixel_dimensions();
         assert!(matches!(dim, Err(SpsError::CroppingError(_))));
     }
+
+    #[test]
+    fn interlaced_crop() {
+        // 1920x1080 coded as 1920x1088 in field pairs, where each unit of vertical cropping is
+        // four rows
+        let sps = SeqParameterSet {
+            profile_idc: ProfileIdc(100),
+            constraint_flags: ConstraintFlags(0),
+            level_idc: 40,
+            seq_parameter_set_id: SeqParamSetId::from_u32(0).unwrap(),
+            chroma_info: ChromaInfo {
+                chroma_format: ChromaFormat::YUV420,
+                separate_colour_plane_flag: false,
+                bit_depth_luma_minus8: 0,
+                bit_depth_chroma_minus8: 0,
+                qpprime_y_zero_transform_bypass_flag: false,
+                scaling_matrix: Default::default()
+            },
+            log2_max_frame_num_minus4: 0,
+            pic_order_cnt: PicOrderCntType::TypeTwo,
+            max_num_ref_frames: 0,
+            frame_cropping: Some(FrameCropping {
+                bottom_offset: 2,
+                left_offset: 0,
+                right_offset: 0,
+                top_offset: 0
+            }),
+            pic_width_in_mbs_minus1: 119,
+            pic_height_in_map_units_minus1: 33,
+            frame_mbs_flags: FrameMbsFlags::Fields { mb_adaptive_frame_field_flag: true },
+            gaps_in_frame_num_value_allowed_flag: false,
+            direct_8x8_inference_flag: true,
+            vui_parameters: None
+        };
+        let geometry = sps.display_geometry().unwrap();
+        assert_eq!((geometry.width_in_mbs, geometry.height_in_mbs), (120, 68));
+        assert_eq!((geometry.coded_width, geometry.coded_height), (1920, 1088));
+        assert_eq!(geometry.crop, CropRect { left: 0, top: 0, width: 1920, height: 1080 });
+        assert_eq!(geometry.sample_aspect_ratio, None);
+        assert_eq!(geometry.display_aspect_ratio, Rational::new(16, 9));
+        assert_eq!(sps.pixel_dimensions(), Ok((1920, 1080)));
+    }
+
+    #[test]
+    fn hrd_bit_rate_and_cpb_size() {
+        let mut sps = SeqParameterSet::from_bytes(&HRD_SPS).unwrap();
+        let hrd = sps.vui_parameters.as_ref().unwrap().vcl_hrd_parameters.as_ref().unwrap();
+        assert_eq!(hrd.bit_rate(0), Some(64_000));
+        assert_eq!(hrd.cpb_size(0), Some(16_000));
+        assert_eq!(hrd.bit_rate(1), None);
+        assert_eq!(sps.vcl_hrd_within_level_limits(), Some(true));
+        assert_eq!(sps.nal_hrd_within_level_limits(), None);
+
+        // the largest scale exceeds level 3's MaxBR of 10Mbit/s
+        let hrd = sps.vui_parameters.as_mut().unwrap().vcl_hrd_parameters.as_mut().unwrap();
+        hrd.bit_rate_scale = 15;
+        assert_eq!(hrd.bit_rate(0), Some(1000 << 21));
+        assert!(!hrd.within_limits(10_000_000, 10_000_000));
+        assert_eq!(sps.vcl_hrd_within_level_limits(), Some(false));
+    }
 }
@@ -0,0 +1,207 @@
+//! Parser for `seq_parameter_set_extension_rbsp()`, which describes the auxiliary coded pictures
+//! (e.g. alpha planes) that may accompany the primary coded pictures of a sequence.
+
+use crate::rbsp::RbspBitReader;
+use crate::rbsp::RbspBitReaderError;
+use crate::Context;
+use super::{NalFlow, NalHandler, NalHeader, UnitType};
+use crate::nal::pps::ParamSetIdError;
+use crate::nal::sps::SeqParamSetId;
+use std::marker;
+
+#[derive(Debug, PartialEq)]
+pub enum SpsExtensionError {
+    ReaderError(bitreader::BitReaderError),
+    RbspReaderError(RbspBitReaderError),
+    BadSeqParamSetId(ParamSetIdError),
+    /// `aux_format_idc` must be between 0 and 3
+    AuxFormatIdcOutOfRange(u32),
+    /// `bit_depth_aux_minus8` must be between 0 and 4
+    BitDepthAuxOutOfRange(u32),
+}
+impl From<bitreader::BitReaderError> for SpsExtensionError {
+    fn from(e: bitreader::BitReaderError) -> Self {
+        SpsExtensionError::ReaderError(e)
+    }
+}
+impl From<RbspBitReaderError> for SpsExtensionError {
+    fn from(e: RbspBitReaderError) -> Self {
+        SpsExtensionError::RbspReaderError(e)
+    }
+}
+
+/// How the samples of auxiliary coded pictures relate to those of the primary coded pictures
+/// (given by `aux_format_idc` values `1` to `3`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuxFormat {
+    /// For alpha blending, the decoded samples of the primary coded picture should be multiplied
+    /// by the interpretation sample values of the auxiliary coded picture (i.e. the primary
+    /// picture has not been premultiplied)
+    StraightAlpha,
+    /// For alpha blending, the decoded samples of the primary coded picture should not be
+    /// multiplied by the interpretation sample values of the auxiliary coded picture (i.e. the
+    /// primary picture has already been premultiplied)
+    PremultipliedAlpha,
+    /// The relationship between primary and auxiliary samples is unspecified
+    Unspecified,
+}
+
+/// The details of the auxiliary coded pictures present in each access unit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuxInfo {
+    pub aux_format: AuxFormat,
+    pub bit_depth_aux_minus8: u8,
+    pub alpha_incr_flag: bool,
+    pub alpha_opaque_value: u16,
+    pub alpha_transparent_value: u16,
+}
+impl AuxInfo {
+    fn read(r: &mut RbspBitReader<'_>, aux_format: AuxFormat) -> Result<AuxInfo, SpsExtensionError> {
+        let bit_depth_aux_minus8 = r.read_ue_named("bit_depth_aux_minus8")?;
+        if bit_depth_aux_minus8 > 4 {
+            return Err(SpsExtensionError::BitDepthAuxOutOfRange(bit_depth_aux_minus8));
+        }
+        let alpha_incr_flag = r.read_bool_named("alpha_incr_flag")?;
+        let bits = bit_depth_aux_minus8 + 9;
+        Ok(AuxInfo {
+            aux_format,
+            bit_depth_aux_minus8: bit_depth_aux_minus8 as u8,
+            alpha_incr_flag,
+            alpha_opaque_value: r.read_u16(bits as u8)?,
+            alpha_transparent_value: r.read_u16(bits as u8)?,
+        })
+    }
+
+    /// The bit depth of the samples of the auxiliary coded pictures
+    pub fn bit_depth_aux(&self) -> u8 {
+        self.bit_depth_aux_minus8 + 8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqParameterSetExtension {
+    /// The id of the SPS which this extension applies to
+    pub seq_parameter_set_id: SeqParamSetId,
+    /// `None` if `aux_format_idc` is `0`, meaning there are no auxiliary coded pictures
+    pub aux_info: Option<AuxInfo>,
+    pub additional_extension_flag: bool,
+}
+impl SeqParameterSetExtension {
+    /// Parses the SPS extension from the given RBSP data.  Any data following the syntax is
+    /// ignored; see `from_bytes_strict()`.
+    pub fn from_bytes(buf: &[u8]) -> Result<SeqParameterSetExtension, SpsExtensionError> {
+        Self::read(&mut RbspBitReader::new(buf))
+    }
+
+    /// Like `from_bytes()`, but additionally requires that the syntax is followed by
+    /// `rbsp_trailing_bits()` (unless `additional_extension_flag` is set, in which case any
+    /// following data is to be ignored).
+    pub fn from_bytes_strict(buf: &[u8]) -> Result<SeqParameterSetExtension, SpsExtensionError> {
+        let mut r = RbspBitReader::new(buf);
+        let ext = Self::read(&mut r)?;
+        if !ext.additional_extension_flag {
+            r.finish()?;
+        }
+        Ok(ext)
+    }
+
+    fn read(r: &mut RbspBitReader<'_>) -> Result<SeqParameterSetExtension, SpsExtensionError> {
+        let seq_parameter_set_id = SeqParamSetId::from_u32(r.read_ue_named("seq_parameter_set_id")?)
+            .map_err(SpsExtensionError::BadSeqParamSetId)?;
+        let aux_info = match r.read_ue_named("aux_format_idc")? {
+            0 => None,
+            1 => Some(AuxInfo::read(r, AuxFormat::StraightAlpha)?),
+            2 => Some(AuxInfo::read(r, AuxFormat::PremultipliedAlpha)?),
+            3 => Some(AuxInfo::read(r, AuxFormat::Unspecified)?),
+            other => return Err(SpsExtensionError::AuxFormatIdcOutOfRange(other)),
+        };
+        Ok(SeqParameterSetExtension {
+            seq_parameter_set_id,
+            aux_info,
+            additional_extension_flag: r.read_bool_named("additional_extension_flag")?,
+        })
+    }
+}
+
+/// A `NalHandler` which parses `SeqParameterSetExtension` NAL units and stores the result in the
+/// `Context`.
+pub struct SeqParameterSetExtensionNalHandler<Ctx> {
+    buf: Vec<u8>,
+    phantom: marker::PhantomData<Ctx>
+}
+impl<Ctx> Default for SeqParameterSetExtensionNalHandler<Ctx> {
+    fn default() -> Self {
+        SeqParameterSetExtensionNalHandler {
+            buf: Vec::new(),
+            phantom: marker::PhantomData,
+        }
+    }
+}
+impl<Ctx> NalHandler for SeqParameterSetExtensionNalHandler<Ctx> {
+    type Ctx = Ctx;
+
+    fn start(&mut self, _ctx: &mut Context<Ctx>, header: NalHeader) -> NalFlow {
+        if header.nal_unit_type() != UnitType::SeqParameterSetExtension {
+            eprintln!("SeqParameterSetExtensionNalHandler handler given NAL unit type {:?}", header.nal_unit_type());
+            return NalFlow::Skip;
+        }
+        NalFlow::Continue
+    }
+
+    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) -> NalFlow {
+        self.buf.extend_from_slice(buf);
+        NalFlow::Continue
+    }
+
+    fn end(&mut self, ctx: &mut Context<Ctx>) -> NalFlow {
+        let ext = SeqParameterSetExtension::from_bytes(&self.buf[..]);
+        self.buf.clear();
+        match ext {
+            Ok(ext) => ctx.put_seq_param_set_ext(ext),
+            Err(e) => eprintln!("seq_parameter_set_extension: {:?}", e),
+        }
+        NalFlow::Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn alpha() {
+        // seq_parameter_set_id=1, aux_format_idc=2, bit_depth_aux_minus8=0, alpha_incr_flag=1,
+        // alpha_opaque_value=255 (9 bits), alpha_transparent_value=0 (9 bits),
+        // additional_extension_flag=0, rbsp_trailing_bits
+        let data = [0x4f, 0x7f, 0x80, 0x10];
+        let ext = SeqParameterSetExtension::from_bytes(&data[..]).unwrap();
+        assert_eq!(ext.seq_parameter_set_id.id(), 1);
+        assert_eq!(ext.aux_info, Some(AuxInfo {
+            aux_format: AuxFormat::PremultipliedAlpha,
+            bit_depth_aux_minus8: 0,
+            alpha_incr_flag: true,
+            alpha_opaque_value: 255,
+            alpha_transparent_value: 0,
+        }));
+        assert!(!ext.additional_extension_flag);
+
+        let mut ctx = Context::default();
+        let mut h = SeqParameterSetExtensionNalHandler::default();
+        h.start(&mut ctx, NalHeader::new(0x6d).unwrap());
+        h.push(&mut ctx, &data[..]);
+        h.end(&mut ctx);
+        assert_eq!(ctx.sps_ext_by_id(SeqParamSetId::from_u32(1).unwrap()), Some(&ext));
+        assert_eq!(ctx.sps_ext_by_id(SeqParamSetId::from_u32(0).unwrap()), None);
+    }
+
+    #[test]
+    fn no_aux() {
+        // seq_parameter_set_id=0, aux_format_idc=0, additional_extension_flag=0
+        let ext = SeqParameterSetExtension::from_bytes(&[0xd0]).unwrap();
+        assert_eq!(ext.aux_info, None);
+        assert_eq!(
+            SeqParameterSetExtension::from_bytes(&[0x94]),
+            Err(SpsExtensionError::AuxFormatIdcOutOfRange(4))
+        );
+    }
+}
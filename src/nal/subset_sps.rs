@@ -0,0 +1,802 @@
+//! Parser for `subset_seq_parameter_set_rbsp()`, which extends the syntax of an ordinary SPS with
+//! the additional parameters needed by the scalable (Annex G), multiview (Annex H) and
+//! multiview-plus-depth (Annex I) profiles.
+//!
+//! Subset SPSs are referred to by NAL units of the non-base layers / views, and have their own
+//! id-space distinct from that of ordinary SPSs, so they are stored in a separate table in the
+//! `Context`.
+
+use crate::rbsp::RbspBitReader;
+use crate::rbsp::RbspBitReaderError;
+use crate::Context;
+use super::{NalFlow, NalHandler, NalHeader, UnitType};
+use super::sps::{SeqParameterSet, SpsError, TimingInfo, HrdParameters, FrameMbsFlags};
+use std::marker;
+
+#[derive(Debug)]
+pub enum SubsetSpsError {
+    ReaderError(bitreader::BitReaderError),
+    RbspReaderError(RbspBitReaderError),
+    /// An error in the `seq_parameter_set_data()` part of the subset SPS
+    Sps(SpsError),
+    /// The `bit_equal_to_one` field preceding an MVC or MVCD extension was zero
+    BitEqualToOneMissing,
+    FieldValueTooLarge { name: &'static str, value: u32 },
+    /// The given `profile_idc` (`139`, for 3D-AVC) uses extension syntax that is not supported
+    UnsupportedProfile(u8),
+}
+impl From<bitreader::BitReaderError> for SubsetSpsError {
+    fn from(e: bitreader::BitReaderError) -> Self {
+        SubsetSpsError::ReaderError(e)
+    }
+}
+impl From<RbspBitReaderError> for SubsetSpsError {
+    fn from(e: RbspBitReaderError) -> Self {
+        SubsetSpsError::RbspReaderError(e)
+    }
+}
+impl From<SpsError> for SubsetSpsError {
+    fn from(e: SpsError) -> Self {
+        SubsetSpsError::Sps(e)
+    }
+}
+
+fn read_ue_max(r: &mut RbspBitReader<'_>, name: &'static str, max: u32) -> Result<u32, SubsetSpsError> {
+    let value = r.read_ue_named(name)?;
+    if value > max {
+        return Err(SubsetSpsError::FieldValueTooLarge { name, value });
+    }
+    Ok(value)
+}
+
+fn read_bit_equal_to_one(r: &mut RbspBitReader<'_>) -> Result<(), SubsetSpsError> {
+    if r.read_bool_named("bit_equal_to_one")? {
+        Ok(())
+    } else {
+        Err(SubsetSpsError::BitEqualToOneMissing)
+    }
+}
+
+/// The scaled reference layer offsets given in the SVC extension when
+/// `extended_spatial_scalability_idc` is `1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqRefLayer {
+    pub chroma_phase_x_plus1_flag: bool,
+    pub chroma_phase_y_plus1: u8,
+    pub scaled_ref_layer_left_offset: i32,
+    pub scaled_ref_layer_top_offset: i32,
+    pub scaled_ref_layer_right_offset: i32,
+    pub scaled_ref_layer_bottom_offset: i32,
+}
+
+/// `seq_parameter_set_svc_extension()`, from Annex G.
+///
+/// Where the chroma phase fields are absent from the bitstream, they take their inferred values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SvcSpsExtension {
+    pub inter_layer_deblocking_filter_control_present_flag: bool,
+    pub extended_spatial_scalability_idc: u8,
+    pub chroma_phase_x_plus1_flag: bool,
+    pub chroma_phase_y_plus1: u8,
+    /// Present only when `extended_spatial_scalability_idc` is `1`
+    pub seq_ref_layer: Option<SeqRefLayer>,
+    pub seq_tcoeff_level_prediction_flag: bool,
+    pub adaptive_tcoeff_level_prediction_flag: bool,
+    pub slice_header_restriction_flag: bool,
+}
+impl SvcSpsExtension {
+    fn read(r: &mut RbspBitReader<'_>, chroma_array_type: u8) -> Result<SvcSpsExtension, SubsetSpsError> {
+        let inter_layer_deblocking_filter_control_present_flag = r.read_bool_named("inter_layer_deblocking_filter_control_present_flag")?;
+        let extended_spatial_scalability_idc = r.read_u8(2)?;
+        let chroma_phase_x_plus1_flag = if chroma_array_type == 1 || chroma_array_type == 2 {
+            r.read_bool_named("chroma_phase_x_plus1_flag")?
+        } else {
+            true
+        };
+        let chroma_phase_y_plus1 = if chroma_array_type == 1 {
+            r.read_u8(2)?
+        } else {
+            1
+        };
+        let seq_ref_layer = if extended_spatial_scalability_idc == 1 {
+            let (x, y) = if chroma_array_type > 0 {
+                (r.read_bool_named("seq_ref_layer_chroma_phase_x_plus1_flag")?, r.read_u8(2)?)
+            } else {
+                (chroma_phase_x_plus1_flag, chroma_phase_y_plus1)
+            };
+            Some(SeqRefLayer {
+                chroma_phase_x_plus1_flag: x,
+                chroma_phase_y_plus1: y,
+                scaled_ref_layer_left_offset: r.read_se_named("seq_scaled_ref_layer_left_offset")?,
+                scaled_ref_layer_top_offset: r.read_se_named("seq_scaled_ref_layer_top_offset")?,
+                scaled_ref_layer_right_offset: r.read_se_named("seq_scaled_ref_layer_right_offset")?,
+                scaled_ref_layer_bottom_offset: r.read_se_named("seq_scaled_ref_layer_bottom_offset")?,
+            })
+        } else {
+            None
+        };
+        let seq_tcoeff_level_prediction_flag = r.read_bool_named("seq_tcoeff_level_prediction_flag")?;
+        let adaptive_tcoeff_level_prediction_flag = if seq_tcoeff_level_prediction_flag {
+            r.read_bool_named("adaptive_tcoeff_level_prediction_flag")?
+        } else {
+            false
+        };
+        Ok(SvcSpsExtension {
+            inter_layer_deblocking_filter_control_present_flag,
+            extended_spatial_scalability_idc,
+            chroma_phase_x_plus1_flag,
+            chroma_phase_y_plus1,
+            seq_ref_layer,
+            seq_tcoeff_level_prediction_flag,
+            adaptive_tcoeff_level_prediction_flag,
+            slice_header_restriction_flag: r.read_bool_named("slice_header_restriction_flag")?,
+        })
+    }
+}
+
+/// The timing and HRD parameters that the SVC, MVC and MVCD VUI extensions give for each
+/// operation point, in the same form as in the base SPS `VuiParameters`.
+#[derive(Debug, Clone)]
+pub struct OperationPointVui {
+    pub timing_info: Option<TimingInfo>,
+    pub nal_hrd_parameters: Option<HrdParameters>,
+    pub vcl_hrd_parameters: Option<HrdParameters>,
+    /// Present only if either of the HRD parameter sets is present
+    pub low_delay_hrd_flag: Option<bool>,
+    pub pic_struct_present_flag: bool,
+}
+impl OperationPointVui {
+    fn read(r: &mut RbspBitReader<'_>) -> Result<OperationPointVui, SubsetSpsError> {
+        let mut hrd_parameters_present = false;
+        let timing_info = TimingInfo::read(r)?;
+        let nal_hrd_parameters = HrdParameters::read(r, &mut hrd_parameters_present)?;
+        let vcl_hrd_parameters = HrdParameters::read(r, &mut hrd_parameters_present)?;
+        let low_delay_hrd_flag = if hrd_parameters_present {
+            Some(r.read_bool_named("low_delay_hrd_flag")?)
+        } else {
+            None
+        };
+        Ok(OperationPointVui {
+            timing_info,
+            nal_hrd_parameters,
+            vcl_hrd_parameters,
+            low_delay_hrd_flag,
+            pic_struct_present_flag: r.read_bool_named("pic_struct_present_flag")?,
+        })
+    }
+}
+
+/// An entry of `svc_vui_parameters_extension()`, applying to the layer representation identified
+/// by the given ids.
+#[derive(Debug, Clone)]
+pub struct SvcVuiEntry {
+    pub dependency_id: u8,
+    pub quality_id: u8,
+    pub temporal_id: u8,
+    pub vui: OperationPointVui,
+}
+
+fn read_svc_vui_parameters_extension(r: &mut RbspBitReader<'_>) -> Result<Vec<SvcVuiEntry>, SubsetSpsError> {
+    let num_entries = read_ue_max(r, "vui_ext_num_entries_minus1", 1023)? + 1;
+    let mut entries = Vec::with_capacity(num_entries as usize);
+    for _ in 0..num_entries {
+        entries.push(SvcVuiEntry {
+            dependency_id: r.read_u8(3)?,
+            quality_id: r.read_u8(4)?,
+            temporal_id: r.read_u8(3)?,
+            vui: OperationPointVui::read(r)?,
+        });
+    }
+    Ok(entries)
+}
+
+/// The reference views for one view, used for either anchor or non-anchor pictures.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ViewRefs {
+    /// View ids of the inter-view references in reference picture list 0
+    pub l0: Vec<u32>,
+    /// View ids of the inter-view references in reference picture list 1
+    pub l1: Vec<u32>,
+}
+impl ViewRefs {
+    fn read(r: &mut RbspBitReader<'_>, anchor: bool) -> Result<ViewRefs, SubsetSpsError> {
+        let (num_l0, l0, num_l1, l1) = if anchor {
+            ("num_anchor_refs_l0", "anchor_ref_l0", "num_anchor_refs_l1", "anchor_ref_l1")
+        } else {
+            ("num_non_anchor_refs_l0", "non_anchor_ref_l0", "num_non_anchor_refs_l1", "non_anchor_ref_l1")
+        };
+        Ok(ViewRefs {
+            l0: Self::read_list(r, num_l0, l0)?,
+            l1: Self::read_list(r, num_l1, l1)?,
+        })
+    }
+
+    fn read_list(r: &mut RbspBitReader<'_>, num_name: &'static str, name: &'static str) -> Result<Vec<u32>, SubsetSpsError> {
+        let num = read_ue_max(r, num_name, 15)?;
+        let mut refs = Vec::with_capacity(num as usize);
+        for _ in 0..num {
+            refs.push(r.read_ue_named(name)?);
+        }
+        Ok(refs)
+    }
+}
+
+/// An operation point to which a signalled level applies, in the MVC extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MvcApplicableOp {
+    pub temporal_id: u8,
+    pub target_view_ids: Vec<u32>,
+    pub num_views_minus1: u32,
+}
+
+/// A level signalled in the MVC extension, together with the operation points that conform to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MvcLevelValue {
+    pub level_idc: u8,
+    pub applicable_ops: Vec<MvcApplicableOp>,
+}
+
+/// Frame-compatible packing parameters, present in the MVC extension for the MFC High profile
+/// (`profile_idc` `134`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MfcFormat {
+    pub mfc_format_idc: u8,
+    /// `(view0_grid_position_x, view0_grid_position_y, view1_grid_position_x,
+    /// view1_grid_position_y)`, present only if `mfc_format_idc` is `0` or `1` and
+    /// `default_grid_position_flag` is unset
+    pub grid_positions: Option<(u8, u8, u8, u8)>,
+    pub rpu_filter_enabled_flag: bool,
+    /// Present only if `frame_mbs_only_flag` is unset
+    pub rpu_field_processing_flag: Option<bool>,
+}
+
+/// `seq_parameter_set_mvc_extension()`, from Annex H.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MvcSpsExtension {
+    /// The `view_id` of each view, indexed by view order index
+    pub view_ids: Vec<u32>,
+    /// Inter-view references for anchor pictures, indexed by view order index (the entry for the
+    /// base view, at index `0`, is always empty)
+    pub anchor_refs: Vec<ViewRefs>,
+    /// Inter-view references for non-anchor pictures, indexed as for `anchor_refs`
+    pub non_anchor_refs: Vec<ViewRefs>,
+    pub level_values: Vec<MvcLevelValue>,
+    pub mfc_format: Option<MfcFormat>,
+}
+impl MvcSpsExtension {
+    fn read(r: &mut RbspBitReader<'_>, sps: &SeqParameterSet) -> Result<MvcSpsExtension, SubsetSpsError> {
+        let num_views = read_ue_max(r, "num_views_minus1", 1023)? + 1;
+        let mut view_ids = Vec::with_capacity(num_views as usize);
+        for _ in 0..num_views {
+            view_ids.push(r.read_ue_named("view_id")?);
+        }
+        let anchor_refs = read_view_refs(r, &vec![true; num_views as usize], true)?;
+        let non_anchor_refs = read_view_refs(r, &vec![true; num_views as usize], false)?;
+        let num_level_values = read_ue_max(r, "num_level_values_signalled_minus1", 63)? + 1;
+        let mut level_values = Vec::with_capacity(num_level_values as usize);
+        for _ in 0..num_level_values {
+            let level_idc = r.read_u8(8)?;
+            let num_ops = read_ue_max(r, "num_applicable_ops_minus1", 1023)? + 1;
+            let mut applicable_ops = Vec::with_capacity(num_ops as usize);
+            for _ in 0..num_ops {
+                let temporal_id = r.read_u8(3)?;
+                let num_target_views = read_ue_max(r, "applicable_op_num_target_views_minus1", 1023)? + 1;
+                let mut target_view_ids = Vec::with_capacity(num_target_views as usize);
+                for _ in 0..num_target_views {
+                    target_view_ids.push(r.read_ue_named("applicable_op_target_view_id")?);
+                }
+                applicable_ops.push(MvcApplicableOp {
+                    temporal_id,
+                    target_view_ids,
+                    num_views_minus1: read_ue_max(r, "applicable_op_num_views_minus1", 1023)?,
+                });
+            }
+            level_values.push(MvcLevelValue { level_idc, applicable_ops });
+        }
+        let mfc_format = if u8::from(sps.profile_idc) == 134 {
+            let mfc_format_idc = r.read_u8(6)?;
+            let grid_positions = if (mfc_format_idc == 0 || mfc_format_idc == 1) && !r.read_bool_named("default_grid_position_flag")? {
+                Some((r.read_u8(4)?, r.read_u8(4)?, r.read_u8(4)?, r.read_u8(4)?))
+            } else {
+                None
+            };
+            let rpu_filter_enabled_flag = r.read_bool_named("rpu_filter_enabled_flag")?;
+            let rpu_field_processing_flag = match sps.frame_mbs_flags {
+                FrameMbsFlags::Frames => None,
+                FrameMbsFlags::Fields { .. } => Some(r.read_bool_named("rpu_field_processing_flag")?),
+            };
+            Some(MfcFormat {
+                mfc_format_idc,
+                grid_positions,
+                rpu_filter_enabled_flag,
+                rpu_field_processing_flag,
+            })
+        } else {
+            None
+        };
+        Ok(MvcSpsExtension {
+            view_ids,
+            anchor_refs,
+            non_anchor_refs,
+            level_values,
+            mfc_format,
+        })
+    }
+}
+
+/// Reads the anchor or non-anchor reference lists for each non-base view for which `present` is
+/// set, leaving the entries of other views empty.
+fn read_view_refs(r: &mut RbspBitReader<'_>, present: &[bool], anchor: bool) -> Result<Vec<ViewRefs>, SubsetSpsError> {
+    let mut refs = Vec::with_capacity(present.len());
+    refs.push(ViewRefs::default());
+    for &p in &present[1..] {
+        refs.push(if p { ViewRefs::read(r, anchor)? } else { ViewRefs::default() });
+    }
+    Ok(refs)
+}
+
+/// An entry of `mvc_vui_parameters_extension()`, applying to the operation point with the given
+/// temporal id and target output views.
+#[derive(Debug, Clone)]
+pub struct MvcVuiOp {
+    pub temporal_id: u8,
+    pub target_output_view_ids: Vec<u32>,
+    pub vui: OperationPointVui,
+}
+
+fn read_mvc_vui_parameters_extension(r: &mut RbspBitReader<'_>) -> Result<Vec<MvcVuiOp>, SubsetSpsError> {
+    let num_ops = read_ue_max(r, "vui_mvc_num_ops_minus1", 1023)? + 1;
+    let mut ops = Vec::with_capacity(num_ops as usize);
+    for _ in 0..num_ops {
+        let temporal_id = r.read_u8(3)?;
+        let num_views = read_ue_max(r, "vui_mvc_num_target_output_views_minus1", 1023)? + 1;
+        let mut target_output_view_ids = Vec::with_capacity(num_views as usize);
+        for _ in 0..num_views {
+            target_output_view_ids.push(r.read_ue_named("vui_mvc_view_id")?);
+        }
+        ops.push(MvcVuiOp {
+            temporal_id,
+            target_output_view_ids,
+            vui: OperationPointVui::read(r)?,
+        });
+    }
+    Ok(ops)
+}
+
+/// A view of the MVCD extension, which may have texture and / or depth components.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MvcdView {
+    pub view_id: u32,
+    pub depth_view_present_flag: bool,
+    pub texture_view_present_flag: bool,
+}
+
+/// A target view of an MVCD operation point, indicating which of its components are included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MvcdTargetView {
+    pub view_id: u32,
+    pub depth_flag: bool,
+    pub texture_flag: bool,
+}
+impl MvcdTargetView {
+    fn read(r: &mut RbspBitReader<'_>, view_id_name: &'static str) -> Result<MvcdTargetView, SubsetSpsError> {
+        Ok(MvcdTargetView {
+            view_id: r.read_ue_named(view_id_name)?,
+            depth_flag: r.read_bool_named("depth_flag")?,
+            texture_flag: r.read_bool_named("texture_flag")?,
+        })
+    }
+}
+
+/// An operation point to which a signalled level applies, in the MVCD extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MvcdApplicableOp {
+    pub temporal_id: u8,
+    pub target_views: Vec<MvcdTargetView>,
+    pub num_texture_views_minus1: u32,
+    pub num_depth_views: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MvcdLevelValue {
+    pub level_idc: u8,
+    pub applicable_ops: Vec<MvcdApplicableOp>,
+}
+
+/// An entry of `mvcd_vui_parameters_extension()`.
+#[derive(Debug, Clone)]
+pub struct MvcdVuiOp {
+    pub temporal_id: u8,
+    pub target_output_views: Vec<MvcdTargetView>,
+    pub vui: OperationPointVui,
+}
+
+/// `seq_parameter_set_mvcd_extension()`, from Annex I, including the VUI extensions that it
+/// contains.
+#[derive(Debug, Clone)]
+pub struct MvcdSpsExtension {
+    /// The views, indexed by view order index
+    pub views: Vec<MvcdView>,
+    /// Inter-view references for anchor pictures, indexed by view order index (entries for the
+    /// base view and for views without a depth component are empty)
+    pub anchor_refs: Vec<ViewRefs>,
+    /// Inter-view references for non-anchor pictures, indexed as for `anchor_refs`
+    pub non_anchor_refs: Vec<ViewRefs>,
+    pub level_values: Vec<MvcdLevelValue>,
+    pub mvcd_vui: Option<Vec<MvcdVuiOp>>,
+    /// VUI for the texture views, present if `texture_vui_parameters_present_flag` is set
+    pub texture_vui: Option<Vec<MvcVuiOp>>,
+}
+impl MvcdSpsExtension {
+    fn read(r: &mut RbspBitReader<'_>) -> Result<MvcdSpsExtension, SubsetSpsError> {
+        let num_views = read_ue_max(r, "num_views_minus1", 1023)? + 1;
+        let mut views = Vec::with_capacity(num_views as usize);
+        for _ in 0..num_views {
+            views.push(MvcdView {
+                view_id: r.read_ue_named("view_id")?,
+                depth_view_present_flag: r.read_bool_named("depth_view_present_flag")?,
+                texture_view_present_flag: r.read_bool_named("texture_view_present_flag")?,
+            });
+        }
+        let depth: Vec<bool> = views.iter().map(|v| v.depth_view_present_flag).collect();
+        let anchor_refs = read_view_refs(r, &depth, true)?;
+        let non_anchor_refs = read_view_refs(r, &depth, false)?;
+        let num_level_values = read_ue_max(r, "num_level_values_signalled_minus1", 63)? + 1;
+        let mut level_values = Vec::with_capacity(num_level_values as usize);
+        for _ in 0..num_level_values {
+            let level_idc = r.read_u8(8)?;
+            let num_ops = read_ue_max(r, "num_applicable_ops_minus1", 1023)? + 1;
+            let mut applicable_ops = Vec::with_capacity(num_ops as usize);
+            for _ in 0..num_ops {
+                let temporal_id = r.read_u8(3)?;
+                let num_target_views = read_ue_max(r, "applicable_op_num_target_views_minus1", 1023)? + 1;
+                let mut target_views = Vec::with_capacity(num_target_views as usize);
+                for _ in 0..num_target_views {
+                    target_views.push(MvcdTargetView::read(r, "applicable_op_target_view_id")?);
+                }
+                applicable_ops.push(MvcdApplicableOp {
+                    temporal_id,
+                    target_views,
+                    num_texture_views_minus1: read_ue_max(r, "applicable_op_num_texture_views_minus1", 1023)?,
+                    num_depth_views: read_ue_max(r, "applicable_op_num_depth_views", 1024)?,
+                });
+            }
+            level_values.push(MvcdLevelValue { level_idc, applicable_ops });
+        }
+        let mvcd_vui = if r.read_bool_named("mvcd_vui_parameters_present_flag")? {
+            Some(Self::read_mvcd_vui_parameters_extension(r)?)
+        } else {
+            None
+        };
+        let texture_vui = if r.read_bool_named("texture_vui_parameters_present_flag")? {
+            Some(read_mvc_vui_parameters_extension(r)?)
+        } else {
+            None
+        };
+        Ok(MvcdSpsExtension {
+            views,
+            anchor_refs,
+            non_anchor_refs,
+            level_values,
+            mvcd_vui,
+            texture_vui,
+        })
+    }
+
+    fn read_mvcd_vui_parameters_extension(r: &mut RbspBitReader<'_>) -> Result<Vec<MvcdVuiOp>, SubsetSpsError> {
+        let num_ops = read_ue_max(r, "vui_mvcd_num_ops_minus1", 1023)? + 1;
+        let mut ops = Vec::with_capacity(num_ops as usize);
+        for _ in 0..num_ops {
+            let temporal_id = r.read_u8(3)?;
+            let num_views = read_ue_max(r, "vui_mvcd_num_target_output_views_minus1", 1023)? + 1;
+            let mut target_output_views = Vec::with_capacity(num_views as usize);
+            for _ in 0..num_views {
+                target_output_views.push(MvcdTargetView::read(r, "vui_mvcd_view_id")?);
+            }
+            ops.push(MvcdVuiOp {
+                temporal_id,
+                target_output_views,
+                vui: OperationPointVui::read(r)?,
+            });
+        }
+        Ok(ops)
+    }
+}
+
+/// The profile-specific part of a subset SPS.
+#[derive(Debug, Clone)]
+pub enum SubsetSpsExtension {
+    /// For the scalable profiles (`profile_idc` `83` and `86`)
+    Svc {
+        svc: SvcSpsExtension,
+        /// Present if `svc_vui_parameters_present_flag` is set
+        vui: Option<Vec<SvcVuiEntry>>,
+    },
+    /// For the multiview profiles (`profile_idc` `118`, `128` and `134`)
+    Mvc {
+        mvc: MvcSpsExtension,
+        /// Present if `mvc_vui_parameters_present_flag` is set
+        vui: Option<Vec<MvcVuiOp>>,
+    },
+    /// For the multiview depth profiles (`profile_idc` `135` and `138`)
+    Mvcd(MvcdSpsExtension),
+    /// Any other `profile_idc` has no extension syntax
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct SubsetSeqParameterSet {
+    /// The base `seq_parameter_set_data()`, whose `seq_parameter_set_id` identifies this subset SPS
+    pub sps: SeqParameterSet,
+    pub extension: SubsetSpsExtension,
+    pub additional_extension2_flag: bool,
+}
+impl SubsetSeqParameterSet {
+    /// Parses the subset SPS from the given RBSP data.  Any data following the syntax is ignored;
+    /// see `from_bytes_strict()`.
+    pub fn from_bytes(buf: &[u8]) -> Result<SubsetSeqParameterSet, SubsetSpsError> {
+        Self::read(&mut RbspBitReader::new(buf))
+    }
+
+    /// Like `from_bytes()`, but additionally requires that the syntax is followed by
+    /// `rbsp_trailing_bits()` (unless `additional_extension2_flag` is set, in which case any
+    /// following `additional_extension2_data_flag` values are ignored).
+    pub fn from_bytes_strict(buf: &[u8]) -> Result<SubsetSeqParameterSet, SubsetSpsError> {
+        let mut r = RbspBitReader::new(buf);
+        let subset_sps = Self::read(&mut r)?;
+        if !subset_sps.additional_extension2_flag {
+            r.finish()?;
+        }
+        Ok(subset_sps)
+    }
+
+    fn read(r: &mut RbspBitReader<'_>) -> Result<SubsetSeqParameterSet, SubsetSpsError> {
+        let sps = SeqParameterSet::read(r)?;
+        let extension = match u8::from(sps.profile_idc) {
+            83 | 86 => {
+                let svc = SvcSpsExtension::read(r, sps.chroma_array_type())?;
+                let vui = if r.read_bool_named("svc_vui_parameters_present_flag")? {
+                    Some(read_svc_vui_parameters_extension(r)?)
+                } else {
+                    None
+                };
+                SubsetSpsExtension::Svc { svc, vui }
+            },
+            118 | 128 | 134 => {
+                read_bit_equal_to_one(r)?;
+                let mvc = MvcSpsExtension::read(r, &sps)?;
+                let vui = if r.read_bool_named("mvc_vui_parameters_present_flag")? {
+                    Some(read_mvc_vui_parameters_extension(r)?)
+                } else {
+                    None
+                };
+                SubsetSpsExtension::Mvc { mvc, vui }
+            },
+            135 | 138 => {
+                read_bit_equal_to_one(r)?;
+                SubsetSpsExtension::Mvcd(MvcdSpsExtension::read(r)?)
+            },
+            139 => return Err(SubsetSpsError::UnsupportedProfile(139)),
+            _ => SubsetSpsExtension::None,
+        };
+        Ok(SubsetSeqParameterSet {
+            sps,
+            extension,
+            additional_extension2_flag: r.read_bool_named("additional_extension2_flag")?,
+        })
+    }
+}
+
+/// A `NalHandler` which parses `SubsetSeqParameterSet` NAL units and stores the result in the
+/// `Context`.
+pub struct SubsetSeqParameterSetNalHandler<Ctx> {
+    buf: Vec<u8>,
+    phantom: marker::PhantomData<Ctx>
+}
+impl<Ctx> Default for SubsetSeqParameterSetNalHandler<Ctx> {
+    fn default() -> Self {
+        SubsetSeqParameterSetNalHandler {
+            buf: Vec::new(),
+            phantom: marker::PhantomData,
+        }
+    }
+}
+impl<Ctx> NalHandler for SubsetSeqParameterSetNalHandler<Ctx> {
+    type Ctx = Ctx;
+
+    fn start(&mut self, _ctx: &mut Context<Ctx>, header: NalHeader) -> NalFlow {
+        if header.nal_unit_type() != UnitType::SubsetSeqParameterSet {
+            eprintln!("SubsetSeqParameterSetNalHandler given NAL unit type {:?}", header.nal_unit_type());
+            return NalFlow::Skip;
+        }
+        NalFlow::Continue
+    }
+
+    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) -> NalFlow {
+        self.buf.extend_from_slice(buf);
+        NalFlow::Continue
+    }
+
+    fn end(&mut self, ctx: &mut Context<Ctx>) -> NalFlow {
+        let subset_sps = SubsetSeqParameterSet::from_bytes(&self.buf[..]);
+        self.buf.clear();
+        match subset_sps {
+            Ok(subset_sps) => ctx.put_subset_seq_param_set(subset_sps),
+            Err(e) => eprintln!("subset_seq_parameter_set: {:?}", e),
+        }
+        NalFlow::Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nal::sps::SeqParamSetId;
+
+    /// Builds RBSP test data field-by-field.
+    #[derive(Default)]
+    struct Bits(Vec<bool>);
+    impl Bits {
+        fn u(mut self, n: u32, v: u32) -> Self {
+            for i in (0..n).rev() {
+                self.0.push(v & (1 << i) != 0);
+            }
+            self
+        }
+        fn ue(self, v: u32) -> Self {
+            let len = 32 - (v + 1).leading_zeros();
+            self.u(len - 1, 0).u(len, v + 1)
+        }
+        fn se(self, v: i32) -> Self {
+            self.ue(if v > 0 { v as u32 * 2 - 1 } else { (-v) as u32 * 2 })
+        }
+        fn base_sps(self, profile_idc: u32, id: u32) -> Self {
+            let b = self.u(8, profile_idc).u(8, 0).u(8, 30).ue(id);
+            // chroma_format_idc=1, bit depths 8, no transform bypass or scaling matrices
+            let b = b.ue(1).ue(0).ue(0).u(1, 0).u(1, 0);
+            // log2_max_frame_num_minus4, pic_order_cnt_type=2, max_num_ref_frames, gaps flag
+            let b = b.ue(0).ue(2).ue(1).u(1, 0);
+            // 176x144, frame_mbs_only_flag, direct_8x8_inference_flag, no cropping or VUI
+            b.ue(10).ue(8).u(1, 1).u(1, 1).u(1, 0).u(1, 0)
+        }
+        fn finish(self) -> Vec<u8> {
+            let mut bits = self.u(1, 1).0;
+            let padding = (8 - bits.len() % 8) % 8;
+            bits.extend(std::iter::repeat(false).take(padding));
+            bits.chunks(8).map(|c| c.iter().fold(0, |acc, &b| acc << 1 | b as u8)).collect()
+        }
+    }
+
+    #[test]
+    fn svc() {
+        let data = Bits::default()
+            .base_sps(83, 1)
+            // inter_layer_deblocking_filter_control_present_flag, extended_spatial_scalability_idc
+            .u(1, 1).u(2, 1)
+            // chroma_phase_x_plus1_flag, chroma_phase_y_plus1
+            .u(1, 0).u(2, 2)
+            // seq_ref_layer chroma phases and offsets
+            .u(1, 1).u(2, 0).se(-2).se(3).se(0).se(4)
+            // seq_tcoeff_level_prediction_flag, adaptive_tcoeff_level_prediction_flag,
+            // slice_header_restriction_flag
+            .u(1, 1).u(1, 1).u(1, 0)
+            // svc_vui_parameters_present_flag, one entry with timing info only
+            .u(1, 1).ue(0).u(3, 1).u(4, 2).u(3, 3)
+            .u(1, 1).u(32, 1001).u(32, 60000).u(1, 1)
+            .u(1, 0).u(1, 0).u(1, 1)
+            // additional_extension2_flag
+            .u(1, 0)
+            .finish();
+        let subset_sps = SubsetSeqParameterSet::from_bytes(&data[..]).unwrap();
+        assert_eq!(subset_sps.sps.seq_parameter_set_id.id(), 1);
+        match subset_sps.extension {
+            SubsetSpsExtension::Svc { ref svc, ref vui } => {
+                assert_eq!(*svc, SvcSpsExtension {
+                    inter_layer_deblocking_filter_control_present_flag: true,
+                    extended_spatial_scalability_idc: 1,
+                    chroma_phase_x_plus1_flag: false,
+                    chroma_phase_y_plus1: 2,
+                    seq_ref_layer: Some(SeqRefLayer {
+                        chroma_phase_x_plus1_flag: true,
+                        chroma_phase_y_plus1: 0,
+                        scaled_ref_layer_left_offset: -2,
+                        scaled_ref_layer_top_offset: 3,
+                        scaled_ref_layer_right_offset: 0,
+                        scaled_ref_layer_bottom_offset: 4,
+                    }),
+                    seq_tcoeff_level_prediction_flag: true,
+                    adaptive_tcoeff_level_prediction_flag: true,
+                    slice_header_restriction_flag: false,
+                });
+                let vui = vui.as_ref().unwrap();
+                assert_eq!(vui.len(), 1);
+                assert_eq!((vui[0].dependency_id, vui[0].quality_id, vui[0].temporal_id), (1, 2, 3));
+                assert!(vui[0].vui.timing_info.is_some());
+                assert_eq!(vui[0].vui.low_delay_hrd_flag, None);
+                assert!(vui[0].vui.pic_struct_present_flag);
+            },
+            ref other => panic!("unexpected extension {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mvc() {
+        let data = Bits::default()
+            .base_sps(128, 2)
+            // bit_equal_to_one, num_views_minus1, view_id
+            .u(1, 1).ue(1).ue(0).ue(5)
+            // anchor refs for view 1: l0=[0], l1=[]
+            .ue(1).ue(0).ue(0)
+            // non-anchor refs for view 1: l0=[0], l1=[]
+            .ue(1).ue(0).ue(0)
+            // one level value, with one operation point
+            .ue(0).u(8, 40).ue(0).u(3, 0).ue(1).ue(0).ue(5).ue(1)
+            // mvc_vui_parameters_present_flag, additional_extension2_flag
+            .u(1, 0).u(1, 0)
+            .finish();
+        let subset_sps = SubsetSeqParameterSet::from_bytes(&data[..]).unwrap();
+        match subset_sps.extension {
+            SubsetSpsExtension::Mvc { ref mvc, ref vui } => {
+                assert_eq!(mvc.view_ids, vec![0, 5]);
+                assert_eq!(mvc.anchor_refs, vec![ViewRefs::default(), ViewRefs { l0: vec![0], l1: vec![] }]);
+                assert_eq!(mvc.non_anchor_refs, mvc.anchor_refs);
+                assert_eq!(mvc.level_values, vec![MvcLevelValue {
+                    level_idc: 40,
+                    applicable_ops: vec![MvcApplicableOp {
+                        temporal_id: 0,
+                        target_view_ids: vec![0, 5],
+                        num_views_minus1: 1,
+                    }],
+                }]);
+                assert_eq!(mvc.mfc_format, None);
+                assert!(vui.is_none());
+            },
+            ref other => panic!("unexpected extension {:?}", other),
+        }
+
+        let mut ctx = Context::default();
+        let mut h = SubsetSeqParameterSetNalHandler::default();
+        h.start(&mut ctx, NalHeader::new(0x6f).unwrap());
+        h.push(&mut ctx, &data[..]);
+        h.end(&mut ctx);
+        assert!(ctx.subset_sps_by_id(SeqParamSetId::from_u32(2).unwrap()).is_some());
+        // kept separately from ordinary SPSs
+        assert!(ctx.sps_by_id(SeqParamSetId::from_u32(2).unwrap()).is_none());
+    }
+
+    #[test]
+    fn mvcd() {
+        let data = Bits::default()
+            .base_sps(138, 0)
+            // bit_equal_to_one, two views: texture-only base view, then texture+depth
+            .u(1, 1).ue(1).ue(0).u(1, 0).u(1, 1).ue(1).u(1, 1).u(1, 1)
+            // anchor and non-anchor refs for view 1
+            .ue(0).ue(0).ue(1).ue(0).ue(0)
+            // one level value, with one operation point of one target view
+            .ue(0).u(8, 41).ue(0).u(3, 0).ue(0).ue(1).u(1, 1).u(1, 0).ue(0).ue(1)
+            // mvcd_vui_parameters_present_flag, texture_vui_parameters_present_flag,
+            // additional_extension2_flag
+            .u(1, 0).u(1, 0).u(1, 0)
+            .finish();
+        let subset_sps = SubsetSeqParameterSet::from_bytes(&data[..]).unwrap();
+        match subset_sps.extension {
+            SubsetSpsExtension::Mvcd(ref mvcd) => {
+                assert_eq!(mvcd.views[1], MvcdView { view_id: 1, depth_view_present_flag: true, texture_view_present_flag: true });
+                assert_eq!(mvcd.anchor_refs[1], ViewRefs::default());
+                assert_eq!(mvcd.non_anchor_refs[1], ViewRefs { l0: vec![0], l1: vec![] });
+                let op = &mvcd.level_values[0].applicable_ops[0];
+                assert_eq!(op.target_views, vec![MvcdTargetView { view_id: 1, depth_flag: true, texture_flag: false }]);
+                assert_eq!(op.num_depth_views, 1);
+            },
+            ref other => panic!("unexpected extension {:?}", other),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let data = Bits::default().base_sps(118, 0).u(1, 0).finish();
+        assert!(matches!(SubsetSeqParameterSet::from_bytes(&data[..]), Err(SubsetSpsError::BitEqualToOneMissing)));
+        let data = Bits::default().base_sps(139, 0).finish();
+        assert!(matches!(SubsetSeqParameterSet::from_bytes(&data[..]), Err(SubsetSpsError::UnsupportedProfile(139))));
+    }
+}
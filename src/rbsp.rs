@@ -23,7 +23,7 @@
 
 use std::ops::{Deref, DerefMut};
 use bitreader;
-use crate::nal::{NalHandler, NalHeader};
+use crate::nal::{NalFlow, NalHandler, NalHeader};
 use crate::Context;
 
 #[derive(Debug)]
@@ -31,6 +31,9 @@ enum ParseState {
     Start,
     OneZero,
     TwoZero,
+    /// The previous byte was an emulation prevention byte, so the current byte must be in the
+    /// range `0x00` to `0x03`
+    EmulationPrevented,
 }
 impl ParseState {
     fn in_rbsp(&self) -> bool {
@@ -38,6 +41,7 @@ impl ParseState {
             ParseState::Start => true,
             ParseState::OneZero => false,
             ParseState::TwoZero => false,
+            ParseState::EmulationPrevented => true,
         }
     }
 
@@ -46,15 +50,33 @@ impl ParseState {
             ParseState::Start => 0,
             ParseState::OneZero => 1,
             ParseState::TwoZero => 2,
+            ParseState::EmulationPrevented => 0,
         }
     }
 }
+
+/// A violation of the NAL unit syntax, detected by an `RbspDecoder` created with
+/// [`RbspDecoder::new_strict()`](struct.RbspDecoder.html#method.new_strict).
+#[derive(Debug, Clone, PartialEq)]
+pub enum RbspConformanceError {
+    /// The byte following an `emulation_prevention_three_byte` was not one of `0x00`, `0x01`,
+    /// `0x02` or `0x03`.  The `offset` is the position of the offending byte, counted from the
+    /// start of the NAL unit data given to the decoder.
+    InvalidByteAfterEmulationPrevention { offset: u64, byte: u8 },
+}
+
 pub struct RbspDecoder<R>
     where
         R: NalHandler
 {
     state: ParseState,
     nal_reader: R,
+    strict: bool,
+    offset: u64,
+    conformance_errors: Vec<RbspConformanceError>,
+    /// true once the wrapped handler has returned something other than `NalFlow::Continue` for
+    /// the current NAL unit, after which no more of its data is passed on
+    skipping: bool,
 }
 impl<R> RbspDecoder<R>
     where
@@ -64,21 +86,56 @@ impl<R> RbspDecoder<R>
         RbspDecoder {
             state: ParseState::Start,
             nal_reader,
+            strict: false,
+            offset: 0,
+            conformance_errors: Vec::new(),
+            skipping: false,
+        }
+    }
+
+    /// Creates a decoder which, in addition to removing emulation prevention bytes, checks that
+    /// the input conforms to the rules for their use.  Any violations found are recorded, and can
+    /// be retrieved with `conformance_errors()`; the data is still passed on to the wrapped
+    /// handler as it would be by a decoder created with `new()`.
+    pub fn new_strict(nal_reader: R) -> Self {
+        RbspDecoder {
+            strict: true,
+            ..Self::new(nal_reader)
         }
     }
 
+    /// Conformance errors found so far (always empty unless this decoder was created with
+    /// `new_strict()`).
+    pub fn conformance_errors(&self) -> &[RbspConformanceError] {
+        &self.conformance_errors[..]
+    }
+
+    /// Removes and returns the conformance errors found so far.
+    pub fn take_conformance_errors(&mut self) -> Vec<RbspConformanceError> {
+        std::mem::take(&mut self.conformance_errors)
+    }
+
     fn to(&mut self, new_state: ParseState) {
         self.state = new_state;
     }
 
-    fn emit(&mut self, ctx: &mut Context<R::Ctx>, buf:&[u8], start_index: Option<usize>, end_index: usize) {
+    fn emit(&mut self, ctx: &mut Context<R::Ctx>, buf:&[u8], start_index: Option<usize>, end_index: usize) -> NalFlow {
         if let Some(start) = start_index {
             self.nal_reader.push(ctx, &buf[start..end_index])
         } else {
             eprintln!("RbspDecoder: no start_index");
+            NalFlow::Continue
         }
     }
 
+    /// Notes that the wrapped handler doesn't want the rest of the current NAL unit, of which
+    /// `len` bytes were passed to the current `push()` call
+    fn stop(&mut self, flow: NalFlow, len: usize) -> NalFlow {
+        self.skipping = true;
+        self.offset += len as u64;
+        flow
+    }
+
     pub fn into_handler(self) -> R {
         self.nal_reader
     }
@@ -89,12 +146,19 @@ impl<R> NalHandler for RbspDecoder<R>
 {
     type Ctx = R::Ctx;
 
-    fn start(&mut self, ctx: &mut Context<Self::Ctx>, header: NalHeader) {
+    fn start(&mut self, ctx: &mut Context<Self::Ctx>, header: NalHeader) -> NalFlow {
         self.state = ParseState::Start;
-        self.nal_reader.start(ctx, header);
+        self.offset = 0;
+        let flow = self.nal_reader.start(ctx, header);
+        self.skipping = flow != NalFlow::Continue;
+        flow
     }
 
-    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
+        if self.skipping {
+            self.offset += buf.len() as u64;
+            return NalFlow::Skip;
+        }
         let mut rbsp_start: Option<usize> = if self.state.in_rbsp() {
             Some(0)
         } else {
@@ -110,13 +174,28 @@ impl<R> NalHandler for RbspDecoder<R>
                         _ => self.to(ParseState::Start),
                     }
                 },
+                ParseState::EmulationPrevented => {
+                    if self.strict && b > 0x03 {
+                        self.conformance_errors.push(RbspConformanceError::InvalidByteAfterEmulationPrevention {
+                            offset: self.offset + i as u64,
+                            byte: b,
+                        });
+                    }
+                    match b {
+                        0x00 => self.to(ParseState::OneZero),
+                        _ => self.to(ParseState::Start),
+                    }
+                },
                 ParseState::OneZero => {
                     match b {
                         0x00 => self.to(ParseState::TwoZero),
                         _ => {
                             if rbsp_start.is_none() {
                                 let fake = [0x00];
-                                self.emit(ctx, &fake[..], Some(0), 1);
+                                let flow = self.emit(ctx, &fake[..], Some(0), 1);
+                                if flow != NalFlow::Continue {
+                                    return self.stop(flow, buf.len());
+                                }
                                 rbsp_start = Some(i);
                             }
                             self.to(ParseState::Start)
@@ -127,17 +206,19 @@ impl<R> NalHandler for RbspDecoder<R>
                     match b {
                         0x03 => {
                             // found an 'emulation prevention' byte; skip it,
-                            if rbsp_start.is_none() {
+                            let flow = if rbsp_start.is_none() {
                                 let fake = [0x00, 0x00];
-                                self.emit(ctx, &fake[..], Some(0), 2);
+                                self.emit(ctx, &fake[..], Some(0), 2)
                             } else {
-                                self.emit(ctx, buf, rbsp_start, i);
+                                self.emit(ctx, buf, rbsp_start, i)
+                            };
+                            if flow != NalFlow::Continue {
+                                return self.stop(flow, buf.len());
                             }
                             rbsp_start = Some(i + 1);
-                            // TODO: per spec, the next byte should be either 0x00, 0x1, 0x02 or
-                            // 0x03, but at the moment we assume this without checking for
-                            // correctness
-                            self.to(ParseState::Start);
+                            // per spec, the next byte should be either 0x00, 0x1, 0x02 or 0x03,
+                            // which is checked if we are in 'strict' mode
+                            self.to(ParseState::EmulationPrevented);
                         },
                         // I see example PES packet payloads that end with 0x80 0x00 0x00 0x00,
                         // which triggered this error; guess the example is correct and this code
@@ -146,7 +227,10 @@ impl<R> NalHandler for RbspDecoder<R>
                         _ => {
                             if rbsp_start.is_none() {
                                 let fake = [0x00, 0x00];
-                                self.emit(ctx, &fake[..], Some(0), 2);
+                                let flow = self.emit(ctx, &fake[..], Some(0), 2);
+                                if flow != NalFlow::Continue {
+                                    return self.stop(flow, buf.len());
+                                }
                                 rbsp_start = Some(i);
                             }
                             self.to(ParseState::Start)
@@ -155,12 +239,16 @@ impl<R> NalHandler for RbspDecoder<R>
                 },
             }
         }
+        self.offset += buf.len() as u64;
         if let Some(start) = rbsp_start {
             let end = buf.len() - self.state.end_backtrack_bytes();
             if start != end {
-                self.nal_reader.push(ctx, &buf[start..end])
+                let flow = self.nal_reader.push(ctx, &buf[start..end]);
+                self.skipping = flow != NalFlow::Continue;
+                return flow;
             }
         }
+        NalFlow::Continue
     }
 
     /// To be invoked when calling code knows that the end of a sequence of NAL Unit data has been
@@ -169,20 +257,63 @@ impl<R> NalHandler for RbspDecoder<R>
     /// For example, if the containing data structure demarcates the end of a sequence of NAL
     /// Units explicitly, the parser for that structure should call `end_units()` once all data
     /// has been passed to the `push()` function.
-    fn end(&mut self, ctx: &mut Context<Self::Ctx>) {
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) -> NalFlow {
         let backtrack = self.state.end_backtrack_bytes();
-        if backtrack > 0 {
+        let skipping = self.skipping;
+        self.to(ParseState::Start);
+        self.offset = 0;
+        self.skipping = false;
+        if backtrack > 0 && !skipping {
             // if we were in the middle of parsing a sequence of 0x00 bytes that might have become
             // a start-code, but actually reached the end of input, then we will now need to emit
             // those 0x00 bytes that we had been holding back,
             let tmp = [0u8; 3];
-            self.nal_reader.push(ctx, &tmp[0..backtrack]);
+            if self.nal_reader.push(ctx, &tmp[0..backtrack]) == NalFlow::Abort {
+                return NalFlow::Abort;
+            }
         }
-        self.to(ParseState::Start);
-        self.nal_reader.end(ctx);
+        self.nal_reader.end(ctx)
+    }
+}
+
+/// Removes _emulation prevention_ bytes from the given buffer, which holds a complete NAL unit
+/// (or the remainder of one, following the header), returning the decoded data.
+///
+/// This is a convenience for callers that already have the whole NAL unit in memory; the
+/// `RbspDecoder` type handles data that arrives piecemeal without requiring a copy.
+pub fn decode_nal(nal_unit: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(nal_unit.len());
+    let mut zero_count = 0;
+    for &b in nal_unit {
+        if zero_count >= 2 && b == 0x03 {
+            zero_count = 0;
+            continue;
+        }
+        zero_count = if b == 0x00 { zero_count + 1 } else { 0 };
+        result.push(b);
     }
+    result
 }
 
+/// Adds _emulation prevention_ bytes to the given RBSP data (or complete NAL unit, in RBSP form),
+/// so that it may be written into a bitstream; the inverse of `decode_nal()`.
+pub fn encode_nal(rbsp: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(rbsp.len() + rbsp.len() / 64);
+    let mut zero_count = 0;
+    for &b in rbsp {
+        if zero_count >= 2 && b <= 0x03 {
+            result.push(0x03);
+            zero_count = 0;
+        }
+        zero_count = if b == 0x00 { zero_count + 1 } else { 0 };
+        result.push(b);
+    }
+    if zero_count >= 2 {
+        // the NAL unit must not end with a zero byte (as with cabac_zero_word)
+        result.push(0x03);
+    }
+    result
+}
 
 impl From<bitreader::BitReaderError> for RbspBitReaderError {
     fn from(e: bitreader::BitReaderError) -> Self {
@@ -194,16 +325,30 @@ impl From<bitreader::BitReaderError> for RbspBitReaderError {
 pub enum RbspBitReaderError {
     ReaderError(bitreader::BitReaderError),
     ReaderErrorFor(&'static str, bitreader::BitReaderError),
+    /// The syntax was followed by the given number of bits of further data before the
+    /// `rbsp_trailing_bits()`
+    UnconsumedData { bits: u64 },
+    /// The `rbsp_stop_one_bit` of `rbsp_trailing_bits()` was not found at the end of the syntax,
+    /// which was at the given bit position
+    MissingTrailingBits { position: u64 },
+    /// The `rbsp_trailing_bits()` were followed by data other than whole `cabac_zero_word`s
+    /// (`0x0000`); `offset` is the position in the RBSP of the first byte which is not part of
+    /// one
+    TrailingData { offset: u64 },
 }
 
 pub struct RbspBitReader<'a> {
-    total_size: usize,
+    /// The bit position of the `rbsp_stop_one_bit` (the last bit set in the buffer), or `None`
+    /// if no bit is set
+    stop_bit: Option<u64>,
+    buf: &'a[u8],
     reader: bitreader::BitReader<'a>,
 }
 impl<'a> RbspBitReader<'a> {
     pub fn new(buf: &'a[u8]) -> RbspBitReader<'a> {
         RbspBitReader {
-            total_size: buf.len() * 8,
+            stop_bit: Self::find_stop_bit(buf),
+            buf,
             reader: bitreader::BitReader::new(buf),
         }
     }
@@ -233,8 +378,56 @@ impl<'a> RbspBitReader<'a> {
         Ok(Self::golomb_to_signed(self.read_ue()?))
     }
 
+    /// Implements the `more_rbsp_data()` function of the spec, returning `true` if there is
+    /// syntax data remaining before the `rbsp_trailing_bits()` (any zero bytes after these, such
+    /// as `cabac_zero_word`s, are ignored).
     pub fn has_more_rbsp_data(&self) -> bool {
-        self.position() < self.total_size as u64
+        match self.stop_bit {
+            Some(stop_bit) => self.position() < stop_bit,
+            None => false,
+        }
+    }
+
+    /// Checks that the reader is positioned at the `rbsp_trailing_bits()` which end the RBSP
+    /// (optionally followed by any number of zero bytes), i.e. that all the syntax data has been
+    /// consumed.
+    pub fn finish(self) -> Result<(), RbspBitReaderError> {
+        let position = self.position();
+        match self.stop_bit {
+            Some(stop_bit) if stop_bit == position => Ok(()),
+            Some(stop_bit) if stop_bit > position => Err(RbspBitReaderError::UnconsumedData { bits: stop_bit - position }),
+            _ => Err(RbspBitReaderError::MissingTrailingBits { position }),
+        }
+    }
+
+    /// Like `finish()`, but additionally requires that anything following the
+    /// `rbsp_trailing_bits()` is a whole number of `cabac_zero_word`s, reporting
+    /// `RbspBitReaderError::TrailingData` otherwise.
+    ///
+    /// The buffer must hold the RBSP of the NAL unit alone; an `AnnexBReader` passes on the
+    /// `trailing_zero_8bits` (and `zero_byte`) of the byte stream as part of the preceding NAL
+    /// unit, and these must be removed first.
+    pub fn finish_strict(self) -> Result<(), RbspBitReaderError> {
+        let position = self.position();
+        let index = (position / 8) as usize;
+        let stop_bit = 0x80 >> (position % 8);
+        match self.buf.get(index) {
+            Some(&b) if b & (stop_bit | (stop_bit - 1)) == stop_bit => {
+                let trailing = &self.buf[index + 1..];
+                match trailing.iter().position(|&b| b != 0) {
+                    Some(i) => Err(RbspBitReaderError::TrailingData { offset: (index + 1 + i) as u64 }),
+                    None if trailing.len() & 1 != 0 => Err(RbspBitReaderError::TrailingData { offset: self.buf.len() as u64 - 1 }),
+                    None => Ok(()),
+                }
+            },
+            _ => self.finish(),
+        }
+    }
+
+    fn find_stop_bit(buf: &[u8]) -> Option<u64> {
+        buf.iter()
+            .rposition(|&b| b != 0)
+            .map(|i| i as u64 * 8 + 7 - u64::from(buf[i].trailing_zeros()))
     }
 
     fn golomb_to_signed(val: u32) -> i32 {
@@ -294,19 +487,78 @@ mod tests {
     impl NalHandler for MockReader {
         type Ctx = ();
 
-        fn start(&mut self, _ctx: &mut Context<Self::Ctx>, _header: NalHeader) {
+        fn start(&mut self, _ctx: &mut Context<Self::Ctx>, _header: NalHeader) -> NalFlow {
             self.state.borrow_mut().started = true;
+            NalFlow::Continue
         }
 
-        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
             self.state.borrow_mut().data.extend_from_slice(buf);
+            NalFlow::Continue
         }
 
-        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
+        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
             self.state.borrow_mut().ended = true;
+            NalFlow::Continue
         }
     }
 
+    #[test]
+    fn encode() {
+        let rbsp = [0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x04, 0x00, 0x00];
+        let nal = encode_nal(&rbsp[..]);
+        assert_eq!(nal, vec![0x00, 0x00, 0x03, 0x00, 0x01, 0x00, 0x00, 0x04, 0x00, 0x00, 0x03]);
+        assert_eq!(decode_nal(&nal[..nal.len() - 1]), rbsp.to_vec());
+    }
+
+    #[test]
+    fn more_rbsp_data() {
+        let mut r = RbspBitReader::new(&[0b1010_0000, 0x00]);
+        assert!(r.has_more_rbsp_data());
+        r.read_bool().unwrap();
+        assert!(r.has_more_rbsp_data());
+        r.read_bool().unwrap();
+        assert!(!r.has_more_rbsp_data());
+        let mut r = RbspBitReader::new(&[0b0000_0001, 0b1000_0000]);
+        r.read_u8(8).unwrap();
+        assert!(!r.has_more_rbsp_data());
+        assert!(!RbspBitReader::new(&[0x00]).has_more_rbsp_data());
+    }
+
+    #[test]
+    fn finish() {
+        let mut r = RbspBitReader::new(&[0b0110_0000, 0x00, 0x00]);
+        r.read_u8(2).unwrap();
+        assert_eq!(r.finish(), Ok(()));
+        let mut r = RbspBitReader::new(&[0b0110_0000]);
+        r.read_u8(1).unwrap();
+        assert_eq!(r.finish(), Err(RbspBitReaderError::UnconsumedData { bits: 1 }));
+        let mut r = RbspBitReader::new(&[0b0110_0000]);
+        r.read_u8(3).unwrap();
+        assert_eq!(r.finish(), Err(RbspBitReaderError::MissingTrailingBits { position: 3 }));
+    }
+
+    #[test]
+    fn finish_strict() {
+        let mut r = RbspBitReader::new(&[0b0110_0000, 0x00, 0x00, 0x00, 0x00]);
+        r.read_u8(2).unwrap();
+        assert_eq!(r.finish_strict(), Ok(()));
+        // an odd number of zero bytes can't form cabac_zero_words
+        let mut r = RbspBitReader::new(&[0b0110_0000, 0x00, 0x00, 0x00]);
+        r.read_u8(2).unwrap();
+        assert_eq!(r.finish_strict(), Err(RbspBitReaderError::TrailingData { offset: 3 }));
+        // a stray byte after the trailing bits, which finish() takes to be unconsumed syntax
+        let mut r = RbspBitReader::new(&[0b0110_0000, 0x00, 0x00, 0x05]);
+        r.read_u8(2).unwrap();
+        assert_eq!(r.finish_strict(), Err(RbspBitReaderError::TrailingData { offset: 3 }));
+        let mut r = RbspBitReader::new(&[0b0110_0000, 0x00, 0x00, 0x05]);
+        r.read_u8(2).unwrap();
+        assert_eq!(r.finish(), Err(RbspBitReaderError::UnconsumedData { bits: 29 }));
+        let mut r = RbspBitReader::new(&[0b0110_0000]);
+        r.read_u8(1).unwrap();
+        assert_eq!(r.finish_strict(), Err(RbspBitReaderError::UnconsumedData { bits: 1 }));
+    }
+
     #[test]
     fn it_works() {
         let data = hex!(
@@ -331,4 +583,86 @@ mod tests {
             assert_eq!(&s.data[..], &expected[..], "on split_at({})", i);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn strict() {
+        let data = hex!(
+           "67 64 00 00 03 00 11 00 00 03 04 00 00 03 01 00 00 03");
+        for i in 1..data.len()-1 {
+            let state = Rc::new(RefCell::new(State {
+                started: false,
+                ended: false,
+                data: Vec::new(),
+            }));
+            let mock = MockReader::new(Rc::clone(&state));
+            let mut r = RbspDecoder::new_strict(mock);
+            let mut ctx = Context::default();
+            let (head, tail) = data.split_at(i);
+            r.push(&mut ctx, head);
+            r.push(&mut ctx, tail);
+            r.end(&mut ctx);
+            let expected = hex!(
+               "67 64 00 00 00 11 00 00 04 00 00 01 00 00");
+            let s = state.borrow();
+            assert_eq!(&s.data[..], &expected[..], "on split_at({})", i);
+            assert_eq!(
+                r.take_conformance_errors(),
+                vec![RbspConformanceError::InvalidByteAfterEmulationPrevention { offset: 10, byte: 0x04 }],
+                "on split_at({})", i
+            );
+            assert!(r.conformance_errors().is_empty());
+        }
+    }
+
+    #[test]
+    fn decode_whole_nal() {
+        let data = hex!(
+           "67 64 00 00 03 00 11 00 00 03 03 00 00 00 03");
+        assert_eq!(&decode_nal(&data[..])[..], &hex!("67 64 00 00 00 11 00 00 03 00 00 00")[..]);
+    }
+
+    /// takes only the first `push()` of each NAL unit
+    #[derive(Default)]
+    struct SkippingReader {
+        pushed: usize,
+        ended: usize,
+    }
+    impl NalHandler for SkippingReader {
+        type Ctx = ();
+
+        fn start(&mut self, _ctx: &mut Context<Self::Ctx>, _header: NalHeader) -> NalFlow {
+            NalFlow::Continue
+        }
+
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalFlow {
+            self.pushed += buf.len();
+            NalFlow::Skip
+        }
+
+        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) -> NalFlow {
+            self.ended += 1;
+            NalFlow::Continue
+        }
+    }
+
+    #[test]
+    fn skip() {
+        let mut ctx = Context::default();
+        let mut decoder = RbspDecoder::new(SkippingReader::default());
+        decoder.start(&mut ctx, NalHeader::new(0x67).unwrap());
+        // the trailing zeros are held back, pending the next push()
+        assert_eq!(decoder.push(&mut ctx, &[0x01, 0x02, 0x00, 0x00]), NalFlow::Skip);
+        assert_eq!(decoder.push(&mut ctx, &[0x03, 0x04, 0x00, 0x00, 0x03, 0x05]), NalFlow::Skip);
+        assert_eq!(decoder.push(&mut ctx, &[0x06, 0x00, 0x00]), NalFlow::Skip);
+        decoder.end(&mut ctx);
+        // a new NAL unit is passed on again
+        decoder.start(&mut ctx, NalHeader::new(0x68).unwrap());
+        assert_eq!(decoder.push(&mut ctx, &[0x07, 0x00, 0x00, 0x03, 0x01]), NalFlow::Skip);
+        decoder.end(&mut ctx);
+        let handler = decoder.into_handler();
+        // "01 02" from the first NAL unit, and "07 00 00" (preceding the emulation prevention
+        // byte) from the second
+        assert_eq!(handler.pushed, 2 + 3);
+        assert_eq!(handler.ended, 2);
+    }
+}